@@ -0,0 +1,234 @@
+/*
+    Copyright 2021 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::{web, Error, HttpMessage, HttpRequest};
+use futures::future::{ok, Ready};
+use futures::Future;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::config::{ApiKey, Config};
+use crate::tls::ClientCert;
+use crate::utils::audit_log::AuditEntry;
+
+/// Data of the request gathered while it is processed and shared with the audit middleware.
+#[derive(Default)]
+struct AuditData {
+    key: Option<String>,
+    params: Map<String, Value>,
+}
+
+#[derive(Clone, Default)]
+struct AuditContext(Rc<RefCell<AuditData>>);
+
+/// Identity of the client of a request, kept with the results of the operations that outlive it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Requester {
+    pub peer_ip: Option<IpAddr>,
+    pub key: Option<String>,
+    pub client_cert: Option<String>,
+}
+
+impl Requester {
+    pub fn of(req: &HttpRequest) -> Self {
+        Requester {
+            peer_ip: req.peer_addr().map(|addr| addr.ip().to_canonical()),
+            key: req.extensions().get::<ApiKey>().map(|k| k.name.clone()),
+            client_cert: req
+                .conn_data::<ClientCert>()
+                .and_then(|cert| cert.cn.clone()),
+        }
+    }
+}
+
+/// Add a parameter to the audit log entry of the request.
+pub fn audit_param<R: HttpMessage, T: Serialize>(req: &R, name: &str, value: T) {
+    if let Some(ctx) = req.extensions().get::<AuditContext>() {
+        if let Ok(value) = serde_json::to_value(value) {
+            ctx.0.borrow_mut().params.insert(String::from(name), value);
+        }
+    }
+}
+
+/// Set the name of the API key used in the request.
+pub fn audit_key<R: HttpMessage>(req: &R, name: &str) {
+    if let Some(ctx) = req.extensions().get::<AuditContext>() {
+        ctx.0.borrow_mut().key = Some(String::from(name));
+    }
+}
+
+// Only the requests that can modify the system are audited.
+fn is_mutating(method: &Method) -> bool {
+    method == Method::POST || method == Method::DELETE
+}
+
+// Audit middleware. It must be the most external one for recording also the requests rejected
+// by the authorization middleware.
+pub struct Audit;
+
+impl<S, B> Transform<S, ServiceRequest> for Audit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditMiddleware { service })
+    }
+}
+
+pub struct AuditMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for AuditMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let cfg = match req.app_data::<web::Data<Arc<Config>>>() {
+            Some(cfg) if cfg.enable_audit_log && is_mutating(req.method()) => {
+                Arc::clone(cfg.get_ref())
+            }
+            _ => return Box::pin(self.service.call(req)),
+        };
+
+        // The request is not available if it is rejected by the authorization middleware, then gather
+        // its data now and share a context with the authorization middleware and the handlers.
+        let ctx = AuditContext::default();
+        req.extensions_mut().insert(ctx.clone());
+        let entry = AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            peer_ip: req.peer_addr().map(|addr| addr.ip().to_canonical()),
+            key: None,
+            client_cert: req
+                .conn_data::<ClientCert>()
+                .and_then(|cert| cert.cn.clone()),
+            method: req.method().to_string(),
+            route: req.path().to_string(),
+            params: Map::new(),
+            status: 0,
+            error: None,
+        };
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+
+            let (status, error) = match &res {
+                Ok(res) => (res.status(), res.response().error().map(|e| e.to_string())),
+                Err(e) => (e.as_response_error().status_code(), Some(e.to_string())),
+            };
+            let data = ctx.0.take();
+            record(
+                &cfg,
+                AuditEntry {
+                    key: data.key,
+                    params: data.params,
+                    status: status.as_u16(),
+                    error,
+                    ..entry
+                },
+            );
+
+            res
+        })
+    }
+}
+
+/// Record an operation started by the agent itself, like the rollback of a policy that has not been
+/// confirmed. Its method is `AGENT` and its route the name of the operation.
+pub fn audit_event(
+    cfg: &Config,
+    operation: &str,
+    params: Map<String, Value>,
+    error: Option<String>,
+) {
+    if !cfg.enable_audit_log {
+        return;
+    }
+
+    record(
+        cfg,
+        AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            peer_ip: None,
+            key: None,
+            client_cert: None,
+            method: String::from("AGENT"),
+            route: String::from(operation),
+            params,
+            status: match error {
+                Some(_) => 500,
+                None => 200,
+            },
+            error,
+        },
+    );
+}
+
+fn record(cfg: &Config, entry: AuditEntry) {
+    debug!("Locking audit log mutex (thread id: {})", thread_id::get());
+    let audit_log = cfg.audit_log.lock().unwrap();
+    if let Err(e) = audit_log.append(&entry, cfg.audit_log_max_size, cfg.audit_log_max_files) {
+        error!("Error writing the audit log: {e}");
+    }
+    debug!(
+        "Releasing audit log mutex (thread id: {})",
+        thread_id::get()
+    );
+}
@@ -20,17 +20,38 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use std::net::IpAddr;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use actix_service::{Service, Transform};
-use actix_web::{dev::ServiceRequest, dev::ServiceResponse, web, Error};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::web::{self, BytesMut};
+use actix_web::{Error, HttpMessage, HttpRequest};
 use futures::future::{ok, Ready};
-use futures::Future;
+use futures::{Future, StreamExt};
+use log::{debug, info, warn};
 
-use crate::config::Config;
+use crate::audit::audit_key;
+use crate::config::{ApiKey, Config};
 use crate::errors::FwcError;
+use crate::tls::{ClientCert, UnixPeer};
+use crate::utils::auth_failures::BanPolicy;
+use crate::utils::myregex::ALPHA_NUM_2;
+use crate::utils::net::ip_in_list;
+use crate::utils::signature::SignedRequest;
+
+// Headers of the HMAC-SHA256 signed requests.
+const KEY_ID_HEADER: &str = "X-FWC-Key-Id";
+const TIMESTAMP_HEADER: &str = "X-FWC-Timestamp";
+const NONCE_HEADER: &str = "X-FWC-Nonce";
+const SIGNATURE_HEADER: &str = "X-FWC-Signature";
+
+// The body of signed requests is kept in memory for computing its hash.
+const MAX_SIGNED_BODY_SIZE: usize = 104_857_600; // One hundred megabytes.
 
 // There are two steps in middleware processing.
 // 1. Middleware initialization, middleware factory gets called with
@@ -43,7 +64,7 @@ pub struct Authorize;
 // `B` - type of response's body
 impl<S, B> Transform<S, ServiceRequest> for Authorize
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -55,12 +76,14 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(AuthorizeMiddleware { service })
+        ok(AuthorizeMiddleware {
+            service: Rc::new(service),
+        })
     }
 }
 
 pub struct AuthorizeMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 macro_rules! err {
@@ -71,7 +94,7 @@ macro_rules! err {
 
 impl<S, B> Service<ServiceRequest> for AuthorizeMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -85,8 +108,6 @@ where
     }
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        let api_key: String;
-
         let cfg: &web::Data<Arc<Config>> = match req.app_data() {
             Some(val) => val,
             None => {
@@ -95,46 +116,58 @@ where
                 ))
             }
         };
+        let cfg = Arc::clone(cfg.get_ref());
 
-        // If the use of API Key is enabled.
-        if cfg.enable_api_key {
-            // (1) Verify that the supplied API key is correct.
-            match req.headers().get("X-API-Key") {
-                Some(value) => api_key = String::from(value.to_str().unwrap()),
-                None => return err!(FwcError::ApiKeyNotFound),
+        // Connections to the local Unix socket are authorized by the file system permissions of the socket.
+        if let Some(peer) = req.conn_data::<UnixPeer>() {
+            let key = ApiKey {
+                name: match peer.uid {
+                    Some(uid) => format!("unix-socket (uid {uid})"),
+                    None => String::from("unix-socket"),
+                },
+                key: String::new(),
+                scopes: vec![String::from("*")],
+            };
+            if let Err(e) = authorize_key(&req, key) {
+                return err!(e);
             }
 
-            if cfg.api_key != api_key {
-                return err!(FwcError::ApiKeyNotValid);
+            return Box::pin(self.service.call(req));
+        }
+
+        // Refuse any request from banned IPs before doing any other check.
+        let peer_ip = req.peer_addr().map(|addr| addr.ip().to_canonical());
+        if let Some(ip) = peer_ip {
+            if cfg.auth_failures.lock().unwrap().is_banned(ip, now()) {
+                return err!(FwcError::TooManyAuthFailures);
             }
+        }
 
-            // (2) Now check that the peer IP is allowed.
-            // If allowed_ips vector is empty we are allowing connections form any IP.
-            if !cfg.allowed_ips.is_empty() {
-                let mut found = false;
-
-                let remote_ip = match req.connection_info().peer_addr() {
-                    Some(data) => {
-                        let ip_and_port: Vec<&str> = data.split(':').collect();
-                        String::from(ip_and_port[0])
-                    }
-                    None => {
-                        return err!(FwcError::Internal(
-                            "Allowed IPs list not empty and was not possible to get the remote IP"
-                        ))
-                    }
+        // API key, client certificate and allowed IPs are independent authorization policies, each one of them
+        // is enforced when it is enabled.
+
+        // Signed requests need the request body for the signature verification.
+        if cfg.live().enable_api_key && req.headers().contains_key(SIGNATURE_HEADER) {
+            let service = Rc::clone(&self.service);
+
+            return Box::pin(async move {
+                let mut req = req;
+                let res = match check_peer(&req, &cfg) {
+                    Ok(()) => match verify_signed_request(&mut req, &cfg).await {
+                        Ok(key) => authorize_key(&req, key),
+                        Err(e) => Err(e),
+                    },
+                    Err(e) => Err(e),
                 };
+                register_auth_result(&cfg, peer_ip, res)?;
 
-                for ip in cfg.allowed_ips.iter() {
-                    if *ip == remote_ip {
-                        found = true;
-                        break;
-                    }
-                }
-                if !found {
-                    return err!(FwcError::NotAllowedIP);
-                }
-            }
+                service.call(req).await
+            });
+        }
+
+        let res = check_peer(&req, &cfg).and_then(|_| check_api_key(&req, &cfg));
+        if let Err(e) = register_auth_result(&cfg, peer_ip, res) {
+            return err!(e);
         }
 
         let fut = self.service.call(req);
@@ -145,3 +178,341 @@ where
         })
     }
 }
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Check the peer IP and the client certificate.
+fn check_peer(req: &ServiceRequest, cfg: &Config) -> crate::errors::Result<()> {
+    // (1) Check that the peer IP is allowed.
+    // If allowed_ips vector is empty we are allowing connections form any IP.
+    let allowed_ips = &cfg.live().allowed_ips;
+    if !allowed_ips.is_empty() {
+        let remote_ip = match req.peer_addr() {
+            Some(addr) => addr.ip(),
+            None => {
+                return Err(FwcError::Internal(
+                    "Allowed IPs list not empty and was not possible to get the remote IP",
+                ))
+            }
+        };
+
+        if !ip_in_list(remote_ip, allowed_ips) {
+            return Err(FwcError::NotAllowedIP);
+        }
+    }
+
+    // (2) If mutual TLS is enabled the TLS layer has already verified the client certificate,
+    // now check that its common name or subject alternative names are allowed.
+    if cfg.enable_client_cert {
+        let allowed = match req.conn_data::<ClientCert>() {
+            Some(cert) => {
+                let allowed_names = &cfg.live().allowed_client_names;
+                allowed_names.is_empty() || cert.is_allowed(allowed_names)
+            }
+            None => return Err(FwcError::ClientCertNotFound),
+        };
+
+        if !allowed {
+            return Err(FwcError::ClientCertNotAllowed);
+        }
+    }
+
+    Ok(())
+}
+
+/// (3) If the use of API Key is enabled, verify that the supplied API key is correct and that it
+/// is allowed to access the requested route.
+fn check_api_key(req: &ServiceRequest, cfg: &Config) -> crate::errors::Result<()> {
+    if !cfg.live().enable_api_key {
+        return Ok(());
+    }
+
+    if cfg.require_signed_requests {
+        return Err(FwcError::SignatureRequired);
+    }
+
+    let api_key = match req.headers().get("X-API-Key") {
+        Some(value) => value.to_str().unwrap_or(""),
+        None => return Err(FwcError::ApiKeyNotFound),
+    };
+
+    let key = find_key(cfg, |_name, key| key_eq(key, api_key)).ok_or(FwcError::ApiKeyNotValid)?;
+
+    authorize_key(req, key)
+}
+
+/// Keep track of the failed authentications of every source IP and ban the ones with too many.
+fn register_auth_result(
+    cfg: &Config,
+    peer_ip: Option<IpAddr>,
+    res: crate::errors::Result<()>,
+) -> crate::errors::Result<()> {
+    let ip = match peer_ip {
+        Some(ip) => ip,
+        None => return res,
+    };
+
+    match res {
+        Ok(()) => {
+            cfg.auth_failures.lock().unwrap().success(ip);
+            Ok(())
+        }
+        // A valid API key without enough scope is not an authentication failure.
+        Err(FwcError::ApiKeyScope) => Err(FwcError::ApiKeyScope),
+        Err(e @ FwcError::Internal(_)) => Err(e),
+        Err(e) => {
+            let policy = BanPolicy {
+                max_failures: cfg.auth_max_failures,
+                ban_time: cfg.auth_ban_time,
+                max_ban_time: cfg.auth_max_ban_time,
+                failures_window: cfg.auth_failures_window,
+            };
+            if let Some(ban_time) = cfg
+                .auth_failures
+                .lock()
+                .unwrap()
+                .failure(ip, now(), &policy)
+            {
+                warn!("Too many authentication failures, banning {ip} for {ban_time} seconds");
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Find the API key whose name and secret satisfy the predicate. The API_KEY of the configuration
+/// has full access and its name is `default`.
+fn find_key<F>(cfg: &Config, predicate: F) -> Option<ApiKey>
+where
+    F: Fn(&str, &str) -> bool,
+{
+    if predicate("default", &cfg.live().api_key) {
+        return Some(ApiKey {
+            name: String::from("default"),
+            key: String::new(),
+            scopes: vec![String::from("*")],
+        });
+    }
+
+    cfg.live()
+        .api_keys
+        .iter()
+        .find(|k| predicate(&k.name, &k.key))
+        .map(|k| ApiKey {
+            name: k.name.clone(),
+            key: String::new(),
+            scopes: k.scopes.clone(),
+        })
+}
+
+/// Check that the API key is allowed to access the requested route, and keep its identity for the
+/// routes that must check scopes that depend on the request data.
+fn authorize_key(req: &ServiceRequest, key: ApiKey) -> crate::errors::Result<()> {
+    audit_key(req, &key.name);
+    info!(
+        "API key '{}' used for {} {}",
+        key.name,
+        req.method(),
+        req.path()
+    );
+
+    if let Some(scope) = route_scope(req.path()) {
+        if !key.allows(scope) {
+            return Err(FwcError::ApiKeyScope);
+        }
+    }
+
+    req.extensions_mut().insert(key);
+
+    Ok(())
+}
+
+fn header_str<'a>(req: &'a ServiceRequest, name: &str) -> crate::errors::Result<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(FwcError::SignatureNotValid)
+}
+
+/// Verify the HMAC-SHA256 signature of the request, its timestamp and its nonce.
+async fn verify_signed_request(
+    req: &mut ServiceRequest,
+    cfg: &Config,
+) -> crate::errors::Result<ApiKey> {
+    let key_id = header_str(req, KEY_ID_HEADER)?.to_string();
+    let nonce = header_str(req, NONCE_HEADER)?.to_string();
+    let signature = header_str(req, SIGNATURE_HEADER)?.to_string();
+    let timestamp = header_str(req, TIMESTAMP_HEADER)?
+        .parse::<u64>()
+        .map_err(|_| FwcError::SignatureNotValid)?;
+
+    if nonce.len() < 16 || nonce.len() > 128 || !ALPHA_NUM_2.is_match(&nonce) {
+        return Err(FwcError::SignatureNotValid);
+    }
+
+    let now = now();
+    if now.abs_diff(timestamp) > cfg.signature_max_clock_skew {
+        return Err(FwcError::SignatureExpired);
+    }
+
+    // Read the whole body for computing its hash and put it back for the route handler.
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| FwcError::ActixWebError(e.to_string()))?;
+        if body.len() + chunk.len() > MAX_SIGNED_BODY_SIZE {
+            return Err(FwcError::SignedBodyTooBig);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("")
+        .to_string();
+    let signed = SignedRequest {
+        method: req.method().as_str(),
+        path: &path,
+        timestamp,
+        nonce: &nonce,
+        body: &body,
+    };
+
+    let key = find_key(cfg, |name, key| {
+        name == key_id && signed.verify(key, &signature)
+    })
+    .ok_or(FwcError::SignatureNotValid)?;
+
+    // Only remember the nonces of the requests with a valid signature.
+    {
+        debug!(
+            "Locking nonce cache mutex (thread id: {})",
+            thread_id::get()
+        );
+        let mut nonce_cache = cfg.nonce_cache.lock().unwrap();
+        if !nonce_cache.insert(
+            &nonce,
+            now,
+            cfg.signature_max_clock_skew,
+            cfg.signature_nonce_cache_size,
+        ) {
+            return Err(FwcError::SignatureReplayed);
+        }
+        debug!(
+            "Releasing nonce cache mutex (thread id: {})",
+            thread_id::get()
+        );
+    }
+
+    req.set_payload(Payload::from(body));
+
+    Ok(key)
+}
+
+/// Compare API keys in constant time.
+fn key_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a.as_bytes(), b.as_bytes())
+}
+
+/// Scope needed for access to an API route.
+///
+/// Routes that return `None` need a scope that depends on the request data and they
+/// must check it by means of the `require_scope` function.
+fn route_scope(path: &str) -> Option<&'static str> {
+    let path = path.strip_prefix("/api/v1").unwrap_or(path);
+
+    let scope = match path {
+        "/ping" => "read:ping",
+        "/info" => "read:info",
+        "/interfaces/info" => "read:interfaces",
+        "/iptables-save/data" | "/iptables-save/backend" => "read:iptables",
+        "/nftables/ruleset" => "read:nftables",
+        "/fwcloud_script/upload" | "/fwcloud_script/confirm" => "script:install",
+        "/fwcloud_script/validate" => "script:validate",
+        "/fwcloud_script/pending" => "script:status",
+        _ if path.starts_with("/fwcloud_script/history/") && path.ends_with("/install") => {
+            "script:install"
+        }
+        _ if path == "/fwcloud_script/history" || path.starts_with("/fwcloud_script/history/") => {
+            "script:history"
+        }
+        "/openvpn/files/upload" => "openvpn:upload",
+        "/openvpn/files/remove" => "openvpn:remove",
+        "/openvpn/files/sha256" => "openvpn:read",
+        "/openvpn/get/status" | "/openvpn/update/status" | "/openvpn/get/status/rt" => {
+            "openvpn:status"
+        }
+        "/wireguard/files/upload" => "wireguard:upload",
+        "/wireguard/files/remove" => "wireguard:remove",
+        "/ipsec/files/upload" => "ipsec:upload",
+        "/ipsec/files/remove" => "ipsec:remove",
+        "/daemon/config/upload" | "/daemon/config/confirm" => "daemon:config",
+        "/drift" | "/drift/check" => "read:drift",
+        "/jobs" => "jobs:read",
+        _ if path.starts_with("/jobs/") && path.ends_with("/cancel") => "jobs:cancel",
+        _ if path.starts_with("/jobs/") => "jobs:read",
+        "/auth/bans" => "admin:bans",
+        "/audit" => "admin:audit",
+        "/plugin" | "/systemctl" => return None,
+        _ if path == "/ws" || path.starts_with("/ws/") => "ws:open",
+        _ => "*",
+    };
+
+    Some(scope)
+}
+
+/// Verify that the API key used in the request is allowed to use the scope.
+pub fn require_scope(req: &HttpRequest, scope: &str) -> crate::errors::Result<()> {
+    match req.extensions().get::<ApiKey>() {
+        Some(key) if !key.allows(scope) => Err(FwcError::ApiKeyScope),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_key_scopes() {
+        let key = ApiKey {
+            name: String::from("monitoring"),
+            key: String::new(),
+            scopes: vec![String::from("read:*"), String::from("plugin:disable")],
+        };
+
+        assert!(key.allows("read:info"));
+        assert!(key.allows("read:iptables"));
+        assert!(key.allows("plugin:disable"));
+        assert!(!key.allows("plugin:enable"));
+        assert!(!key.allows("script:install"));
+        assert!(!key.allows("*"));
+    }
+
+    #[test]
+    fn routes_scopes() {
+        assert_eq!(route_scope("/api/v1/info"), Some("read:info"));
+        assert_eq!(
+            route_scope("/api/v1/openvpn/get/status/rt"),
+            Some("openvpn:status")
+        );
+        assert_eq!(route_scope("/api/v1/ws/test/id/30"), Some("ws:open"));
+        assert_eq!(route_scope("/api/v1/auth/bans"), Some("admin:bans"));
+        assert_eq!(route_scope("/api/v1/audit"), Some("admin:audit"));
+        assert_eq!(route_scope("/api/v1/plugin"), None);
+        assert_eq!(route_scope("/api/v1/jobs"), Some("jobs:read"));
+        assert_eq!(
+            route_scope("/api/v1/jobs/1b4e28ba-2fa1-11d2-883f-0016d3cca427/cancel"),
+            Some("jobs:cancel")
+        );
+        assert_eq!(route_scope("/api/v1/unknown"), Some("*"));
+    }
+}
@@ -0,0 +1,190 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use sysinfo::System;
+
+use crate::config::{random_api_key, Config};
+use crate::config_file::effective_config;
+use crate::errors::{FwcError, Result};
+use crate::status::query_status;
+use crate::tls::generate_self_signed;
+
+#[derive(Serialize)]
+struct Version {
+    name: &'static str,
+    version: &'static str,
+}
+
+/// Validate the configuration and return the effective one, with secrets redacted.
+///
+/// Nothing is created, and an access policy that would prevent the agent from starting is reported as a warning.
+pub fn check_config(config_file: Option<&str>) -> Result<String> {
+    let cfg = Config::read(config_file)?;
+    let mut res = effective_config(&cfg)?;
+    if let Err(e) = cfg.check_access_policy() {
+        res.push_str(&format!("\n# Warning: {e}\n"));
+    }
+
+    Ok(res)
+}
+
+pub fn gen_api_key() -> String {
+    random_api_key()
+}
+
+/// Generate a self-signed certificate in the configured key and certificate files.
+///
+/// Existing files are only replaced if `force` is given, once the new ones have been generated.
+pub fn gen_cert(
+    config_file: Option<&str>,
+    host_name: Option<String>,
+    force: bool,
+) -> Result<String> {
+    let cfg = Config::read(config_file)?;
+
+    for file in [&cfg.tls_key_file, &cfg.tls_cert_file] {
+        if Path::new(file).exists() && !force {
+            return Err(FwcError::TlsConfig(format!(
+                "File '{file}' already exists (use --force for replacing it)"
+            )));
+        }
+        if let Some(dir) = Path::new(file).parent() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    let host_name = host_name
+        .or_else(System::host_name)
+        .unwrap_or_else(|| String::from("fwcloud-agent"));
+
+    // Generated in temporary files, the current ones are kept if anything fails.
+    let key_tmp = format!("{}.tmp", cfg.tls_key_file);
+    let cert_tmp = format!("{}.tmp", cfg.tls_cert_file);
+    for tmp in [&key_tmp, &cert_tmp] {
+        let _ = fs::remove_file(tmp);
+    }
+    let res = generate_self_signed(&key_tmp, &cert_tmp, &host_name).and_then(|cert| {
+        fs::rename(&key_tmp, &cfg.tls_key_file)?;
+        fs::rename(&cert_tmp, &cfg.tls_cert_file)?;
+        Ok(cert)
+    });
+    if res.is_err() {
+        for tmp in [&key_tmp, &cert_tmp] {
+            let _ = fs::remove_file(tmp);
+        }
+    }
+    let cert = res?;
+
+    Ok(format!(
+        "Private key: {}\nCertificate: {}\nSubject: {}\nNot after: {}\nSHA256 fingerprint: {}",
+        cfg.tls_key_file, cfg.tls_cert_file, cert.subject, cert.not_after, cert.sha256_fingerprint
+    ))
+}
+
+pub fn version(json: bool) -> Result<String> {
+    let version = Version {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+    };
+
+    if json {
+        Ok(serde_json::to_string(&version)?)
+    } else {
+        Ok(format!("{} {}", version.name, version.version))
+    }
+}
+
+/// Status of the running agent, using the given status socket or the configured one.
+pub async fn status(config_file: Option<&str>, socket: Option<String>) -> Result<String> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => Config::read(config_file)?.status_socket,
+    };
+
+    let status = query_status(&socket)
+        .await
+        .map_err(|e| FwcError::AgentNotRunning(format!("{socket} ({e})")))?;
+
+    Ok(serde_json::to_string_pretty(&status)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use std::env;
+
+    #[test]
+    fn prints_version() {
+        assert_eq!(
+            version(false).unwrap(),
+            format!("fwcloud-agent {}", env!("CARGO_PKG_VERSION"))
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&version(true).unwrap()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn generates_api_keys() {
+        let key = gen_api_key();
+        assert_eq!(key.len(), 64);
+        assert!(key.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(key, gen_api_key());
+    }
+
+    #[test]
+    #[serial]
+    fn generates_certificates_whatever_the_access_policy() {
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", uuid::Uuid::new_v4()));
+        env::set_var("ETC_DIR", dir.to_str().unwrap());
+        env::set_var("ENABLE_API_KEY", "false");
+        env::set_var("BIND_IP", "0.0.0.0");
+
+        let res = check_config(None);
+        assert!(res.unwrap().contains("# Warning: "));
+        assert!(!dir.exists());
+
+        let first = gen_cert(None, Some(String::from("fwcloud-test")), false).unwrap();
+        assert!(matches!(
+            gen_cert(None, None, false),
+            Err(FwcError::TlsConfig(_))
+        ));
+        let second = gen_cert(None, Some(String::from("fwcloud-test")), true).unwrap();
+        env::remove_var("ETC_DIR");
+        env::remove_var("ENABLE_API_KEY");
+        env::remove_var("BIND_IP");
+
+        assert_ne!(first, second);
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, vec!["cert.pem", "key.pem"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -19,18 +19,32 @@
     You should have received a copy of the GNU General Public License
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
+use ipnet::IpNet;
 use rand::Rng;
 use rand_distr::Alphanumeric;
+use serde::Deserialize;
+use std::path::Path;
 use std::{
     collections::HashMap,
-    env, fs,
-    net::TcpListener,
-    sync::{Arc, Mutex},
+    fs,
+    net::{IpAddr, SocketAddr, TcpListener},
+    sync::{Arc, Mutex, RwLock},
 };
 use uuid::Uuid;
 use validator::Validate; // A trait that the Validate derive will impl
 
-use crate::errors::Result;
+use crate::config_file::Settings;
+use crate::errors::{FwcError, Result};
+use crate::tls::{tls_profile, TlsCert};
+use crate::utils::audit_log::AuditLog;
+use crate::utils::auth_failures::AuthFailures;
+use crate::utils::config_update::PendingConfig;
+use crate::utils::drift::DriftStatus;
+use crate::utils::jobs::Jobs;
+use crate::utils::net::{parse_ip_net_list, parse_listener_list};
+use crate::utils::policy_apply::PendingPolicy;
+use crate::utils::script_signature;
+use crate::utils::signature::NonceCache;
 use crate::utils::ws::WsData;
 
 pub struct MyMutex {
@@ -39,55 +53,244 @@ pub struct MyMutex {
     pub ipsec: Arc<tokio::sync::Mutex<u8>>,
     pub fwcloud_script: Arc<tokio::sync::Mutex<u8>>,
     pub daemon: Arc<tokio::sync::Mutex<u8>>,
-    pub plugins: Arc<Mutex<u8>>,
+    pub plugins: Arc<tokio::sync::Mutex<u8>>,
+}
+
+/// Named API key with the list of scopes (`area:action`) that it is allowed to use.
+///
+/// The scope `*` allows everything and `area:*` allows all the actions of an area,
+/// for example `openvpn:*` or `read:*`.
+#[derive(Clone, Deserialize, Validate)]
+pub struct ApiKey {
+    #[validate(regex(path = "crate::utils::myregex::ALPHA_NUM_2"))]
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+
+    #[validate(regex(path = "crate::utils::myregex::ALPHA_NUM_2"))]
+    #[validate(length(min = 16, max = 128))]
+    pub key: String,
+
+    #[validate(custom(function = "validate_scopes", message = "Bad API key scope"))]
+    pub scopes: Vec<String>,
+}
+
+/// Random API key of 64 alphanumeric characters.
+pub fn random_api_key() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect()
+}
+
+fn validate_scopes(scopes: &[String]) -> std::result::Result<(), validator::ValidationError> {
+    for scope in scopes.iter() {
+        if !crate::utils::myregex::API_KEY_SCOPE.is_match(scope) {
+            return Err(validator::ValidationError::new("scope"));
+        }
+    }
+
+    Ok(())
+}
+
+/// TCP endpoint where the API is served, using TLS if it has a certificate.
+#[derive(Clone)]
+pub struct Listener {
+    pub address: SocketAddr,
+    pub tls: Option<TlsCert>,
+}
+
+/// Options that can be changed without restarting the agent.
+///
+/// They are replaced as a whole when the configuration is reloaded (SIGHUP), this way a request never sees a mix of
+/// old and new options.
+#[derive(Clone, Default, Validate)]
+pub struct LiveOptions {
+    pub allowed_ips: Vec<IpNet>,
+
+    // Named API keys with restricted scopes, loaded from the key store file etc_dir/api_keys.json.
+    #[validate(nested)]
+    pub api_keys: Vec<ApiKey>,
+
+    pub fwcloud_script_paths: Vec<String>,
+
+    pub openvpn_status_files: Vec<String>,
+
+    pub enable_api_key: bool,
+
+    #[validate(regex(path = "crate::utils::myregex::ALPHA_NUM_2"))]
+    #[validate(length(min = 16, max = 128))]
+    pub api_key: String,
+
+    // Client certificate names (common name or subject alternative name) allowed, any one if it is empty.
+    pub allowed_client_names: Vec<String>,
+}
+
+impl ApiKey {
+    pub fn allows(&self, scope: &str) -> bool {
+        let area = scope.split(':').next().unwrap_or("");
+
+        self.scopes
+            .iter()
+            .any(|s| s == "*" || s == scope || *s == format!("{area}:*"))
+    }
 }
 
 #[derive(Validate)]
 pub struct Config {
-    pub etc_dir: &'static str,
-    pub tmp_dir: &'static str,
-    pub data_dir: &'static str,
-    pub plugins_dir: &'static str,
+    pub etc_dir: String,
+    pub tmp_dir: String,
+    pub data_dir: String,
+    pub plugins_dir: String,
 
     pub enable_env_logger: bool,
 
-    #[validate(regex(path = "crate::utils::myregex::IPV4", message = "Bad IPv4 address"))]
+    #[validate(custom(
+        function = "crate::utils::net::validate_ip",
+        message = "Bad IPv4 or IPv6 address in BIND_IP"
+    ))]
     pub bind_ip: String,
 
     #[validate(range(min = 1, max = 65535))]
     pub bind_port: u16,
 
+    // Additional TCP listeners, each one with its own TLS settings.
+    #[validate(custom(
+        function = "crate::utils::net::validate_listener_list",
+        message = "Bad listener in EXTRA_LISTENERS"
+    ))]
+    extra_listeners_list: String,
+
+    // TCP listeners, the first one is the main listener (BIND_IP, BIND_PORT and ENABLE_SSL).
+    pub listeners: Vec<Listener>,
+
+    // Local Unix socket, authorized by its file system permissions instead of by the API key.
+    pub unix_socket: String,
+    #[validate(regex(
+        path = "crate::utils::myregex::FILE_PERMISSIONS",
+        message = "Invalid permissions in UNIX_SOCKET_MODE"
+    ))]
+    pub unix_socket_mode: String,
+
     #[validate(range(min = 1, max = 65535))]
     pub workers: usize,
 
     pub enable_tls: bool,
 
+    // Base TLS settings profile: mozilla_modern or mozilla_intermediate.
+    #[validate(regex(
+        path = "crate::utils::myregex::TLS_PROFILES",
+        message = "Bad TLS profile in TLS_PROFILE (mozilla_modern or mozilla_intermediate)"
+    ))]
+    pub tls_profile: String,
     #[validate(regex(
-        path = "crate::utils::myregex::IPV4_LIST",
-        message = "Bad IPv4 address list"
+        path = "crate::utils::myregex::TLS_VERSIONS",
+        message = "Bad TLS version in TLS_MIN_VERSION (1.2 or 1.3)"
+    ))]
+    pub tls_min_version: String,
+    // OpenSSL cipher list for TLS 1.2 and cipher suites for TLS 1.3, empty for the profile defaults.
+    pub tls_cipher_list: String,
+    pub tls_ciphersuites: String,
+    pub tls_key_file: String,
+    pub tls_cert_file: String,
+
+    // Seconds between checks of changes in the certificate files for reloading them (0 disables the checks).
+    pub tls_reload_check_interval: u64,
+
+    // Mutual TLS, require a client certificate signed by the CA bundle etc_dir/ca.pem.
+    pub enable_client_cert: bool,
+    // Check client certificates against the revocation list etc_dir/crl.pem.
+    pub client_cert_crl: bool,
+    #[validate(regex(
+        path = "crate::utils::myregex::CERT_NAMES_LIST",
+        message = "Bad certificate names list in ALLOWED_CLIENT_NAMES"
+    ))]
+    allowed_client_names_list: String,
+
+    #[validate(custom(
+        function = "crate::utils::net::validate_ip_net_list",
+        message = "Bad IP address or network in ALLOWED_IPS"
     ))]
     pub allowed_ips_list: String,
-    pub allowed_ips: Vec<String>,
 
-    pub enable_api_key: bool,
+    // Allow starting without any authorization policy while listening on a non-loopback address.
+    pub allow_insecure: bool,
 
-    #[validate(regex(path = "crate::utils::myregex::ALPHA_NUM_2"))]
-    #[validate(length(min = 16, max = 128))]
-    pub api_key: String,
+    // API_KEY is not set and a random one has been generated.
+    generated_api_key: bool,
+
+    // Only accept HMAC-SHA256 signed requests, the X-API-Key header alone will be rejected.
+    pub require_signed_requests: bool,
+
+    // Maximum difference in seconds between the timestamp of a signed request and the local clock.
+    #[validate(range(min = 1, max = 3600))]
+    pub signature_max_clock_skew: u64,
+
+    #[validate(range(min = 1))]
+    pub signature_nonce_cache_size: usize,
+
+    // Only run FWCloud scripts with a valid detached signature made by the console.
+    pub require_signed_scripts: bool,
+    // Public key (PEM, Ed25519 or RSA) of the console for verifying the FWCloud script signatures.
+    pub script_signing_key: String,
+
+    // Failed authentications from the same IP before banning it (0 disables the bans).
+    pub auth_max_failures: u32,
+    // Seconds of the first ban of an IP, it is doubled for each new ban of the same IP.
+    #[validate(range(min = 1))]
+    pub auth_ban_time: u64,
+    #[validate(range(min = 1))]
+    pub auth_max_ban_time: u64,
+    // Seconds without failed authentications after which the history of an IP is forgotten.
+    #[validate(range(min = 1))]
+    pub auth_failures_window: u64,
+
+    // Audit log of the requests that modify the system, stored in data_dir/audit.log.
+    pub enable_audit_log: bool,
+    #[validate(range(min = 1024))]
+    pub audit_log_max_size: u64,
+    #[validate(range(min = 1, max = 100))]
+    pub audit_log_max_files: usize,
 
     #[validate(regex(
         path = "crate::utils::myregex::ABSOLUTE_PATH_LIST",
         message = "Bad absolute path file names in FWCLOUD_SCRIPT_PATHS"
     ))]
     fwcloud_script_paths_list: String,
-    pub fwcloud_script_paths: Vec<String>,
 
     #[validate(regex(
         path = "crate::utils::myregex::ABSOLUTE_PATH_LIST",
         message = "Bad absolute path file names in OPENVPN_STATUS_FILES"
     ))]
     openvpn_status_files_list: String,
-    pub openvpn_status_files: Vec<String>,
+
+    // Number of installed FWCloud scripts kept in data_dir/script_history, 0 disables the history.
+    #[validate(range(max = 100))]
+    pub script_history_size: usize,
+
+    // Seconds between the checks of the running ruleset against the one installed by FWCloud, 0 disables them.
+    pub drift_check_interval: u64,
+
+    // Number of finished jobs kept in data_dir/jobs with their output.
+    #[validate(range(min = 1, max = 1000))]
+    pub job_history_size: usize,
+
+    // Seconds before terminating the commands run for each type of operation, 0 means no timeout.
+    #[validate(range(max = 86400))]
+    pub plugin_timeout: u64,
+    #[validate(range(max = 86400))]
+    pub fwcloud_script_timeout: u64,
+    #[validate(range(max = 86400))]
+    pub systemctl_timeout: u64,
+    #[validate(range(max = 86400))]
+    pub cmd_timeout: u64,
+
+    // Seconds for confirming a configuration uploaded through the API before it is rolled back.
+    #[validate(range(min = 5, max = 3600))]
+    pub config_rollback_timeout: u64,
+
+    // Local socket where the agent answers its status to the status command, data_dir/fwcloud-agent.sock by default.
+    pub status_socket: String,
 
     #[validate(range(min = 1))]
     pub openvpn_status_sampling_interval: u64,
@@ -101,67 +304,216 @@ pub struct Config {
     pub mutex: MyMutex,
 
     pub ws_map: Arc<Mutex<HashMap<Uuid, Arc<Mutex<WsData>>>>>,
+
+    pub nonce_cache: Arc<Mutex<NonceCache>>,
+
+    pub auth_failures: Arc<Mutex<AuthFailures>>,
+
+    pub audit_log: Arc<Mutex<AuditLog>>,
+
+    // TLS context of the TLS_KEY_FILE and TLS_CERT_FILE certificate files.
+    pub tls: TlsCert,
+
+    // Configuration file given in the command line, it is read again when the configuration is reloaded.
+    pub config_file: Option<String>,
+
+    pub live: Arc<RwLock<Arc<LiveOptions>>>,
+
+    pub pending_config: Arc<Mutex<Option<PendingConfig>>>,
+
+    pub pending_policy: Arc<Mutex<Option<PendingPolicy>>>,
+
+    pub drift: Arc<Mutex<DriftStatus>>,
+
+    pub jobs: Arc<Mutex<Jobs>>,
 }
 
 impl Config {
     pub fn new() -> Result<Self> {
-        dotenvy::dotenv().ok();
+        Config::load(None)
+    }
+
+    /// Load the configuration from the environment variables and the configuration file for running the agent.
+    ///
+    /// Besides validating it, the access policy is checked and the base directories are created if they don't
+    /// exist.
+    pub fn load(config_file: Option<&str>) -> Result<Self> {
+        let cfg = Config::read(config_file)?;
+        cfg.check_access_policy()?;
+
+        // Create config and temporary directories if don't exist.
+        fs::create_dir_all(&cfg.etc_dir)?;
+        fs::create_dir_all(&cfg.tmp_dir)?;
+        fs::create_dir_all(&cfg.data_dir)?;
+
+        Ok(cfg)
+    }
+
+    /// Read and validate the configuration without modifying anything nor checking the access policy, for the
+    /// command line subcommands and for validating a new configuration.
+    pub fn read(config_file: Option<&str>) -> Result<Self> {
+        Config::from_settings(Settings::load(config_file)?, config_file)
+    }
+
+    /// Build and validate the configuration from the already loaded settings, without side effects.
+    pub(crate) fn from_settings(settings: Settings, config_file: Option<&str>) -> Result<Self> {
+        // Base directories are converted to absolute paths, this way we don't depend on the working directory.
+        let etc_dir = absolute_path(settings.get("ETC_DIR", "./etc"))?;
+        let tmp_dir = absolute_path(settings.get("TMP_DIR", "./tmp"))?;
+        let data_dir = absolute_path(settings.get("DATA_DIR", "./data"))?;
+        let plugins_dir = absolute_path(settings.get("PLUGINS_DIR", "./plugins"))?;
 
         let mut cfg = Config {
-            etc_dir: "./etc",
-            tmp_dir: "./tmp",
-            data_dir: "./data",
-            plugins_dir: "./plugins",
+            audit_log: Arc::new(Mutex::new(AuditLog::new(format!("{data_dir}/audit.log")))),
+            etc_dir,
+            tmp_dir,
+            data_dir,
+            plugins_dir,
 
             enable_env_logger: true,
 
-            bind_ip: env::var("BIND_IP").unwrap_or_else(|_| String::from("0.0.0.0")),
-            bind_port: env::var("BIND_PORT")
-                .unwrap_or_else(|_| String::from("33033"))
+            bind_ip: settings.get("BIND_IP", "0.0.0.0"),
+            bind_port: settings
+                .get("BIND_PORT", "33033")
                 .parse::<u16>()
                 .unwrap_or(33033),
-            workers: env::var("WORKERS")
-                .unwrap_or_else(|_| String::from("5"))
-                .parse::<usize>()
-                .unwrap_or(5),
-            enable_tls: env::var("ENABLE_SSL")
-                .unwrap_or_else(|_| String::from("true"))
+            workers: settings.get("WORKERS", "5").parse::<usize>().unwrap_or(5),
+            extra_listeners_list: settings.get("EXTRA_LISTENERS", ""),
+            listeners: vec![],
+            unix_socket: settings.get("UNIX_SOCKET", ""),
+            unix_socket_mode: settings.get("UNIX_SOCKET_MODE", "600"),
+            enable_tls: settings
+                .get("ENABLE_SSL", "true")
                 .parse::<bool>()
                 .unwrap_or(true),
+            tls_profile: settings.get("TLS_PROFILE", "mozilla_intermediate"),
+            tls_min_version: settings.get("TLS_MIN_VERSION", "1.2"),
+            tls_cipher_list: settings.get("TLS_CIPHER_LIST", ""),
+            tls_ciphersuites: settings.get("TLS_CIPHERSUITES", ""),
+            tls_key_file: settings.get("TLS_KEY_FILE", ""),
+            tls_cert_file: settings.get("TLS_CERT_FILE", ""),
+            tls_reload_check_interval: settings
+                .get("TLS_RELOAD_CHECK_INTERVAL", "60")
+                .parse::<u64>()
+                .unwrap_or(60),
+
+            enable_client_cert: settings
+                .get("ENABLE_CLIENT_CERT", "false")
+                .parse::<bool>()
+                .unwrap_or(false),
+            client_cert_crl: settings
+                .get("CLIENT_CERT_CRL", "false")
+                .parse::<bool>()
+                .unwrap_or(false),
+            allowed_client_names_list: settings.get("ALLOWED_CLIENT_NAMES", ""),
+
+            allowed_ips_list: settings.get("ALLOWED_IPS", ""),
 
-            allowed_ips_list: env::var("ALLOWED_IPS").unwrap_or_else(|_| String::from("")),
-            allowed_ips: vec![],
+            allow_insecure: settings
+                .get("ALLOW_INSECURE", "false")
+                .parse::<bool>()
+                .unwrap_or(false),
+            generated_api_key: settings.get_opt("API_KEY").is_none(),
 
-            enable_api_key: env::var("ENABLE_API_KEY")
-                .unwrap_or_else(|_| String::from("true"))
+            require_signed_requests: settings
+                .get("REQUIRE_SIGNED_REQUESTS", "false")
+                .parse::<bool>()
+                .unwrap_or(false),
+            signature_max_clock_skew: settings
+                .get("SIGNATURE_MAX_CLOCK_SKEW", "300")
+                .parse::<u64>()
+                .unwrap_or(300),
+            signature_nonce_cache_size: settings
+                .get("SIGNATURE_NONCE_CACHE_SIZE", "100000")
+                .parse::<usize>()
+                .unwrap_or(100_000),
+            require_signed_scripts: settings
+                .get("REQUIRE_SIGNED_SCRIPTS", "false")
+                .parse::<bool>()
+                .unwrap_or(false),
+            script_signing_key: settings.get("SCRIPT_SIGNING_KEY", ""),
+
+            auth_max_failures: settings
+                .get("AUTH_MAX_FAILURES", "5")
+                .parse::<u32>()
+                .unwrap_or(5),
+            auth_ban_time: settings
+                .get("AUTH_BAN_TIME", "60")
+                .parse::<u64>()
+                .unwrap_or(60),
+            auth_max_ban_time: settings
+                .get("AUTH_MAX_BAN_TIME", "86400")
+                .parse::<u64>()
+                .unwrap_or(86400),
+            auth_failures_window: settings
+                .get("AUTH_FAILURES_WINDOW", "600")
+                .parse::<u64>()
+                .unwrap_or(600),
+
+            enable_audit_log: settings
+                .get("AUDIT_LOG", "true")
                 .parse::<bool>()
                 .unwrap_or(true),
-            api_key: env::var("API_KEY").unwrap_or_else(|_| {
-                rand::rng()
-                    .sample_iter(&Alphanumeric)
-                    .take(64)
-                    .map(char::from)
-                    .collect()
-            }),
-
-            fwcloud_script_paths_list: env::var("FWCLOUD_SCRIPT_PATHS").unwrap_or_else(|_| {
-                String::from("/etc/fwcloud/fwcloud.sh,/config/scripts/post-config.d/fwcloud.sh")
-            }),
-            fwcloud_script_paths: vec![],
-
-            openvpn_status_files_list: env::var("OPENVPN_STATUS_FILES")
-                .unwrap_or_else(|_| String::from("/etc/openvpn/openvpn-status.log")),
-            openvpn_status_files: vec![],
-            openvpn_status_sampling_interval: env::var("OPENVPN_STATUS_SAMPLING_INTERVAL")
-                .unwrap_or_else(|_| String::from("30"))
+            audit_log_max_size: settings
+                .get("AUDIT_LOG_MAX_SIZE", "10485760")
+                .parse::<u64>()
+                .unwrap_or(10_485_760), // Ten megabytes.
+            audit_log_max_files: settings
+                .get("AUDIT_LOG_MAX_FILES", "5")
+                .parse::<usize>()
+                .unwrap_or(5),
+
+            fwcloud_script_paths_list: settings.get(
+                "FWCLOUD_SCRIPT_PATHS",
+                "/etc/fwcloud/fwcloud.sh,/config/scripts/post-config.d/fwcloud.sh",
+            ),
+
+            openvpn_status_files_list: settings
+                .get("OPENVPN_STATUS_FILES", "/etc/openvpn/openvpn-status.log"),
+            script_history_size: settings
+                .get("SCRIPT_HISTORY_SIZE", "10")
+                .parse::<usize>()
+                .unwrap_or(10),
+            drift_check_interval: settings
+                .get("DRIFT_CHECK_INTERVAL", "300")
+                .parse::<u64>()
+                .unwrap_or(300),
+            job_history_size: settings
+                .get("JOB_HISTORY_SIZE", "100")
+                .parse::<usize>()
+                .unwrap_or(100),
+            plugin_timeout: settings
+                .get("PLUGIN_TIMEOUT", "3600")
+                .parse::<u64>()
+                .unwrap_or(3600),
+            fwcloud_script_timeout: settings
+                .get("FWCLOUD_SCRIPT_TIMEOUT", "600")
+                .parse::<u64>()
+                .unwrap_or(600),
+            systemctl_timeout: settings
+                .get("SYSTEMCTL_TIMEOUT", "120")
+                .parse::<u64>()
+                .unwrap_or(120),
+            cmd_timeout: settings
+                .get("CMD_TIMEOUT", "120")
+                .parse::<u64>()
+                .unwrap_or(120),
+            config_rollback_timeout: settings
+                .get("CONFIG_ROLLBACK_TIMEOUT", "60")
+                .parse::<u64>()
+                .unwrap_or(60),
+            status_socket: settings.get("STATUS_SOCKET", ""),
+
+            openvpn_status_sampling_interval: settings
+                .get("OPENVPN_STATUS_SAMPLING_INTERVAL", "30")
                 .parse::<u64>()
                 .unwrap_or(30),
-            openvpn_status_request_max_lines: env::var("OPENVPN_STATUS_REQUEST_MAX_LINES")
-                .unwrap_or_else(|_| String::from("1000"))
+            openvpn_status_request_max_lines: settings
+                .get("OPENVPN_STATUS_REQUEST_MAX_LINES", "1000")
                 .parse::<usize>()
                 .unwrap_or(1000),
-            openvpn_status_cache_max_size: env::var("OPENVPN_STATUS_CACHE_MAX_SIZE")
-                .unwrap_or_else(|_| String::from("10_485_760"))
+            openvpn_status_cache_max_size: settings
+                .get("OPENVPN_STATUS_CACHE_MAX_SIZE", "10_485_760")
                 .parse::<usize>()
                 .unwrap_or(10_485_760),
 
@@ -171,23 +523,88 @@ impl Config {
                 ipsec: Arc::new(tokio::sync::Mutex::new(0)),
                 fwcloud_script: Arc::new(tokio::sync::Mutex::new(0)),
                 daemon: Arc::new(tokio::sync::Mutex::new(0)),
-                plugins: Arc::new(Mutex::new(0)),
+                plugins: Arc::new(tokio::sync::Mutex::new(0)),
             },
 
             ws_map: Arc::new(Mutex::new(HashMap::new())),
+
+            nonce_cache: Arc::new(Mutex::new(NonceCache::new())),
+            auth_failures: Arc::new(Mutex::new(AuthFailures::new())),
+            tls: TlsCert::new("", ""),
+
+            config_file: config_file.map(String::from),
+            live: Arc::new(RwLock::new(Arc::new(LiveOptions::default()))),
+            pending_config: Arc::new(Mutex::new(None)),
+            pending_policy: Arc::new(Mutex::new(None)),
+            drift: Arc::new(Mutex::new(DriftStatus::default())),
+            jobs: Arc::new(Mutex::new(Jobs::default())),
         };
 
         cfg.validate()?;
 
+        let mut live = LiveOptions {
+            enable_api_key: settings
+                .get("ENABLE_API_KEY", "true")
+                .parse::<bool>()
+                .unwrap_or(true),
+            api_key: settings.get_opt("API_KEY").unwrap_or_else(random_api_key),
+            ..Default::default()
+        };
+
+        // Load the API keys store.
+        let api_keys_file = format!("{}/api_keys.json", cfg.etc_dir);
+        if Path::new(&api_keys_file).is_file() {
+            live.api_keys = serde_json::from_str(&fs::read_to_string(&api_keys_file)?)?;
+        }
+        live.validate()?;
+
         // We need at least two workers.
         // If we have less than two workers websocket output is not displayed in real time.
         if cfg.workers < 2 {
             cfg.workers = 2;
         }
 
-        // Create the list of allowed IPs.
-        for ip in cfg.allowed_ips_list.split(' ').filter(|&x| !x.is_empty()) {
-            cfg.allowed_ips.push(String::from(ip));
+        // Create the list of allowed IPs and networks.
+        live.allowed_ips = parse_ip_net_list(&cfg.allowed_ips_list).unwrap_or_default();
+
+        // Create the list of client certificate names (common name or subject alternative name) allowed.
+        for name in cfg
+            .allowed_client_names_list
+            .split(' ')
+            .filter(|&x| !x.is_empty())
+        {
+            live.allowed_client_names.push(String::from(name));
+        }
+
+        if cfg.enable_client_cert && !cfg.enable_tls {
+            return Err(FwcError::Internal(
+                "Client certificate authentication requires TLS (ENABLE_SSL=true)",
+            ));
+        }
+
+        if cfg.status_socket.is_empty() {
+            cfg.status_socket = format!("{}/fwcloud-agent.sock", cfg.data_dir);
+        }
+
+        if cfg.tls_key_file.is_empty() {
+            cfg.tls_key_file = format!("{}/key.pem", cfg.etc_dir);
+        }
+        if cfg.tls_cert_file.is_empty() {
+            cfg.tls_cert_file = format!("{}/cert.pem", cfg.etc_dir);
+        }
+        cfg.tls = TlsCert::new(&cfg.tls_key_file, &cfg.tls_cert_file);
+        cfg.listeners = cfg.build_listeners();
+        if cfg.listeners.iter().any(|listener| listener.tls.is_some()) {
+            // Verify the protocol and ciphers settings now instead of when starting the server.
+            tls_profile(&cfg)?;
+        }
+
+        if cfg.script_signing_key.is_empty() {
+            cfg.script_signing_key = format!("{}/console_pub.pem", cfg.etc_dir);
+        }
+        if cfg.require_signed_scripts {
+            // Without a usable key no FWCloud script could be installed.
+            script_signature::load_key(&cfg.script_signing_key)?;
         }
 
         for file in cfg
@@ -195,7 +612,7 @@ impl Config {
             .split(',')
             .filter(|&x| !x.is_empty())
         {
-            cfg.fwcloud_script_paths.push(String::from(file.trim()));
+            live.fwcloud_script_paths.push(String::from(file.trim()));
         }
 
         for file in cfg
@@ -203,27 +620,337 @@ impl Config {
             .split(',')
             .filter(|&x| !x.is_empty())
         {
-            cfg.openvpn_status_files.push(String::from(file.trim()));
+            live.openvpn_status_files.push(String::from(file.trim()));
         }
-
-        // Create config and temporary directories if don't exist.
-        fs::create_dir_all(cfg.etc_dir)?;
-        fs::create_dir_all(cfg.tmp_dir)?;
-        fs::create_dir_all(cfg.data_dir)?;
+        cfg.set_live(live);
 
         Ok(cfg)
     }
 
-    pub fn bind_to(&mut self) -> TcpListener {
-        let addr = format!("{}:{}", self.bind_ip, self.bind_port);
-        let listener = TcpListener::bind(addr)
-            .unwrap_or_else(|_| panic!("Error binding to {}:{}", self.bind_ip, self.bind_port));
+    /// Refuse an API open to anyone in the network.
+    ///
+    /// Without API key, client certificate and allowed IPs list there is no access control at all, and this is only
+    /// acceptable if we listen on a loopback address or the insecure override has been explicitly given.
+    pub fn check_access_policy(&self) -> Result<()> {
+        self.check_live_access_policy(&self.live())
+    }
+
+    /// Access policy resulting from the given live options with the listeners, TLS and overrides of this
+    /// configuration, this way live options that are going to be swapped in are checked against the running agent.
+    pub fn check_live_access_policy(&self, live: &LiveOptions) -> Result<()> {
+        let loopback = self
+            .listeners
+            .iter()
+            .all(|listener| listener.address.ip().is_loopback());
+
+        if !live.enable_api_key
+            && !self.enable_client_cert
+            && live.allowed_ips.is_empty()
+            && !loopback
+            && !self.allow_insecure
+        {
+            return Err(FwcError::InsecureAccessPolicy);
+        }
+
+        Ok(())
+    }
+
+    // Main listener followed by the additional ones. Listeners with the same certificate files share the TLS context.
+    fn build_listeners(&self) -> Vec<Listener> {
+        let mut listeners = vec![Listener {
+            address: SocketAddr::new(
+                self.bind_ip.parse().unwrap_or(IpAddr::from([0, 0, 0, 0])),
+                self.bind_port,
+            ),
+            tls: self.enable_tls.then(|| self.tls.clone()),
+        }];
+
+        for spec in parse_listener_list(&self.extra_listeners_list).unwrap_or_default() {
+            let tls = spec.tls.unwrap_or(self.enable_tls).then(|| {
+                let cert = TlsCert::new(
+                    spec.key_file.as_deref().unwrap_or(&self.tls_key_file),
+                    spec.cert_file.as_deref().unwrap_or(&self.tls_cert_file),
+                );
+                self.tls_certs_of(&listeners)
+                    .into_iter()
+                    .chain([self.tls.clone()])
+                    .find(|shared| shared.same_files(&cert))
+                    .unwrap_or(cert)
+            });
+            listeners.push(Listener {
+                address: spec.address,
+                tls,
+            });
+        }
+
+        listeners
+    }
+
+    fn tls_certs_of(&self, listeners: &[Listener]) -> Vec<TlsCert> {
+        let mut certs: Vec<TlsCert> = vec![];
+        for cert in listeners
+            .iter()
+            .filter_map(|listener| listener.tls.as_ref())
+        {
+            if !certs.iter().any(|c| c.same_files(cert)) {
+                certs.push(cert.clone());
+            }
+        }
+
+        certs
+    }
+
+    /// Certificates used by the TLS listeners, without duplicates.
+    pub fn tls_certs(&self) -> Vec<TlsCert> {
+        self.tls_certs_of(&self.listeners)
+    }
+
+    /// Current snapshot of the options that can be reloaded.
+    pub fn live(&self) -> Arc<LiveOptions> {
+        Arc::clone(&self.live.read().unwrap())
+    }
+
+    pub fn set_live(&self, live: LiveOptions) {
+        *self.live.write().unwrap() = Arc::new(live);
+    }
+
+    /// Read and validate the configuration again, and if it is fine swap in the new live options.
+    ///
+    /// An invalid configuration is returned as an error and the current options are kept. The rest of the options
+    /// (listener, TLS, directories, ...) are only applied on restart.
+    pub fn reload(&self) -> Result<()> {
+        let new_cfg = Config::read(self.config_file.as_deref())?;
+        new_cfg.check_access_policy()?;
+
+        let mut live = (*new_cfg.live()).clone();
+        // Without API_KEY we keep the random one generated when the agent started, not a new one nobody knows.
+        if new_cfg.generated_api_key {
+            live.api_key = self.live().api_key.clone();
+        }
+        // Listeners, client certificates and the insecure override of the file are only applied on restart, until
+        // then the new live options are protected by the running ones.
+        self.check_live_access_policy(&live)?;
+        self.set_live(live);
+
+        Ok(())
+    }
+
+    /// Bind the TCP listeners.
+    ///
+    /// The listeners are built again from the options, this way changes made after loading the configuration are
+    /// taken into account.
+    pub fn bind_to(&mut self) -> Vec<TcpListener> {
+        self.listeners = self.build_listeners();
+
+        let mut tcp_listeners = vec![];
+        for listener in self.listeners.iter_mut() {
+            let tcp_listener = TcpListener::bind(listener.address)
+                .unwrap_or_else(|_| panic!("Error binding to {}", listener.address));
+
+            // If we want listen to a random TCP port, update the listener with the random one supplied by the operative system.
+            if listener.address.port() == 0 {
+                listener.address = tcp_listener.local_addr().unwrap();
+            }
+            tcp_listeners.push(tcp_listener);
+        }
+        self.bind_port = self.listeners[0].address.port();
+
+        tcp_listeners
+    }
+}
+
+fn absolute_path(path: String) -> Result<String> {
+    Ok(std::path::absolute(&path)?.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use std::env;
+
+    fn config_factory(env_list: Vec<(&str, &str)>) -> Result<Config> {
+        for v in env_list.iter() {
+            env::set_var(v.0, v.1);
+        }
+
+        let cfg = Config::new();
 
-        // If we want listen to a random TCP port, update the bind_port with the random one supplied by the operative system.
-        if self.bind_port == 0 {
-            self.bind_port = listener.local_addr().unwrap().port();
+        for v in env_list.iter() {
+            env::remove_var(v.0);
         }
 
-        listener
+        cfg
+    }
+
+    #[test]
+    #[serial]
+    fn refuses_no_access_control_on_non_loopback_address() {
+        let cfg = config_factory(vec![("ENABLE_API_KEY", "false"), ("BIND_IP", "0.0.0.0")]);
+        assert!(matches!(cfg, Err(FwcError::InsecureAccessPolicy)));
+    }
+
+    #[test]
+    #[serial]
+    fn refuses_reload_without_access_control_until_restart() {
+        let mut cfg = config_factory(vec![("BIND_IP", "0.0.0.0")]).unwrap();
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("agent.toml");
+        cfg.config_file = Some(config_file.to_str().unwrap().to_string());
+
+        // The client certificates of the file are not enabled until restart, but the API key would be disabled now.
+        fs::write(
+            &config_file,
+            "[listener]\nbind_ip = \"0.0.0.0\"\n[tls]\nclient_cert = true\n[auth]\nenable_api_key = false\n",
+        )
+        .unwrap();
+        assert!(matches!(cfg.reload(), Err(FwcError::InsecureAccessPolicy)));
+        assert!(cfg.live().enable_api_key);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn allows_no_access_control_with_insecure_override() {
+        let cfg = config_factory(vec![
+            ("ENABLE_API_KEY", "false"),
+            ("BIND_IP", "0.0.0.0"),
+            ("ALLOW_INSECURE", "true"),
+        ]);
+        assert!(cfg.is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn allows_no_api_key_with_allowed_ips_or_loopback_address() {
+        let cfg = config_factory(vec![
+            ("ENABLE_API_KEY", "false"),
+            ("BIND_IP", "0.0.0.0"),
+            ("ALLOWED_IPS", "10.0.0.0/8"),
+        ]);
+        assert!(cfg.is_ok());
+
+        let cfg = config_factory(vec![("ENABLE_API_KEY", "false"), ("BIND_IP", "127.0.0.1")]);
+        assert!(cfg.is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn validates_tls_settings() {
+        let cfg = config_factory(vec![("TLS_PROFILE", "mozilla_old")]);
+        assert!(matches!(cfg, Err(FwcError::Validation(_))));
+
+        let cfg = config_factory(vec![("TLS_MIN_VERSION", "1.1")]);
+        assert!(matches!(cfg, Err(FwcError::Validation(_))));
+
+        let cfg = config_factory(vec![("TLS_CIPHER_LIST", "NOT-A-CIPHER")]);
+        assert!(matches!(cfg, Err(FwcError::TlsConfig(_))));
+
+        let cfg = config_factory(vec![("TLS_CIPHERSUITES", "TLS_NOT_A_SUITE")]);
+        assert!(matches!(cfg, Err(FwcError::TlsConfig(_))));
+
+        let cfg = config_factory(vec![
+            ("TLS_PROFILE", "mozilla_modern"),
+            ("TLS_MIN_VERSION", "1.3"),
+            (
+                "TLS_CIPHERSUITES",
+                "TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256",
+            ),
+            ("TLS_KEY_FILE", "/etc/ssl/private/fwcloud-agent.key"),
+        ])
+        .unwrap();
+        assert_eq!(cfg.tls_key_file, "/etc/ssl/private/fwcloud-agent.key");
+        assert_eq!(cfg.tls_cert_file, format!("{}/cert.pem", cfg.etc_dir));
+    }
+
+    #[test]
+    #[serial]
+    fn loads_config_file() {
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("agent.toml");
+        fs::write(
+            &config_file,
+            format!(
+                r#"
+                [paths]
+                data_dir = "{}/data"
+
+                [listener]
+                bind_ip = "127.0.0.1"
+                workers = 8
+
+                [auth]
+                allowed_ips = ["10.0.0.0/8"]
+                "#,
+                dir.display()
+            ),
+        )
+        .unwrap();
+
+        env::set_var("WORKERS", "3");
+        let cfg = Config::load(config_file.to_str()).unwrap();
+        env::remove_var("WORKERS");
+
+        assert_eq!(cfg.data_dir, format!("{}/data", dir.display()));
+        assert!(dir.join("data").is_dir());
+        assert_eq!(cfg.bind_ip, "127.0.0.1");
+        assert_eq!(cfg.workers, 3);
+        assert_eq!(
+            cfg.live().allowed_ips,
+            vec!["10.0.0.0/8".parse::<IpNet>().unwrap()]
+        );
+
+        // Reload the new options, an invalid file is ignored.
+        let live = cfg.live();
+        fs::write(
+            &config_file,
+            format!(
+                "[paths]\ndata_dir = \"{}/data\"\n[listener]\nbind_ip = \"127.0.0.1\"\n[auth]\nallowed_ips = [\"192.168.0.0/16\"]\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        cfg.reload().unwrap();
+        assert_eq!(
+            live.allowed_ips,
+            vec!["10.0.0.0/8".parse::<IpNet>().unwrap()]
+        );
+        assert_eq!(
+            cfg.live().allowed_ips,
+            vec!["192.168.0.0/16".parse::<IpNet>().unwrap()]
+        );
+        // Without API_KEY the random key generated at start is kept.
+        assert_eq!(cfg.live().api_key, live.api_key);
+
+        // The API key and the client names are reloaded too.
+        fs::write(
+            &config_file,
+            "[listener]\nbind_ip = \"127.0.0.1\"\n[tls]\nallowed_client_names = [\"console\"]\n[auth]\nenable_api_key = true\napi_key = \"f2f6ZCmrMPyrFEQ9kP7e\"\nallowed_ips = [\"192.168.0.0/16\"]\n",
+        )
+        .unwrap();
+        cfg.reload().unwrap();
+        assert_eq!(cfg.live().api_key, "f2f6ZCmrMPyrFEQ9kP7e");
+        assert_eq!(cfg.live().allowed_client_names, vec!["console"]);
+
+        fs::write(&config_file, "[listener]\nbind_port = 0\n").unwrap();
+        assert!(cfg.reload().is_err());
+        assert_eq!(
+            cfg.live().allowed_ips,
+            vec!["192.168.0.0/16".parse::<IpNet>().unwrap()]
+        );
+        assert!(matches!(
+            Config::load(config_file.to_str()),
+            Err(FwcError::Validation(_))
+        ));
+
+        assert!(matches!(
+            Config::load(Some("/nonexistent/agent.toml")),
+            Err(FwcError::ConfigFile(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,589 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::errors::{FwcError, Result};
+
+/// Configuration file used if no other one is indicated.
+pub const DEFAULT_CONFIG_FILE: &str = "/etc/fwcloud-agent/agent.toml";
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PathsSection {
+    etc_dir: Option<String>,
+    tmp_dir: Option<String>,
+    data_dir: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ListenerSection {
+    bind_ip: Option<String>,
+    bind_port: Option<u16>,
+    workers: Option<usize>,
+    unix_socket: Option<String>,
+    unix_socket_mode: Option<String>,
+    extra_listeners: Option<Vec<ExtraListener>>,
+}
+
+/// Additional TCP listener, the TLS settings not indicated are the ones of the main listener.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct ExtraListener {
+    address: String,
+    port: u16,
+    tls: Option<bool>,
+    key_file: Option<String>,
+    cert_file: Option<String>,
+}
+
+impl fmt::Display for ExtraListener {
+    // Same format as the EXTRA_LISTENERS environment variable.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.address.contains(':') {
+            write!(f, "[{}]:{}", self.address, self.port)?;
+        } else {
+            write!(f, "{}:{}", self.address, self.port)?;
+        }
+        if let Some(tls) = self.tls {
+            write!(f, ";tls={tls}")?;
+        }
+        if let Some(key_file) = &self.key_file {
+            write!(f, ";key_file={key_file}")?;
+        }
+        if let Some(cert_file) = &self.cert_file {
+            write!(f, ";cert_file={cert_file}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    enable: Option<bool>,
+    profile: Option<String>,
+    min_version: Option<String>,
+    cipher_list: Option<String>,
+    ciphersuites: Option<String>,
+    key_file: Option<String>,
+    cert_file: Option<String>,
+    reload_check_interval: Option<u64>,
+    client_cert: Option<bool>,
+    client_cert_crl: Option<bool>,
+    allowed_client_names: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSection {
+    enable_api_key: Option<bool>,
+    api_key: Option<String>,
+    allowed_ips: Option<Vec<String>>,
+    allow_insecure: Option<bool>,
+    require_signed_requests: Option<bool>,
+    signature_max_clock_skew: Option<u64>,
+    signature_nonce_cache_size: Option<usize>,
+    require_signed_scripts: Option<bool>,
+    script_signing_key: Option<String>,
+    max_failures: Option<u32>,
+    ban_time: Option<u64>,
+    max_ban_time: Option<u64>,
+    failures_window: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AuditSection {
+    enable: Option<bool>,
+    max_size: Option<u64>,
+    max_files: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DaemonSection {
+    config_rollback_timeout: Option<u64>,
+    status_socket: Option<String>,
+    drift_check_interval: Option<u64>,
+    job_history_size: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TimeoutsSection {
+    plugin: Option<u64>,
+    fwcloud_script: Option<u64>,
+    systemctl: Option<u64>,
+    command: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct OpenVPNSection {
+    status_files: Option<Vec<String>>,
+    status_sampling_interval: Option<u64>,
+    status_request_max_lines: Option<usize>,
+    status_cache_max_size: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PluginsSection {
+    dir: Option<String>,
+    fwcloud_script_paths: Option<Vec<String>>,
+    script_history_size: Option<usize>,
+}
+
+/// TOML configuration file. Every option has an equivalent environment variable.
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    paths: PathsSection,
+    listener: ListenerSection,
+    tls: TlsSection,
+    auth: AuthSection,
+    audit: AuditSection,
+    daemon: DaemonSection,
+    timeouts: TimeoutsSection,
+    openvpn: OpenVPNSection,
+    plugins: PluginsSection,
+}
+
+/// Effective configuration in the configuration file format, with the API key redacted.
+pub fn effective_config(cfg: &Config) -> Result<String> {
+    let live = cfg.live();
+    let file = ConfigFile {
+        paths: PathsSection {
+            etc_dir: Some(cfg.etc_dir.clone()),
+            tmp_dir: Some(cfg.tmp_dir.clone()),
+            data_dir: Some(cfg.data_dir.clone()),
+        },
+        listener: ListenerSection {
+            bind_ip: Some(cfg.bind_ip.clone()),
+            bind_port: Some(cfg.bind_port),
+            workers: Some(cfg.workers),
+            unix_socket: Some(cfg.unix_socket.clone()),
+            unix_socket_mode: Some(cfg.unix_socket_mode.clone()),
+            extra_listeners: Some(
+                cfg.listeners
+                    .iter()
+                    .skip(1)
+                    .map(|listener| ExtraListener {
+                        address: listener.address.ip().to_string(),
+                        port: listener.address.port(),
+                        tls: Some(listener.tls.is_some()),
+                        key_file: listener.tls.as_ref().map(|cert| cert.key_file.clone()),
+                        cert_file: listener.tls.as_ref().map(|cert| cert.cert_file.clone()),
+                    })
+                    .collect(),
+            ),
+        },
+        tls: TlsSection {
+            enable: Some(cfg.enable_tls),
+            profile: Some(cfg.tls_profile.clone()),
+            min_version: Some(cfg.tls_min_version.clone()),
+            cipher_list: Some(cfg.tls_cipher_list.clone()),
+            ciphersuites: Some(cfg.tls_ciphersuites.clone()),
+            key_file: Some(cfg.tls_key_file.clone()),
+            cert_file: Some(cfg.tls_cert_file.clone()),
+            reload_check_interval: Some(cfg.tls_reload_check_interval),
+            client_cert: Some(cfg.enable_client_cert),
+            client_cert_crl: Some(cfg.client_cert_crl),
+            allowed_client_names: Some(live.allowed_client_names.clone()),
+        },
+        auth: AuthSection {
+            enable_api_key: Some(live.enable_api_key),
+            api_key: Some(String::from("<redacted>")),
+            allowed_ips: Some(live.allowed_ips.iter().map(|ip| ip.to_string()).collect()),
+            allow_insecure: Some(cfg.allow_insecure),
+            require_signed_requests: Some(cfg.require_signed_requests),
+            signature_max_clock_skew: Some(cfg.signature_max_clock_skew),
+            signature_nonce_cache_size: Some(cfg.signature_nonce_cache_size),
+            require_signed_scripts: Some(cfg.require_signed_scripts),
+            script_signing_key: Some(cfg.script_signing_key.clone()),
+            max_failures: Some(cfg.auth_max_failures),
+            ban_time: Some(cfg.auth_ban_time),
+            max_ban_time: Some(cfg.auth_max_ban_time),
+            failures_window: Some(cfg.auth_failures_window),
+        },
+        audit: AuditSection {
+            enable: Some(cfg.enable_audit_log),
+            max_size: Some(cfg.audit_log_max_size),
+            max_files: Some(cfg.audit_log_max_files),
+        },
+        daemon: DaemonSection {
+            config_rollback_timeout: Some(cfg.config_rollback_timeout),
+            status_socket: Some(cfg.status_socket.clone()),
+            drift_check_interval: Some(cfg.drift_check_interval),
+            job_history_size: Some(cfg.job_history_size),
+        },
+        timeouts: TimeoutsSection {
+            plugin: Some(cfg.plugin_timeout),
+            fwcloud_script: Some(cfg.fwcloud_script_timeout),
+            systemctl: Some(cfg.systemctl_timeout),
+            command: Some(cfg.cmd_timeout),
+        },
+        openvpn: OpenVPNSection {
+            status_files: Some(live.openvpn_status_files.clone()),
+            status_sampling_interval: Some(cfg.openvpn_status_sampling_interval),
+            status_request_max_lines: Some(cfg.openvpn_status_request_max_lines),
+            status_cache_max_size: Some(cfg.openvpn_status_cache_max_size),
+        },
+        plugins: PluginsSection {
+            dir: Some(cfg.plugins_dir.clone()),
+            fwcloud_script_paths: Some(live.fwcloud_script_paths.clone()),
+            script_history_size: Some(cfg.script_history_size),
+        },
+    };
+
+    let mut res = toml::to_string(&file).map_err(|e| FwcError::ConfigFile(e.to_string()))?;
+    if !live.api_keys.is_empty() {
+        res.push_str("\n# Named API keys (etc_dir/api_keys.json):\n");
+        for key in live.api_keys.iter() {
+            res.push_str(&format!("#   {}: {}\n", key.name, key.scopes.join(", ")));
+        }
+    }
+
+    Ok(res)
+}
+
+// Insert an option of the configuration file with the name of its environment variable.
+macro_rules! setting {
+    ($map:ident, $name:literal, $value:expr) => {
+        if let Some(value) = &$value {
+            $map.insert($name, value.to_string());
+        }
+    };
+    ($map:ident, $name:literal, $value:expr, $sep:literal) => {
+        if let Some(value) = &$value {
+            $map.insert($name, value.join($sep));
+        }
+    };
+}
+
+impl ConfigFile {
+    fn settings(&self) -> HashMap<&'static str, String> {
+        let mut map = HashMap::new();
+
+        setting!(map, "ETC_DIR", self.paths.etc_dir);
+        setting!(map, "TMP_DIR", self.paths.tmp_dir);
+        setting!(map, "DATA_DIR", self.paths.data_dir);
+
+        setting!(map, "BIND_IP", self.listener.bind_ip);
+        setting!(map, "BIND_PORT", self.listener.bind_port);
+        setting!(map, "WORKERS", self.listener.workers);
+        setting!(map, "UNIX_SOCKET", self.listener.unix_socket);
+        setting!(map, "UNIX_SOCKET_MODE", self.listener.unix_socket_mode);
+        if let Some(listeners) = &self.listener.extra_listeners {
+            let listeners: Vec<String> = listeners.iter().map(|l| l.to_string()).collect();
+            map.insert("EXTRA_LISTENERS", listeners.join(" "));
+        }
+
+        setting!(map, "ENABLE_SSL", self.tls.enable);
+        setting!(map, "TLS_PROFILE", self.tls.profile);
+        setting!(map, "TLS_MIN_VERSION", self.tls.min_version);
+        setting!(map, "TLS_CIPHER_LIST", self.tls.cipher_list);
+        setting!(map, "TLS_CIPHERSUITES", self.tls.ciphersuites);
+        setting!(map, "TLS_KEY_FILE", self.tls.key_file);
+        setting!(map, "TLS_CERT_FILE", self.tls.cert_file);
+        setting!(
+            map,
+            "TLS_RELOAD_CHECK_INTERVAL",
+            self.tls.reload_check_interval
+        );
+        setting!(map, "ENABLE_CLIENT_CERT", self.tls.client_cert);
+        setting!(map, "CLIENT_CERT_CRL", self.tls.client_cert_crl);
+        setting!(
+            map,
+            "ALLOWED_CLIENT_NAMES",
+            self.tls.allowed_client_names,
+            " "
+        );
+
+        setting!(map, "ENABLE_API_KEY", self.auth.enable_api_key);
+        setting!(map, "API_KEY", self.auth.api_key);
+        setting!(map, "ALLOWED_IPS", self.auth.allowed_ips, " ");
+        setting!(map, "ALLOW_INSECURE", self.auth.allow_insecure);
+        setting!(
+            map,
+            "REQUIRE_SIGNED_REQUESTS",
+            self.auth.require_signed_requests
+        );
+        setting!(
+            map,
+            "SIGNATURE_MAX_CLOCK_SKEW",
+            self.auth.signature_max_clock_skew
+        );
+        setting!(
+            map,
+            "SIGNATURE_NONCE_CACHE_SIZE",
+            self.auth.signature_nonce_cache_size
+        );
+        setting!(
+            map,
+            "REQUIRE_SIGNED_SCRIPTS",
+            self.auth.require_signed_scripts
+        );
+        setting!(map, "SCRIPT_SIGNING_KEY", self.auth.script_signing_key);
+        setting!(map, "AUTH_MAX_FAILURES", self.auth.max_failures);
+        setting!(map, "AUTH_BAN_TIME", self.auth.ban_time);
+        setting!(map, "AUTH_MAX_BAN_TIME", self.auth.max_ban_time);
+        setting!(map, "AUTH_FAILURES_WINDOW", self.auth.failures_window);
+
+        setting!(map, "AUDIT_LOG", self.audit.enable);
+        setting!(map, "AUDIT_LOG_MAX_SIZE", self.audit.max_size);
+        setting!(map, "AUDIT_LOG_MAX_FILES", self.audit.max_files);
+
+        setting!(
+            map,
+            "CONFIG_ROLLBACK_TIMEOUT",
+            self.daemon.config_rollback_timeout
+        );
+        setting!(map, "STATUS_SOCKET", self.daemon.status_socket);
+        setting!(
+            map,
+            "DRIFT_CHECK_INTERVAL",
+            self.daemon.drift_check_interval
+        );
+        setting!(map, "JOB_HISTORY_SIZE", self.daemon.job_history_size);
+
+        setting!(map, "PLUGIN_TIMEOUT", self.timeouts.plugin);
+        setting!(map, "FWCLOUD_SCRIPT_TIMEOUT", self.timeouts.fwcloud_script);
+        setting!(map, "SYSTEMCTL_TIMEOUT", self.timeouts.systemctl);
+        setting!(map, "CMD_TIMEOUT", self.timeouts.command);
+
+        setting!(map, "OPENVPN_STATUS_FILES", self.openvpn.status_files, ",");
+        setting!(
+            map,
+            "OPENVPN_STATUS_SAMPLING_INTERVAL",
+            self.openvpn.status_sampling_interval
+        );
+        setting!(
+            map,
+            "OPENVPN_STATUS_REQUEST_MAX_LINES",
+            self.openvpn.status_request_max_lines
+        );
+        setting!(
+            map,
+            "OPENVPN_STATUS_CACHE_MAX_SIZE",
+            self.openvpn.status_cache_max_size
+        );
+
+        setting!(map, "PLUGINS_DIR", self.plugins.dir);
+        setting!(
+            map,
+            "FWCLOUD_SCRIPT_PATHS",
+            self.plugins.fwcloud_script_paths,
+            ","
+        );
+        setting!(map, "SCRIPT_HISTORY_SIZE", self.plugins.script_history_size);
+
+        map
+    }
+}
+
+/// Configuration options from the environment variables and the configuration file.
+///
+/// Environment variables (including the ones of the `.env` file) take precedence over the
+/// configuration file options.
+pub struct Settings {
+    dotenv: HashMap<String, String>,
+    file: HashMap<&'static str, String>,
+}
+
+impl Settings {
+    /// Load the configuration file. If no file is indicated the default one is used, if it exists.
+    pub fn load(config_file: Option<&str>) -> Result<Self> {
+        let path = match config_file {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => DEFAULT_CONFIG_FILE,
+            None => {
+                return Ok(Settings {
+                    dotenv: dotenv(),
+                    file: HashMap::new(),
+                })
+            }
+        };
+
+        let mut settings = Settings::parse(&fs::read_to_string(path).map_err(|e| {
+            FwcError::ConfigFile(format!("Error reading configuration file '{path}' ({e})"))
+        })?)
+        .map_err(|e| FwcError::ConfigFile(format!("{path}: {e}")))?;
+        settings.dotenv = dotenv();
+
+        Ok(settings)
+    }
+
+    /// Settings with the given configuration file document instead of the one on disk.
+    pub fn with_file(data: &str) -> Result<Self> {
+        let mut settings =
+            Settings::parse(data).map_err(|e| FwcError::ConfigFile(e.to_string()))?;
+        settings.dotenv = dotenv();
+
+        Ok(settings)
+    }
+
+    /// Settings with the given `.env` document instead of the one on disk.
+    pub fn with_dotenv(config_file: Option<&str>, data: &str) -> Result<Self> {
+        let mut settings = Settings::load(config_file)?;
+        settings.dotenv = dotenvy::from_read_iter(data.as_bytes())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| FwcError::ConfigFile(format!(".env: {e}")))?;
+
+        Ok(settings)
+    }
+
+    fn parse(data: &str) -> std::result::Result<Self, toml::de::Error> {
+        let file: ConfigFile = toml::from_str(data)?;
+
+        Ok(Settings {
+            dotenv: HashMap::new(),
+            file: file.settings(),
+        })
+    }
+
+    pub fn get(&self, name: &str, default: &str) -> String {
+        self.get_opt(name).unwrap_or_else(|| String::from(default))
+    }
+
+    /// Environment variables take precedence over the `.env` file and this one over the configuration file.
+    pub fn get_opt(&self, name: &str) -> Option<String> {
+        env::var(name)
+            .ok()
+            .or_else(|| self.dotenv.get(name).cloned())
+            .or_else(|| self.file.get(name).cloned())
+    }
+}
+
+/// Path of the `.env` file: the first one found from the current directory up (as dotenvy looks for it), or the
+/// one of the current directory if there is none.
+pub fn dotenv_path() -> Result<PathBuf> {
+    let cwd = env::current_dir()?;
+
+    Ok(cwd
+        .ancestors()
+        .map(|dir| dir.join(".env"))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| cwd.join(".env")))
+}
+
+/// Variables of the `.env` file, if any.
+///
+/// They are not exported to the process environment, this way a configuration reload sees the changes in the file.
+fn dotenv() -> HashMap<String, String> {
+    dotenv_path()
+        .ok()
+        .filter(|path| path.is_file())
+        .and_then(|path| dotenvy::from_path_iter(path).ok())
+        .map(|iter| iter.filter_map(|item| item.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn maps_file_options_to_settings() {
+        let settings = Settings::parse(
+            r#"
+            [paths]
+            etc_dir = "/etc/fwcloud-agent"
+
+            [listener]
+            bind_port = 33034
+            extra_listeners = [
+                { address = "::", port = 33033 },
+                { address = "10.0.0.1", port = 33035, tls = true, cert_file = "/etc/c.pem" },
+            ]
+
+            [auth]
+            allowed_ips = ["10.0.0.0/8", "2001:db8::/64"]
+            max_failures = 3
+
+            [openvpn]
+            status_files = ["/etc/openvpn/a.log", "/etc/openvpn/b.log"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.get("ETC_DIR", "./etc"), "/etc/fwcloud-agent");
+        assert_eq!(settings.get("BIND_PORT", "33033"), "33034");
+        assert_eq!(
+            settings.get("EXTRA_LISTENERS", ""),
+            "[::]:33033 10.0.0.1:33035;tls=true;cert_file=/etc/c.pem"
+        );
+        assert_eq!(settings.get("ALLOWED_IPS", ""), "10.0.0.0/8 2001:db8::/64");
+        assert_eq!(settings.get("AUTH_MAX_FAILURES", "5"), "3");
+        assert_eq!(
+            settings.get("OPENVPN_STATUS_FILES", ""),
+            "/etc/openvpn/a.log,/etc/openvpn/b.log"
+        );
+        assert_eq!(settings.get("WORKERS", "5"), "5");
+        assert_eq!(settings.get_opt("API_KEY"), None);
+    }
+
+    #[test]
+    #[serial]
+    fn env_vars_override_file_options() {
+        let settings = Settings::parse("[listener]\nworkers = 8\nbind_port = 33034\n").unwrap();
+
+        env::set_var("WORKERS", "3");
+        assert_eq!(settings.get("WORKERS", "5"), "3");
+        assert_eq!(settings.get("BIND_PORT", "33033"), "33034");
+        env::remove_var("WORKERS");
+    }
+
+    #[test]
+    #[serial]
+    fn effective_config_is_a_valid_config_file() {
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        env::set_var("ALLOWED_IPS", "10.0.0.0/8 ::1");
+        let cfg = Config::new();
+        env::remove_var("ALLOWED_IPS");
+        env::remove_var("API_KEY");
+        let effective = effective_config(&cfg.unwrap()).unwrap();
+
+        assert!(!effective.contains("d64c88318c8f213f427af857d0013f93"));
+        let settings = Settings::parse(&effective).unwrap();
+        assert_eq!(settings.file["ALLOWED_IPS"], "10.0.0.0/8 ::1/128");
+        assert_eq!(settings.file["API_KEY"], "<redacted>");
+    }
+
+    #[test]
+    fn rejects_unknown_and_bad_options() {
+        assert!(Settings::parse("[listener]\nbind_address = \"0.0.0.0\"\n").is_err());
+        assert!(Settings::parse("[tls]\nenable = \"yes\"\n").is_err());
+        assert!(Settings::parse("[firewall]\n").is_err());
+    }
+}
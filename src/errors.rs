@@ -43,6 +43,36 @@ pub enum FwcError {
     #[error("Authorization error, access from your IP is not allowed")]
     NotAllowedIP,
 
+    #[error("Both API key and allowed IPs list are disabled while listening on a non-loopback address (set ALLOW_INSECURE=true to allow it)")]
+    InsecureAccessPolicy,
+
+    #[error("Authorization error, API key not allowed for this operation")]
+    ApiKeyScope,
+
+    #[error("Signed request required")]
+    SignatureRequired,
+
+    #[error("Invalid request signature")]
+    SignatureNotValid,
+
+    #[error("Request timestamp out of the allowed time window")]
+    SignatureExpired,
+
+    #[error("Request nonce already used")]
+    SignatureReplayed,
+
+    #[error("Too big body for a signed request")]
+    SignedBodyTooBig,
+
+    #[error("Too many authentication failures, try again later")]
+    TooManyAuthFailures,
+
+    #[error("Client certificate not found")]
+    ClientCertNotFound,
+
+    #[error("Authorization error, client certificate not allowed")]
+    ClientCertNotAllowed,
+
     #[error("Not allowed parameter in request")]
     NotAllowedParameter,
 
@@ -85,9 +115,75 @@ pub enum FwcError {
     #[error("Command exit status not 0")]
     CmdExitStatusNotZero,
 
+    #[error("Command timed out after {0} seconds")]
+    CmdTimeout(u64),
+
     #[error("{0}")]
     Internal(&'static str),
 
+    #[error("Configuration file error: {0}")]
+    ConfigFile(String),
+
+    #[error("TLS configuration error: {0}")]
+    TlsConfig(String),
+
+    #[error("Configuration not valid: {0}")]
+    ConfigNotValid(String),
+
+    #[error("There is already a configuration change pending of confirmation")]
+    ConfigChangePending,
+
+    #[error("No configuration change pending of confirmation")]
+    NoConfigChangePending,
+
+    #[error("There is already a policy apply pending of confirmation")]
+    PolicyApplyPending,
+
+    #[error("No policy apply pending of confirmation")]
+    NoPolicyApplyPending,
+
+    #[error("Error restoring the ruleset: {0}")]
+    RulesetNotRestored(String),
+
+    #[error("FWCloud script not found in the history")]
+    ScriptNotFound,
+
+    #[error("Signed FWCloud script required")]
+    ScriptSignatureRequired,
+
+    #[error("Invalid FWCloud script signature")]
+    ScriptSignatureNotValid,
+
+    #[error("FWCloud script signature expired")]
+    ScriptSignatureExpired,
+
+    #[error("FWCloud script signed for another path")]
+    ScriptSignaturePath,
+
+    #[error("FWCloud script path not allowed")]
+    ScriptPathNotAllowed,
+
+    #[error("FWCloud script can not be writable by group or others")]
+    ScriptPermsNotAllowed,
+
+    #[error("FWCloud script signing key error: {0}")]
+    ScriptSigningKey(String),
+
+    #[error("Job not found")]
+    JobNotFound,
+
+    #[error("Job already finished")]
+    JobFinished,
+
+    #[error("Job cancelled")]
+    JobCancelled,
+
+    #[error("Agent not running or status socket not available: {0}")]
+    AgentNotRunning(String),
+
+    #[error("Error parsing iptables-save output, {0}")]
+    IptablesSaveParse(String),
+
     #[error(transparent)]
     Validation(#[from] validator::ValidationErrors),
 
@@ -97,16 +193,30 @@ pub enum FwcError {
     #[error(transparent)]
     BlockingError(#[from] actix_web::error::BlockingError),
 
-    #[error(transparent)]
-    ActixWebError(#[from] actix_web::Error),
+    // Only the message, actix_web::Error can't be sent between threads and the errors of the
+    // commands run in the blocking thread pool must be.
+    #[error("{0}")]
+    ActixWebError(String),
 
     #[error(transparent)]
     PopenError(#[from] subprocess::PopenError),
 
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    OpenSSLError(#[from] openssl::error::ErrorStack),
+
     #[error(transparent)]
     SendError(#[from] std::sync::mpsc::SendError<u8>),
 }
 
+impl From<actix_web::Error> for FwcError {
+    fn from(e: actix_web::Error) -> Self {
+        FwcError::ActixWebError(e.to_string())
+    }
+}
+
 impl ResponseError for FwcError {
     fn status_code(&self) -> StatusCode {
         match self {
@@ -118,10 +228,33 @@ impl ResponseError for FwcError {
             | FwcError::LessFilesThanExpected
             | FwcError::MoreFilesThanExpected
             | FwcError::NotExpectedFileName
+            | FwcError::SignedBodyTooBig
+            | FwcError::ConfigNotValid(_)
+            | FwcError::ScriptPermsNotAllowed
             | FwcError::DstDirFirst => StatusCode::BAD_REQUEST,
-            FwcError::ApiKeyNotValid | FwcError::ApiKeyNotFound | &FwcError::NotAllowedIP => {
-                StatusCode::FORBIDDEN
-            }
+            FwcError::ApiKeyNotValid
+            | FwcError::ApiKeyNotFound
+            | FwcError::ApiKeyScope
+            | FwcError::SignatureRequired
+            | FwcError::SignatureNotValid
+            | FwcError::SignatureExpired
+            | FwcError::SignatureReplayed
+            | FwcError::NotAllowedIP
+            | FwcError::ClientCertNotFound
+            | FwcError::ClientCertNotAllowed
+            | FwcError::ScriptSignatureRequired
+            | FwcError::ScriptSignatureNotValid
+            | FwcError::ScriptSignatureExpired
+            | FwcError::ScriptSignaturePath
+            | FwcError::ScriptPathNotAllowed => StatusCode::FORBIDDEN,
+            FwcError::TooManyAuthFailures => StatusCode::TOO_MANY_REQUESTS,
+            FwcError::CmdTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            FwcError::ConfigChangePending
+            | FwcError::NoConfigChangePending
+            | FwcError::PolicyApplyPending
+            | FwcError::NoPolicyApplyPending
+            | FwcError::JobFinished => StatusCode::CONFLICT,
+            FwcError::ScriptNotFound | FwcError::JobNotFound => StatusCode::NOT_FOUND,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
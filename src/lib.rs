@@ -23,24 +23,35 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod audit;
 mod auth;
+pub mod cli;
 pub mod config;
+mod config_file;
 mod errors;
 pub mod routes;
+mod status;
+mod tls;
 mod utils;
 mod workers;
 
 use actix_web::{dev::Server, middleware, web, App, HttpServer};
 use env_logger::Env;
-use log::{info, warn};
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use log::{error, info, warn};
+use std::fs::{self, DirBuilder, Permissions};
 use std::net::TcpListener;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
 use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
 
-use crate::workers::{openvpn_status_collector::OpenVPNStCollector, WorkersChannels};
+use crate::workers::{
+    drift_detector::DriftDetector, openvpn_status_collector::OpenVPNStCollector, WorkersChannels,
+};
 use config::Config;
 
-pub fn run(config: Config, listener: TcpListener) -> Result<Server, std::io::Error> {
+pub fn run(config: Config, listeners: Vec<TcpListener>) -> Result<Server, std::io::Error> {
     if config.enable_env_logger {
         env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     }
@@ -49,42 +60,117 @@ pub fn run(config: Config, listener: TcpListener) -> Result<Server, std::io::Err
         "Starting fwcloud-agent application (version: {})",
         env!("CARGO_PKG_VERSION")
     );
-    info!("Listening on: {}:{}", config.bind_ip, config.bind_port);
 
     let cfg = Arc::new(config);
     let cfg_main_thread = cfg.clone();
+    status::start_status_socket(cfg.clone());
+    utils::jobs::recover(&cfg);
+    utils::config_update::recover(&cfg);
+    utils::policy_apply::recover(&cfg);
 
     // Start workers threads.
+    DriftDetector::new(&cfg).start(cfg.clone());
     let workers_channels = WorkersChannels {
         openvpn_st_collector: OpenVPNStCollector::new(&cfg).start(cfg.clone()),
     };
 
-    let server = HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(cfg.clone()))
             .app_data(web::Data::new(workers_channels.clone()))
             .wrap(middleware::Logger::default())
             .wrap(auth::Authorize)
+            .wrap(audit::Audit)
             .configure(routes::routes_setup)
     })
+    .on_connect(tls::on_connect)
     .workers(cfg_main_thread.workers);
 
-    if cfg_main_thread.enable_tls {
-        info!("Using secure communications (https)");
-        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        builder
-            .set_private_key_file(
-                format!("{}/key.pem", cfg_main_thread.etc_dir),
-                SslFiletype::PEM,
-            )
-            .unwrap();
-        builder
-            .set_certificate_chain_file(format!("{}/cert.pem", cfg_main_thread.etc_dir))
-            .unwrap();
-
-        Ok(server.listen_openssl(listener, builder)?.run())
-    } else {
-        warn!("Insecure communications (http) not recommended in production");
-        Ok(server.listen(listener)?.run())
+    if cfg_main_thread.enable_client_cert {
+        info!("Client certificate authentication enabled");
+        if cfg_main_thread.client_cert_crl {
+            info!("Client certificates revocation list check enabled");
+        }
     }
+
+    for (listener, tcp_listener) in cfg_main_thread.listeners.iter().zip(listeners) {
+        server = match &listener.tls {
+            Some(cert) => {
+                info!("Listening on: {} (https)", listener.address);
+                let builder = tls::ssl_acceptor(&cfg_main_thread, cert)
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+                server.listen_openssl(tcp_listener, builder)?
+            }
+            None => {
+                info!("Listening on: {} (http)", listener.address);
+                if !listener.address.ip().is_loopback() {
+                    warn!("Insecure communications (http) not recommended in production");
+                }
+                server.listen(tcp_listener)?
+            }
+        };
+    }
+
+    if !cfg_main_thread.unix_socket.is_empty() {
+        info!(
+            "Listening on: {} (unix socket)",
+            cfg_main_thread.unix_socket
+        );
+        server = server.listen_uds(bind_unix_socket(&cfg_main_thread)?)?;
+    }
+
+    tls::start_reloader(cfg_main_thread.clone());
+    start_sighup_handler(cfg_main_thread);
+
+    Ok(server.run())
+}
+
+// The socket file is created again, with the configured permissions. It is bound inside a private directory next to
+// its path and moved there once it has them, so it is never reachable with the default permissions.
+fn bind_unix_socket(cfg: &Config) -> Result<UnixListener, std::io::Error> {
+    let path = Path::new(&cfg.unix_socket);
+    let tmp_dir = path.with_file_name(format!(".fwcloud-agent-{}", uuid::Uuid::new_v4()));
+    DirBuilder::new().mode(0o700).create(&tmp_dir)?;
+
+    let tmp_path = tmp_dir.join("socket");
+    let res = UnixListener::bind(&tmp_path).and_then(|listener| {
+        let mode = u32::from_str_radix(&cfg.unix_socket_mode, 8).unwrap_or(0o600);
+        fs::set_permissions(&tmp_path, Permissions::from_mode(mode))?;
+        fs::rename(&tmp_path, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&tmp_path);
+    fs::remove_dir(&tmp_dir)?;
+
+    res
+}
+
+/// Reload the configuration and the TLS certificate on SIGHUP.
+///
+/// The HTTP listener and the active WebSocket sessions are not affected, and if the new configuration is not valid
+/// the current one is kept.
+fn start_sighup_handler(cfg: Arc<Config>) {
+    tokio::spawn(async move {
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(e) => {
+                error!("Error installing the SIGHUP handler: {e}");
+                return;
+            }
+        };
+
+        while sighup.recv().await.is_some() {
+            info!("SIGHUP received, reloading configuration");
+            match cfg.reload() {
+                Ok(_) => info!("Configuration reloaded"),
+                Err(e) => error!("Error reloading configuration, keeping the current one: {e}"),
+            }
+
+            for cert in cfg.tls_certs().iter() {
+                if let Err(e) = tls::reload(&cfg, cert) {
+                    error!("Error reloading TLS certificate, keeping the current one: {e}");
+                }
+            }
+        }
+    });
 }
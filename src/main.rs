@@ -20,13 +20,103 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use clap::{Parser, Subcommand};
+use std::process::ExitCode;
+
 use fwcloud_agent::config::Config;
-use fwcloud_agent::run;
+use fwcloud_agent::{cli, run};
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "FWCloud Agent daemon for remote firewalls management"
+)]
+struct Cli {
+    /// Configuration file (/etc/fwcloud-agent/agent.toml by default, if it exists)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the agent (default if no command is given)
+    Serve,
+    /// Validate the configuration and print the effective one with the secrets redacted
+    CheckConfig,
+    /// Print a new random API key
+    GenApiKey,
+    /// Generate a self-signed TLS certificate in the configured key and certificate files
+    GenCert {
+        /// Certificate common name and subject alternative name (host name by default)
+        #[arg(long)]
+        host_name: Option<String>,
+        /// Replace the existing key and certificate files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the agent version
+    Version {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the status of the running agent, queried through its local status socket
+    Status {
+        /// Status socket (the configured one by default)
+        #[arg(long, value_name = "FILE")]
+        socket: Option<String>,
+    },
+}
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let mut config = Config::new().unwrap();
-    let listener = config.bind_to();
+async fn main() -> ExitCode {
+    let args = Cli::parse();
+    let config_file = args.config.as_deref();
+
+    let res = match args.command.unwrap_or(Command::Serve) {
+        Command::Serve => return serve(config_file).await,
+        Command::CheckConfig => cli::check_config(config_file),
+        Command::GenApiKey => Ok(cli::gen_api_key()),
+        Command::GenCert { host_name, force } => cli::gen_cert(config_file, host_name, force),
+        Command::Version { json } => cli::version(json),
+        Command::Status { socket } => cli::status(config_file, socket).await,
+    };
+
+    match res {
+        Ok(output) => {
+            println!("{}", output.trim_end());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn serve(config_file: Option<&str>) -> ExitCode {
+    let mut config = match Config::load(config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let listeners = config.bind_to();
 
-    run(config, listener)?.await
+    match run(config, listeners) {
+        Ok(server) => match server.await {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                ExitCode::FAILURE
+            }
+        },
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
 }
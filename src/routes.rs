@@ -20,12 +20,17 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+mod audit;
+mod auth;
 mod daemon;
+mod drift;
 mod fwcloud_script;
 mod info;
 mod interfaces;
 mod ipsec;
 mod iptables_save;
+mod jobs;
+mod nftables;
 mod openvpn;
 mod ping;
 pub mod plugin;
@@ -40,8 +45,19 @@ pub fn routes_setup(config: &mut web::ServiceConfig) {
         web::scope("/api/v1")
             .service(ping::ping)
             .service(info::info)
+            // Authentication.
+            .service(auth::bans)
+            // Audit log.
+            .service(audit::audit)
             // FWCloud script.
             .service(fwcloud_script::upload_and_run)
+            .service(fwcloud_script::validate)
+            .service(fwcloud_script::confirm)
+            .service(fwcloud_script::pending)
+            .service(fwcloud_script::history)
+            .service(fwcloud_script::history_diff)
+            .service(fwcloud_script::history_script)
+            .service(fwcloud_script::history_install)
             // OpenVPN.
             .service(openvpn::files_upload)
             .service(openvpn::files_remove)
@@ -59,12 +75,23 @@ pub fn routes_setup(config: &mut web::ServiceConfig) {
             .service(interfaces::info)
             // IPTables save.
             .service(iptables_save::data)
+            .service(iptables_save::backends)
+            // nftables.
+            .service(nftables::ruleset)
             // Plugins.
             .service(plugin::plugin)
             // Systemctl.
             .service(systemctl::systemctl)
             // Daemon.
             .service(daemon::config_upload)
+            .service(daemon::config_confirm)
+            // Ruleset drift.
+            .service(drift::get_status)
+            .service(drift::check)
+            // Jobs.
+            .service(jobs::list)
+            .service(jobs::get_job)
+            .service(jobs::cancel)
             // WebSocket.
             .service(ws::websocket)
             .service(ws::websocket_test),
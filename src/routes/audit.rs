@@ -0,0 +1,70 @@
+/*
+    Copyright 2023 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use log::debug;
+use serde::Deserialize;
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::config::Config;
+use crate::errors::Result;
+
+#[derive(Deserialize, Validate)]
+struct AuditQuery {
+    #[serde(default)]
+    offset: usize,
+
+    #[serde(default = "default_limit")]
+    #[validate(range(min = 1, max = 1000))]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+/*
+  Entries of the audit log from the newest to the oldest one, with more=true if there are older
+  entries after the page.
+
+  curl -v -k -i -X GET -H 'X-API-Key: **************************' \
+    'https://localhost:33033/api/v1/audit?offset=0&limit=100'
+*/
+#[get("/audit")]
+async fn audit(query: web::Query<AuditQuery>, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    query.validate()?; // Validate input.
+
+    let page;
+    {
+        debug!("Locking audit log mutex (thread id: {})", thread_id::get());
+        let audit_log = cfg.audit_log.lock().unwrap();
+        page = audit_log.page(query.offset, query.limit, cfg.audit_log_max_files)?;
+        debug!(
+            "Releasing audit log mutex (thread id: {})",
+            thread_id::get()
+        );
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(serde_json::to_string(&page)?))
+}
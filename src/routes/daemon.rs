@@ -20,16 +20,31 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 use actix_multipart::Multipart;
-use actix_web::{post, web, HttpResponse};
+use actix_web::{post, web, HttpRequest, HttpResponse};
 use log::debug;
 use std::sync::Arc;
 
 use crate::config::Config;
 use crate::errors::Result;
+use crate::utils::config_update::{self, ConfigUpdate};
 use crate::utils::http_files::HttpFiles;
 
+/*
+  The uploaded file must be named .env or agent.toml, it is validated, installed and applied. If the
+  change is not confirmed in CONFIG_ROLLBACK_TIMEOUT seconds (using the new settings) it is rolled back.
+
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    -F "file=@agent.toml" \
+    https://localhost:33033/api/v1/daemon/config/upload
+*/
 #[post("/daemon/config/upload")]
-async fn config_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+async fn config_upload(
+    req: HttpRequest,
+    payload: Multipart,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    let update: ConfigUpdate;
+
     // Mutex scope start.
     {
         debug!("Locking daemon mutex (thread id: {})", thread_id::get());
@@ -37,12 +52,27 @@ async fn config_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Resul
         let _mutex_data = mutex.lock().await;
         debug!("Daemon mutex locked (thread id: {})", thread_id::get());
 
-        HttpFiles::new(cfg.tmp_dir, true)
-            .files_upload(payload)
+        let (name, data) = HttpFiles::new(&cfg.tmp_dir, false)
+            .audit(&req)
+            .config_document(payload)
             .await?;
+        update = config_update::apply(&cfg, &name, &data)?;
 
         debug!("Releasing daemon mutex (thread id: {})", thread_id::get());
     }
 
+    config_update::schedule_rollback(&cfg, update.id, cfg.config_rollback_timeout);
+
+    Ok(HttpResponse::Ok().json(update))
+}
+
+/*
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/daemon/config/confirm
+*/
+#[post("/daemon/config/confirm")]
+async fn config_confirm(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    config_update::confirm(&cfg)?;
+
     Ok(HttpResponse::Ok().finish())
 }
@@ -0,0 +1,70 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use actix_web::{get, post, web, HttpResponse};
+use log::debug;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::errors::Result;
+use crate::utils::drift;
+
+/*
+  Result of the last comparison between the ruleset installed by FWCloud and the running one, with
+  a unified diff between them if they are different.
+
+  curl -k -i -X GET -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/drift
+*/
+#[get("/drift")]
+async fn get_status(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    debug!("Locking drift mutex (thread id: {})", thread_id::get());
+    let status = cfg.drift.lock().unwrap().clone();
+    debug!("Releasing drift mutex (thread id: {})", thread_id::get());
+
+    Ok(HttpResponse::Ok().json(status))
+}
+
+/*
+  Compare now the running ruleset with the one installed by FWCloud.
+
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/drift/check
+*/
+#[post("/drift/check")]
+async fn check(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    let res;
+
+    // Mutex scope start.
+    {
+        debug!("Locking script mutex (thread id: {})", thread_id::get());
+        let mutex = Arc::clone(&cfg.mutex.fwcloud_script);
+        let _mutex_data = mutex.lock().await;
+        debug!("Script mutex locked (thread id: {})", thread_id::get());
+
+        res = drift::check(&cfg);
+
+        debug!("Releasing script mutex (thread id: {})", thread_id::get());
+    } // End of mutex scope.
+
+    Ok(HttpResponse::Ok().json(res))
+}
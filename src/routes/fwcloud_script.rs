@@ -21,18 +21,107 @@
 */
 
 use actix_multipart::Multipart;
-use actix_web::{post, web, HttpResponse};
+use actix_web::{get, http::header::ContentType, post, web, HttpRequest, HttpResponse};
 use log::debug;
+use serde::Deserialize;
+use std::path::Path;
 use std::sync::Arc;
+use validator::Validate;
 
+use crate::audit::Requester;
 use crate::config::Config;
-use crate::utils::http_files::HttpFiles;
+use crate::utils::cmd::CmdKind;
+use crate::utils::http_files::{
+    fwcloud_script_path_allowed, install_fwcloud_script, install_fwcloud_script_job, write_file,
+    HttpFiles,
+};
+use crate::utils::jobs;
+use crate::utils::policy_apply::{self, PolicyApply};
+use crate::utils::script_history;
 
-use crate::errors::Result;
+use crate::errors::{FwcError, Result};
 
+use super::jobs::accepted;
+
+#[derive(Deserialize, Validate)]
+struct UploadQuery {
+    #[validate(range(min = 10, max = 3600))]
+    rollback_timeout: Option<u64>,
+
+    // Install the script in the background, answering its job right away.
+    #[serde(default)]
+    job: bool,
+
+    // Seconds before terminating each command of the script, instead of FWCLOUD_SCRIPT_TIMEOUT.
+    #[validate(range(min = 1, max = 86400))]
+    timeout: Option<u64>,
+}
+
+// Options of /fwcloud_script/history/{id}/install, it is not run as a job.
+#[derive(Deserialize, Validate)]
+struct InstallQuery {
+    #[validate(range(min = 10, max = 3600))]
+    rollback_timeout: Option<u64>,
+
+    #[validate(range(min = 1, max = 86400))]
+    timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    from: u64,
+    to: u64,
+}
+
+/*
+  With the rollback_timeout option the current ruleset is saved before installing the script and it
+  is restored if the policy is not confirmed with /fwcloud_script/confirm in that number of seconds.
+  The detached signature of the script (fwcloud.sh.sig) is optional, unless REQUIRE_SIGNED_SCRIPTS
+  is enabled, and then the script can only be installed in one of the FWCLOUD_SCRIPT_PATHS. A signed
+  script is only installed in the path that was signed. Permissions writable by group or others are
+  rejected. With job=true the script is installed in the background and its job is answered right
+  away, the pending policy must be confirmed in the same way once the job has finished.
+
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    -F "dst_dir=/etc/fwcloud" -F "perms=700" -F "file=@fwcloud.sh" -F "file=@fwcloud.sh.sig" \
+    'https://localhost:33033/api/v1/fwcloud_script/upload?rollback_timeout=60'
+*/
 #[post("/fwcloud_script/upload")]
-async fn upload_and_run(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
-    let res: HttpResponse;
+async fn upload_and_run(
+    req: HttpRequest,
+    query: web::Query<UploadQuery>,
+    payload: Multipart,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    query.validate()?; // Validate input.
+    let timeout = CmdKind::FwcloudScript.timeout(&cfg, query.timeout);
+
+    if query.job {
+        let upload = HttpFiles::new(&cfg.tmp_dir, false)
+            .audit(&req)
+            .fwcloud_script_upload(payload, &cfg)
+            .await?;
+
+        let job_cfg = Arc::clone(&cfg);
+        let rollback_timeout = query.rollback_timeout;
+        let job = jobs::submit(
+            &cfg,
+            "fwcloud_script",
+            format!("install {}", upload.dst_path),
+            Requester::of(&req),
+            move |job| {
+                let apply =
+                    install_fwcloud_script_job(&job_cfg, upload, rollback_timeout, timeout, job)?;
+                if let Some(apply) = apply {
+                    policy_apply::schedule_rollback(&job_cfg, apply.id, apply.rollback_timeout);
+                }
+                Ok(())
+            },
+        )?;
+        return Ok(accepted(&req, job));
+    }
+
+    let (mut res, apply);
 
     // Mutex scope start.
     {
@@ -41,12 +130,189 @@ async fn upload_and_run(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Resu
         let _mutex_data = mutex.lock().await;
         debug!("Script mutex locked (thread id: {})", thread_id::get());
 
-        res = HttpFiles::new(cfg.tmp_dir, false)
-            .fwcloud_script(payload, &cfg)
+        (res, apply) = HttpFiles::new(&cfg.tmp_dir, false)
+            .audit(&req)
+            .fwcloud_script(payload, &cfg, query.rollback_timeout, timeout)
             .await?;
 
         debug!("Releasing script mutex (thread id: {})", thread_id::get());
     } // End of mutex scope.
 
+    wait_confirmation(&cfg, &mut res, apply);
+    Ok(res)
+}
+
+// Roll back the installed policy if it is not confirmed in time.
+fn wait_confirmation(cfg: &Arc<Config>, res: &mut HttpResponse, apply: Option<PolicyApply>) {
+    if let Some(apply) = apply {
+        res.headers_mut().insert(
+            "x-policy-rollback-timeout".parse().unwrap(),
+            apply.rollback_timeout.into(),
+        );
+        policy_apply::schedule_rollback(cfg, apply.id, apply.rollback_timeout);
+    }
+}
+
+/*
+  Dry-run of the script: its syntax is checked and it is started in throwaway network, mount, PID,
+  IPC and UTS namespaces with read-only file systems, the live firewall and the files are not
+  modified. The errors are returned with their line numbers.
+
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    -F "file=@fwcloud.sh" \
+    https://localhost:33033/api/v1/fwcloud_script/validate
+*/
+#[post("/fwcloud_script/validate")]
+async fn validate(
+    req: HttpRequest,
+    payload: Multipart,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    let validation = HttpFiles::new(&cfg.tmp_dir, false)
+        .audit(&req)
+        .fwcloud_script_validate(payload, &cfg)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(validation))
+}
+
+/*
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/fwcloud_script/confirm
+*/
+#[post("/fwcloud_script/confirm")]
+async fn confirm(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    policy_apply::confirm(&cfg)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/*
+  Policy installed with rollback that is waiting for confirmation, with the seconds remaining before
+  the rollback. Empty object if there is none. If the rollback failed, rollback_error has the error
+  and the policy stays pending until it is confirmed.
+
+  curl -k -i -X GET -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/fwcloud_script/pending
+*/
+#[get("/fwcloud_script/pending")]
+async fn pending(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    match policy_apply::pending(&cfg) {
+        Some(apply) => Ok(HttpResponse::Ok().json(apply)),
+        None => Ok(HttpResponse::Ok().json(serde_json::json!({}))),
+    }
+}
+
+/*
+  Installed FWCloud scripts from the newest to the oldest one.
+
+  curl -k -i -X GET -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/fwcloud_script/history
+*/
+#[get("/fwcloud_script/history")]
+async fn history(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(script_history::list(&cfg)?))
+}
+
+/*
+  Unified diff between two scripts of the history.
+
+  curl -k -i -X GET -H 'X-API-Key: **************************' \
+    'https://localhost:33033/api/v1/fwcloud_script/history/diff?from=3&to=4'
+*/
+#[get("/fwcloud_script/history/diff")]
+async fn history_diff(
+    query: web::Query<DiffQuery>,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    let (cfg, from, to) = (Arc::clone(&cfg), query.from, query.to);
+    let diff = web::block(move || script_history::diff(&cfg, from, to)).await??;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(diff))
+}
+
+/*
+  Download a script of the history.
+
+  curl -k -i -X GET -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/fwcloud_script/history/3
+*/
+#[get("/fwcloud_script/history/{id}")]
+async fn history_script(id: web::Path<u64>, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    let (_, script) = script_history::get(&cfg, *id)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(script))
+}
+
+/*
+  Install again a script of the history in the path where it was installed, with the same
+  install and start sequence of /fwcloud_script/upload and its rollback_timeout option. The path
+  must be one of the FWCLOUD_SCRIPT_PATHS, and the script is written atomically with the
+  permissions it had when it was installed. It is not available with REQUIRE_SIGNED_SCRIPTS, the signature of an old script doesn't prove that the
+  console wants it installed now, and it would allow going back to an old policy with a stolen key.
+
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    'https://localhost:33033/api/v1/fwcloud_script/history/3/install?rollback_timeout=60'
+*/
+#[post("/fwcloud_script/history/{id}/install")]
+async fn history_install(
+    req: HttpRequest,
+    id: web::Path<u64>,
+    query: web::Query<InstallQuery>,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    query.validate()?; // Validate input.
+    if cfg.require_signed_scripts {
+        return Err(FwcError::ScriptSignatureRequired);
+    }
+    let timeout = CmdKind::FwcloudScript.timeout(&cfg, query.timeout);
+
+    let (output, apply);
+
+    // Mutex scope start.
+    {
+        debug!("Locking script mutex (thread id: {})", thread_id::get());
+        let mutex = Arc::clone(&cfg.mutex.fwcloud_script);
+        let _mutex_data = mutex.lock().await;
+        debug!("Script mutex locked (thread id: {})", thread_id::get());
+
+        let (id, rollback_timeout) = (*id, query.rollback_timeout);
+        let (install_cfg, requester) = (Arc::clone(&cfg), Requester::of(&req));
+        (output, apply) = web::block(move || {
+            let cfg = install_cfg;
+            let (entry, script) = script_history::get(&cfg, id)?;
+            // FWCLOUD_SCRIPT_PATHS could have changed since it was installed.
+            if !fwcloud_script_path_allowed(&cfg, Path::new(&entry.path)) {
+                return Err(FwcError::ScriptPathNotAllowed);
+            }
+            let snapshot = policy_apply::prepare(&cfg, rollback_timeout)?;
+            write_file(
+                &entry.path,
+                script.as_bytes(),
+                entry.perms.unwrap_or(0o700) & 0o755,
+            )?;
+
+            let result = install_fwcloud_script(&cfg, &entry.path, uuid::Uuid::nil(), timeout);
+            script_history::record(
+                &cfg,
+                &entry.path,
+                &requester,
+                &result,
+                Some(entry.id),
+                entry.signed,
+            );
+            policy_apply::finish(&cfg, snapshot, rollback_timeout, result)
+        })
+        .await??;
+
+        debug!("Releasing script mutex (thread id: {})", thread_id::get());
+    } // End of mutex scope.
+
+    let mut res = output.into();
+    wait_confirmation(&cfg, &mut res, apply);
     Ok(res)
 }
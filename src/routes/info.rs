@@ -19,11 +19,14 @@
     You should have received a copy of the GNU General Public License
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
-use actix_web::{get, http::header::ContentType, HttpResponse};
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
 use serde::Serialize;
+use std::sync::Arc;
 use sysinfo::System;
 
+use crate::config::Config;
 use crate::errors::Result;
+use crate::tls::CertInfo;
 
 #[derive(Serialize)]
 struct Info {
@@ -32,19 +35,21 @@ struct Info {
     system_name: String,
     os_version: String,
     kernel_version: String,
+    tls_certificate: Option<CertInfo>,
 }
 
 /*
   curl -v -k -i -X GET -H 'X-API-Key: **************************' https://localhost:33033/api/v1/info
 */
 #[get("/info")]
-async fn info() -> Result<HttpResponse> {
+async fn info(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
     let info = Info {
         fwc_agent_version: env!("CARGO_PKG_VERSION"),
         host_name: System::host_name().unwrap_or("".to_owned()),
         system_name: System::name().unwrap_or("".to_owned()),
         os_version: System::os_version().unwrap_or("".to_owned()),
         kernel_version: System::kernel_version().unwrap_or("".to_owned()),
+        tls_certificate: cfg.tls.state.lock().unwrap().certificate(),
     };
 
     Ok(HttpResponse::Ok()
@@ -20,11 +20,13 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::config::Config;
 use crate::errors::Result;
-use crate::utils::cmd::run_cmd;
-use actix_web::{get, HttpResponse};
+use crate::utils::cmd::{run_cmd, CmdKind};
+use actix_web::{get, web, HttpResponse};
+use std::sync::Arc;
 
 #[get("/interfaces/info")]
-async fn info() -> Result<HttpResponse> {
-    run_cmd("ip", &["a"])
+async fn info(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    run_cmd("ip", &["a"], CmdKind::Other.timeout(&cfg, None)).map(HttpResponse::from)
 }
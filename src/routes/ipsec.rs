@@ -21,10 +21,11 @@
 */
 
 use actix_multipart::Multipart;
-use actix_web::{delete, post, web, HttpResponse};
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
 use log::debug;
 use std::sync::Arc;
 
+use crate::audit::audit_param;
 use crate::config::Config;
 use crate::utils::files_list::FilesList;
 use crate::utils::http_files::HttpFiles;
@@ -35,7 +36,11 @@ use thread_id;
 //use std::{thread, time};
 
 #[post("/ipsec/files/upload")]
-async fn files_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+async fn files_upload(
+    req: HttpRequest,
+    payload: Multipart,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
     // Mutex scope start.
     {
         debug!("Locking IPSec mutex (thread id: {})", thread_id::get());
@@ -46,7 +51,8 @@ async fn files_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result
         // Only for debug purposes. It is useful for verify that the mutex makes its work.
         //thread::sleep(time::Duration::from_millis(10_000));
 
-        HttpFiles::new(cfg.tmp_dir, true)
+        HttpFiles::new(&cfg.tmp_dir, true)
+            .audit(&req)
             .files_upload(payload)
             .await?;
 
@@ -58,9 +64,12 @@ async fn files_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result
 
 #[delete("/ipsec/files/remove")]
 async fn files_remove(
+    req: HttpRequest,
     files_list: web::Json<FilesList>,
     cfg: web::Data<Arc<Config>>,
 ) -> Result<HttpResponse> {
+    audit_param(&req, "files_list", &*files_list);
+
     // Mutex scope start.
     {
         debug!("Locking IPSec mutex (thread id: {})", thread_id::get());
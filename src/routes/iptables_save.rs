@@ -20,11 +20,86 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::config::Config;
 use crate::errors::Result;
-use crate::utils::cmd::run_cmd;
-use actix_web::{get, HttpResponse};
+use crate::utils::cmd::{run_cmd, run_cmd_output, CmdKind};
+use crate::utils::iptables::{detect_backend, BackendInfo, Ruleset, SaveOptions};
+use crate::utils::myregex::{IPTABLES_BACKENDS, IPTABLES_TABLES, OUTPUT_FORMATS};
+
+#[derive(Deserialize, Validate)]
+struct SaveQuery {
+    #[serde(default)]
+    ipv6: bool,
+
+    #[validate(regex(path = *IPTABLES_TABLES))]
+    table: Option<String>,
+
+    #[serde(default)]
+    counters: bool,
+
+    #[validate(regex(path = *IPTABLES_BACKENDS))]
+    backend: Option<String>,
+
+    #[validate(regex(path = *OUTPUT_FORMATS))]
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Backends {
+    iptables: Option<BackendInfo>,
+    ip6tables: Option<BackendInfo>,
+}
+
+/*
+  Output of iptables-save (or ip6tables-save with ipv6=true). Optionally only for one table, with
+  the packet and byte counters and with the legacy or nft backend variant of the command.
+  With format=json the output is parsed into tables, chains and rules.
 
+  curl -v -k -i -X GET -H 'X-API-Key: **************************' \
+    'https://localhost:33033/api/v1/iptables-save/data?ipv6=true&table=filter&counters=true&backend=nft&format=json'
+*/
 #[get("/iptables-save/data")]
-async fn data() -> Result<HttpResponse> {
-    run_cmd("iptables-save", &[])
+async fn data(query: web::Query<SaveQuery>, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    query.validate()?; // Validate input.
+    let timeout = CmdKind::Other.timeout(&cfg, None);
+
+    let opts = SaveOptions {
+        ipv6: query.ipv6,
+        table: query.table.as_deref(),
+        counters: query.counters,
+        backend: query.backend.as_deref(),
+    };
+
+    if query.format.as_deref() != Some("json") {
+        return run_cmd(&opts.cmd(), &opts.args(), timeout).map(HttpResponse::from);
+    }
+
+    let ruleset = Ruleset::parse(&run_cmd_output(&opts.cmd(), &opts.args(), timeout)?)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(serde_json::to_string(&ruleset)?))
+}
+
+/*
+  Version and backend (legacy or nft) of the iptables and ip6tables commands.
+
+  curl -v -k -i -X GET -H 'X-API-Key: **************************' \
+    'https://localhost:33033/api/v1/iptables-save/backend'
+*/
+#[get("/iptables-save/backend")]
+async fn backends(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    let timeout = CmdKind::Other.timeout(&cfg, None);
+    let info = Backends {
+        iptables: detect_backend(false, timeout)?,
+        ip6tables: detect_backend(true, timeout)?,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(serde_json::to_string(&info)?))
 }
@@ -0,0 +1,95 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::audit::audit_param;
+use crate::config::Config;
+use crate::errors::Result;
+use crate::utils::jobs::{self, Job};
+
+/// Options of the plugin and systemctl requests: running them as a job and the seconds before
+/// terminating the command, instead of the configured timeout. Without the job option, each route
+/// decides if the command is run as a job.
+#[derive(Deserialize, Validate)]
+pub struct CmdQuery {
+    pub job: Option<bool>,
+
+    #[validate(range(min = 1, max = 86400))]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct OutputQuery {
+    #[serde(default)]
+    offset: u64,
+}
+
+/// Answer a submitted job, that can be polled in its Location.
+pub fn accepted(req: &HttpRequest, job: Job) -> HttpResponse {
+    audit_param(req, "job", job.id);
+
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/api/v1/jobs/{}", job.id)))
+        .json(job)
+}
+
+/*
+  Jobs from the newest to the oldest one, without their output.
+
+  curl -k -i -X GET -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/jobs
+*/
+#[get("/jobs")]
+async fn list(cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(jobs::list(&cfg)?))
+}
+
+/*
+  State, exit code and output of a job. With the offset option only the output from that byte is
+  returned, for polling a running job.
+
+  curl -k -i -X GET -H 'X-API-Key: **************************' \
+    'https://localhost:33033/api/v1/jobs/1b4e28ba-2fa1-11d2-883f-0016d3cca427?offset=0'
+*/
+#[get("/jobs/{id}")]
+async fn get_job(
+    id: web::Path<Uuid>,
+    query: web::Query<OutputQuery>,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(jobs::get(&cfg, *id, query.offset)?))
+}
+
+/*
+  curl -k -i -X POST -H 'X-API-Key: **************************' \
+    https://localhost:33033/api/v1/jobs/1b4e28ba-2fa1-11d2-883f-0016d3cca427/cancel
+*/
+#[post("/jobs/{id}/cancel")]
+async fn cancel(id: web::Path<Uuid>, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+    jobs::cancel(&cfg, *id)?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
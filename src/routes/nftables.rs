@@ -0,0 +1,64 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::config::Config;
+use crate::errors::Result;
+use crate::utils::cmd::{run_cmd_output, CmdKind};
+use crate::utils::myregex::{ALPHA_NUM_2, NFT_FAMILIES};
+use crate::utils::nftables::Ruleset;
+
+#[derive(Deserialize, Validate)]
+struct RulesetQuery {
+    #[validate(regex(path = *NFT_FAMILIES))]
+    family: Option<String>,
+
+    #[validate(length(min = 1, max = 64), regex(path = *ALPHA_NUM_2))]
+    table: Option<String>,
+}
+
+/*
+  Current nftables ruleset (nft -j list ruleset) as JSON, optionally filtered by family and table.
+
+  curl -v -k -i -X GET -H 'X-API-Key: **************************' \
+    'https://localhost:33033/api/v1/nftables/ruleset?family=inet&table=filter'
+*/
+#[get("/nftables/ruleset")]
+async fn ruleset(
+    query: web::Query<RulesetQuery>,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    query.validate()?; // Validate input.
+
+    let timeout = CmdKind::Other.timeout(&cfg, None);
+    let output = run_cmd_output("nft", &["-j", "list", "ruleset"], timeout)?;
+    let mut ruleset = Ruleset::parse(&output)?;
+    ruleset.filter(query.family.as_deref(), query.table.as_deref());
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(serde_json::to_string(&ruleset)?))
+}
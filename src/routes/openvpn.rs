@@ -21,10 +21,11 @@
 */
 
 use actix_multipart::Multipart;
-use actix_web::{delete, http::header, post, put, web, HttpResponse};
+use actix_web::{delete, http::header, post, put, web, HttpRequest, HttpResponse};
 use log::debug;
 use std::sync::Arc;
 
+use crate::audit::audit_param;
 use crate::config::Config;
 use crate::utils::files_list::FilesList;
 use crate::utils::http_files::HttpFiles;
@@ -36,7 +37,11 @@ use thread_id;
 //use std::{thread, time};
 
 #[post("/openvpn/files/upload")]
-async fn files_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+async fn files_upload(
+    req: HttpRequest,
+    payload: Multipart,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
     // Mutex scope start.
     {
         debug!("Locking OpenVPN mutex (thread id: {})", thread_id::get());
@@ -47,7 +52,8 @@ async fn files_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result
         // Only for debug purposes. It is useful for verify that the mutex makes its work.
         //thread::sleep(time::Duration::from_millis(10_000));
 
-        HttpFiles::new(cfg.tmp_dir, true)
+        HttpFiles::new(&cfg.tmp_dir, true)
+            .audit(&req)
             .files_upload(payload)
             .await?;
 
@@ -59,9 +65,12 @@ async fn files_upload(payload: Multipart, cfg: web::Data<Arc<Config>>) -> Result
 
 #[delete("/openvpn/files/remove")]
 async fn files_remove(
+    req: HttpRequest,
     files_list: web::Json<FilesList>,
     cfg: web::Data<Arc<Config>>,
 ) -> Result<HttpResponse> {
+    audit_param(&req, "files_list", &*files_list);
+
     // Mutex scope start.
     {
         debug!("Locking OpenVPN mutex (thread id: {})", thread_id::get());
@@ -141,7 +150,7 @@ async fn get_status(
 
         let file_name =
             format!("{}/{}.data", files_list.dir(), files_list.name(0)).replace('/', "_");
-        files_list.chdir(cfg.data_dir);
+        files_list.chdir(&cfg.data_dir);
         files_list.rename(0, &file_name);
 
         result = files_list.head_remove(0, cfg.openvpn_status_request_max_lines)?;
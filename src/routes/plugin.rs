@@ -17,17 +17,22 @@
 
 use std::sync::{Arc, Mutex};
 
-use actix_web::{post, web, HttpResponse};
+use actix_web::{post, web, HttpRequest, HttpResponse};
 use log::debug;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::audit::{audit_param, Requester};
+use crate::auth::require_scope;
 use crate::config::Config;
 use crate::errors::{FwcError, Result};
-use crate::utils::cmd::{run_cmd, run_cmd_ws};
+use crate::utils::cmd::{run_blocking, run_cmd, run_cmd_job, run_cmd_ws, CmdKind};
+use crate::utils::jobs;
 use crate::utils::ws::WsData;
 
+use super::jobs::{accepted, CmdQuery};
+
 //use std::{thread, time};
 
 #[derive(Deserialize, Serialize, Validate)]
@@ -52,49 +57,95 @@ pub struct Plugin {
     -H "Content-Type: application/json" \
     -d '{"name":"test", "action":"enable"}' \
     https://localhost:33033/api/v1/plugin
+
+  With ?job=true the plugin is run in the background and its job is answered right away, its output
+  can't be streamed to the WebSocket ws_id then. With ?timeout=<seconds> the plugin is terminated
+  after that time instead of PLUGIN_TIMEOUT.
 */
 #[post("/plugin")]
-async fn plugin(plugin: web::Json<Plugin>, cfg: web::Data<Arc<Config>>) -> Result<HttpResponse> {
+async fn plugin(
+    req: HttpRequest,
+    plugin: web::Json<Plugin>,
+    query: web::Query<CmdQuery>,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    audit_param(&req, "name", &plugin.name);
+    audit_param(&req, "action", &plugin.action);
     plugin.validate()?; // Validate input.
+    query.validate()?;
+    require_scope(&req, &format!("plugin:{}", plugin.action))?;
 
     let cmd = "sh";
     let argv0 = format!("{}/{}/{}.sh", cfg.plugins_dir, plugin.name, plugin.name);
-    let args = [argv0.as_str(), plugin.action.as_str()];
+    let timeout = CmdKind::Plugin.timeout(&cfg, query.timeout);
+
+    if query.job.unwrap_or(false) {
+        // The output is kept in the job, not streamed to a WebSocket.
+        if plugin.ws_id.is_some() {
+            return Err(FwcError::NotAllowedParameter);
+        }
+
+        let job_cfg = Arc::clone(&cfg);
+        let action = plugin.action.clone();
+        let job = jobs::submit(
+            &cfg,
+            "plugin",
+            format!("{} {}", plugin.name, plugin.action),
+            Requester::of(&req),
+            move |job| {
+                debug!("Locking plugins mutex (thread id: {})", thread_id::get());
+                let _mutex_data = job_cfg.mutex.plugins.blocking_lock();
+                debug!("Plugins mutex locked (thread id: {})", thread_id::get());
+
+                job.start()?;
+                let res = run_cmd_job(cmd, &[&argv0, &action], job, timeout);
+
+                debug!("Releasing plugins mutex (thread id: {})", thread_id::get());
+                res
+            },
+        )?;
+        return Ok(accepted(&req, job));
+    }
     let res: HttpResponse;
 
     // Mutex scope start.
     {
         debug!("Locking plugins mutex (thread id: {})", thread_id::get());
         let mutex = Arc::clone(&cfg.mutex.plugins);
-        let _mutex_data = mutex.lock().unwrap();
+        let _mutex_data = mutex.lock().await;
         debug!("Plugins mutex locked (thread id: {})", thread_id::get());
 
         // If the websocket id is present in the Plugin Json data received in the request, then
         // stream the command input to the websocket. If not, the command output will be sent
         // as a whole when the command execution finishes.
-        res = match plugin.ws_id {
-            Some(id) => {
-                let ws_data: Arc<Mutex<WsData>>;
-                {
-                    debug!("Locking ws map mutex (thread id: {})", thread_id::get());
-                    let ws_map = cfg.ws_map.lock().unwrap();
-                    ws_data = ws_map
-                        .get(&id)
-                        .ok_or(FwcError::WebSocketIdNotFound)?
-                        .clone();
-                    debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
+        let (cmd_cfg, action, ws_id) = (Arc::clone(&cfg), plugin.action.clone(), plugin.ws_id);
+        res = run_blocking(move || {
+            let args = [argv0.as_str(), action.as_str()];
+            match ws_id {
+                Some(id) => {
+                    let ws_data: Arc<Mutex<WsData>>;
+                    {
+                        debug!("Locking ws map mutex (thread id: {})", thread_id::get());
+                        let ws_map = cmd_cfg.ws_map.lock().unwrap();
+                        ws_data = ws_map
+                            .get(&id)
+                            .ok_or(FwcError::WebSocketIdNotFound)?
+                            .clone();
+                        debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
+                    }
+                    let res = run_cmd_ws(cmd, &args, &ws_data, true, timeout)?;
+                    {
+                        debug!("Locking ws map mutex (thread id: {})", thread_id::get());
+                        let mut ws_map = cmd_cfg.ws_map.lock().unwrap();
+                        ws_map.remove(&id);
+                        debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
+                    }
+                    Ok(res)
                 }
-                let res = run_cmd_ws(cmd, &args, &ws_data, true)?;
-                {
-                    debug!("Locking ws map mutex (thread id: {})", thread_id::get());
-                    let mut ws_map = cfg.ws_map.lock().unwrap();
-                    ws_map.remove(&id);
-                    debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
-                }
-                res
+                None => run_cmd(cmd, &args, timeout),
             }
-            None => run_cmd(cmd, &args)?,
-        };
+        })
+        .await?;
 
         debug!("Releasing plugins mutex (thread id: {})", thread_id::get());
     } // Mutex scope end.
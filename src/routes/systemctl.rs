@@ -19,13 +19,20 @@
     You should have received a copy of the GNU General Public License
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
-use crate::utils::cmd::run_cmd;
-use actix_web::{post, web, HttpResponse};
+use crate::audit::{audit_param, Requester};
+use crate::auth::require_scope;
+use crate::config::Config;
+use crate::utils::cmd::{run_blocking, run_cmd, run_cmd_job, CmdKind};
+use crate::utils::jobs;
+use actix_web::{post, web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use validator::Validate;
 
 use crate::errors::Result;
 
+use super::jobs::{accepted, CmdQuery};
+
 #[derive(Deserialize, Serialize, Validate)]
 pub struct Systemctl {
     #[validate(regex(
@@ -45,13 +52,39 @@ pub struct Systemctl {
     -H "Content-Type: application/json" \
     -d '{"action":"status", "service":"openvpn"}' \
     https://localhost:33033/api/v1/systemctl
+
+  With ?job=true the command is run in the background and its job is answered right away. With
+  ?timeout=<seconds> the command is terminated after that time instead of SYSTEMCTL_TIMEOUT.
 */
 #[post("/systemctl")]
-async fn systemctl(systemctl: web::Json<Systemctl>) -> Result<HttpResponse> {
+async fn systemctl(
+    req: HttpRequest,
+    systemctl: web::Json<Systemctl>,
+    query: web::Query<CmdQuery>,
+    cfg: web::Data<Arc<Config>>,
+) -> Result<HttpResponse> {
+    audit_param(&req, "command", &systemctl.command);
+    audit_param(&req, "service", &systemctl.service);
     systemctl.validate()?; // Validate input.
+    query.validate()?;
+    require_scope(&req, &format!("systemctl:{}", systemctl.command))?;
+
+    let timeout = CmdKind::Systemctl.timeout(&cfg, query.timeout);
+
+    let (command, service) = (systemctl.command.clone(), systemctl.service.clone());
+    if query.job.unwrap_or(false) {
+        let job = jobs::submit(
+            &cfg,
+            "systemctl",
+            format!("{command} {service}"),
+            Requester::of(&req),
+            move |job| {
+                job.start()?;
+                run_cmd_job("systemctl", &[&command, &service], job, timeout)
+            },
+        )?;
+        return Ok(accepted(&req, job));
+    }
 
-    run_cmd(
-        "systemctl",
-        &[systemctl.command.as_ref(), systemctl.service.as_ref()],
-    )
+    run_blocking(move || run_cmd("systemctl", &[&command, &service], timeout)).await
 }
@@ -0,0 +1,186 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::config::Config;
+use crate::errors::Result;
+use crate::utils::policy_apply;
+
+/// Status of a running agent, answered on the local status socket.
+#[derive(Serialize, Deserialize)]
+pub struct AgentStatus {
+    pub version: String,
+    pub pid: u32,
+    pub uptime: u64,
+    pub listeners: Vec<String>,
+    pub tls: bool,
+    pub tls_certificate_days_until_expiry: Option<i32>,
+    pub websocket_sessions: usize,
+    pub banned_ips: usize,
+    pub config_change_pending: bool,
+    /// Seconds before rolling back the installed policy if it is not confirmed.
+    pub policy_rollback_remaining: Option<u64>,
+    /// The running ruleset doesn't match the one installed by FWCloud.
+    pub ruleset_drift: bool,
+    /// Jobs queued or running.
+    pub active_jobs: usize,
+}
+
+impl AgentStatus {
+    fn new(cfg: &Config, started: Instant) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        AgentStatus {
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            pid: std::process::id(),
+            uptime: started.elapsed().as_secs(),
+            listeners: cfg
+                .listeners
+                .iter()
+                .map(|listener| {
+                    let scheme = if listener.tls.is_some() {
+                        "https"
+                    } else {
+                        "http"
+                    };
+                    format!("{scheme}://{}", listener.address)
+                })
+                .chain((!cfg.unix_socket.is_empty()).then(|| format!("unix:{}", cfg.unix_socket)))
+                .collect(),
+            tls: cfg.enable_tls,
+            tls_certificate_days_until_expiry: cfg
+                .tls
+                .state
+                .lock()
+                .unwrap()
+                .certificate()
+                .map(|cert| cert.days_until_expiry),
+            websocket_sessions: cfg.ws_map.lock().unwrap().len(),
+            banned_ips: cfg.auth_failures.lock().unwrap().bans(now).len(),
+            config_change_pending: cfg.pending_config.lock().unwrap().is_some(),
+            policy_rollback_remaining: policy_apply::pending(cfg).map(|apply| apply.remaining),
+            ruleset_drift: cfg.drift.lock().unwrap().drift,
+            active_jobs: cfg.jobs.lock().unwrap().active(),
+        }
+    }
+}
+
+/// Answer the agent status to every connection of the local status socket.
+///
+/// The socket can only be used by the owner of the agent process. A stale socket file is replaced, but not one
+/// that is in use by another agent.
+pub fn start_status_socket(cfg: Arc<Config>) {
+    let path = cfg.status_socket.clone();
+    if Path::new(&path).exists() {
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            warn!("Status socket '{path}' in use by another process, not starting it");
+            return;
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    let listener = match UnixListener::bind(&path).and_then(|listener| {
+        fs::set_permissions(&path, Permissions::from_mode(0o600)).map(|_| listener)
+    }) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Error creating the status socket '{path}': {e}");
+            return;
+        }
+    };
+    info!("Status socket: {path}");
+
+    let started = Instant::now();
+    tokio::spawn(async move {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _addr)) => stream,
+                Err(e) => {
+                    error!("Error accepting status socket connection: {e}");
+                    continue;
+                }
+            };
+
+            let status = serde_json::to_vec(&AgentStatus::new(&cfg, started)).unwrap_or_default();
+            if let Err(e) = stream.write_all(&status).await {
+                debug!("Error writing agent status: {e}");
+            }
+        }
+    });
+}
+
+/// Ask a running agent for its status through its local status socket.
+pub async fn query_status(path: &str) -> Result<AgentStatus> {
+    let mut stream = UnixStream::connect(path).await?;
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data).await?;
+
+    Ok(serde_json::from_slice(&data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use std::env;
+
+    #[tokio::test]
+    #[serial]
+    async fn answers_agent_status() {
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        let mut cfg = Config::new().unwrap();
+        env::remove_var("API_KEY");
+        cfg.status_socket = format!(
+            "{}/fwcloud-agent-{}.sock",
+            env::temp_dir().display(),
+            uuid::Uuid::new_v4()
+        );
+        let cfg = Arc::new(cfg);
+
+        start_status_socket(cfg.clone());
+        let status = query_status(&cfg.status_socket).await.unwrap();
+        assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(status.pid, std::process::id());
+        assert!(!status.config_change_pending);
+        assert_eq!(status.policy_rollback_remaining, None);
+        assert!(!status.ruleset_drift);
+        assert_eq!(status.active_jobs, 0);
+
+        // A second agent doesn't steal the socket of the running one.
+        start_status_socket(cfg.clone());
+        assert!(query_status(&cfg.status_socket).await.is_ok());
+
+        fs::remove_file(&cfg.status_socket).unwrap();
+    }
+}
@@ -0,0 +1,567 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use actix_tls::accept::openssl::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::{TcpStream, UnixStream};
+use log::{debug, error, info, warn};
+use openssl::asn1::{Asn1Integer, Asn1Time};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{
+    SniError, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype, SslMethod, SslVerifyMode,
+    SslVersion,
+};
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::store::{X509Lookup, X509StoreBuilder};
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::{X509Name, X509NameBuilder, X509NameRef, X509Ref, X509};
+use serde::Serialize;
+use std::any::Any;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use sysinfo::System;
+
+use crate::config::Config;
+use crate::errors::{FwcError, Result};
+
+// Days before the expiration of the server certificate from which we start warning about it.
+const CERT_EXPIRY_WARNING_DAYS: i32 = 30;
+
+// Validity of the self-signed certificate generated on first start.
+const SELF_SIGNED_CERT_DAYS: u32 = 3650;
+
+/// Identity of the client certificate presented in a mutual TLS connection.
+#[derive(Clone, Debug)]
+pub struct ClientCert {
+    pub cn: Option<String>,
+    pub san: Vec<String>,
+}
+
+impl ClientCert {
+    pub fn from_x509(cert: &X509Ref) -> Self {
+        let cn = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|data| data.to_string());
+
+        let mut san: Vec<String> = vec![];
+        if let Some(names) = cert.subject_alt_names() {
+            for name in names.iter() {
+                if let Some(dns) = name.dnsname() {
+                    san.push(String::from(dns));
+                } else if let Some(email) = name.email() {
+                    san.push(String::from(email));
+                }
+            }
+        }
+
+        ClientCert { cn, san }
+    }
+
+    /// Check if the common name or any of the subject alternative names is in the list.
+    pub fn is_allowed(&self, allowed_names: &[String]) -> bool {
+        allowed_names.iter().any(|allowed| {
+            self.cn.as_ref() == Some(allowed) || self.san.iter().any(|name| name == allowed)
+        })
+    }
+}
+
+/// Summary of the server certificate.
+#[derive(Clone, Serialize)]
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub sha256_fingerprint: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub days_until_expiry: i32,
+}
+
+impl CertInfo {
+    pub fn from_x509(cert: &X509Ref) -> Result<Self> {
+        let fingerprint = cert.digest(MessageDigest::sha256())?;
+
+        Ok(CertInfo {
+            subject: name_to_string(cert.subject_name()),
+            issuer: name_to_string(cert.issuer_name()),
+            sha256_fingerprint: fingerprint
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect::<Vec<String>>()
+                .join(":"),
+            not_before: cert.not_before().to_string(),
+            not_after: cert.not_after().to_string(),
+            days_until_expiry: Asn1Time::days_from_now(0)?.diff(cert.not_after())?.days,
+        })
+    }
+}
+
+fn name_to_string(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let field = entry.object().nid().short_name().unwrap_or("?");
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|data| data.to_string())
+                .unwrap_or_default();
+            format!("{field}={value}")
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Identity of the peer of a connection to the local Unix socket.
+#[derive(Clone, Debug)]
+pub struct UnixPeer {
+    pub uid: Option<u32>,
+}
+
+/// Certificate files of a TLS listener and the TLS context loaded from them.
+///
+/// Listeners that use the same files share the same TLS context.
+#[derive(Clone)]
+pub struct TlsCert {
+    pub key_file: String,
+    pub cert_file: String,
+    pub state: Arc<Mutex<TlsState>>,
+}
+
+impl TlsCert {
+    pub fn new(key_file: &str, cert_file: &str) -> Self {
+        TlsCert {
+            key_file: String::from(key_file),
+            cert_file: String::from(cert_file),
+            state: Arc::new(Mutex::new(TlsState::new())),
+        }
+    }
+
+    pub fn same_files(&self, other: &TlsCert) -> bool {
+        self.key_file == other.key_file && self.cert_file == other.cert_file
+    }
+}
+
+/// TLS context currently in use, it is replaced when the certificate files change.
+#[derive(Default)]
+pub struct TlsState {
+    context: Option<SslContext>,
+    certificate: Option<CertInfo>,
+    modified: Option<SystemTime>,
+}
+
+impl TlsState {
+    pub fn new() -> Self {
+        TlsState::default()
+    }
+
+    pub fn certificate(&self) -> Option<CertInfo> {
+        self.certificate.clone()
+    }
+}
+
+/// TLS acceptor whose connections use the last loaded TLS context.
+///
+/// The server name callback is called in the handshake of every connection, even if the client
+/// doesn't use SNI, and it swaps the TLS context of the connection for the current one. This way
+/// new certificates are used for new connections while the established ones are not disturbed.
+pub fn ssl_acceptor(cfg: &Config, cert: &TlsCert) -> Result<SslAcceptorBuilder> {
+    ensure_certificate(cert)?;
+
+    let mut builder = ssl_acceptor_builder(cfg, cert)?;
+    reload(cfg, cert)?;
+
+    let tls = Arc::clone(&cert.state);
+    builder.set_servername_callback(move |ssl, _alert| {
+        if let Some(context) = &tls.lock().unwrap().context {
+            ssl.set_ssl_context(context)
+                .map_err(|_| SniError::ALERT_FATAL)?;
+        }
+        Ok(())
+    });
+
+    Ok(builder)
+}
+
+/// Generate a self-signed certificate if neither the private key nor the certificate exist.
+fn ensure_certificate(cert: &TlsCert) -> Result<()> {
+    let key_file = &cert.key_file;
+    let cert_file = &cert.cert_file;
+
+    match (Path::new(key_file).exists(), Path::new(cert_file).exists()) {
+        (true, true) => Ok(()),
+        (false, false) => {
+            let host_name = System::host_name().unwrap_or_else(|| String::from("fwcloud-agent"));
+            warn!("TLS certificate not found, generating a self-signed one for '{host_name}'");
+
+            let cert = generate_self_signed(key_file, cert_file, &host_name)?;
+            info!(
+                "Self-signed TLS certificate generated (sha256 fingerprint: {})",
+                cert.sha256_fingerprint
+            );
+            Ok(())
+        }
+        (false, true) => Err(FwcError::TlsConfig(format!(
+            "Certificate found but its private key file '{key_file}' is missing"
+        ))),
+        (true, false) => Err(FwcError::TlsConfig(format!(
+            "Private key found but its certificate file '{cert_file}' is missing"
+        ))),
+    }
+}
+
+/// Generate a RSA key pair and a self-signed certificate with the host name as common name and
+/// subject alternative name.
+pub fn generate_self_signed(key_file: &str, cert_file: &str, host_name: &str) -> Result<CertInfo> {
+    let pkey = PKey::from_rsa(Rsa::generate(4096)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, host_name)?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+    let serial = Asn1Integer::from_bn(&serial)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(SELF_SIGNED_CERT_DAYS)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&pkey)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+    let san = SubjectAlternativeName::new()
+        .dns(host_name)
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+    builder.sign(&pkey, MessageDigest::sha256())?;
+    let cert = builder.build();
+
+    // The private key must be readable only by the owner.
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(key_file)?;
+    file.write_all(&pkey.private_key_to_pem_pkcs8()?)?;
+    fs::write(cert_file, cert.to_pem()?)?;
+
+    CertInfo::from_x509(&cert)
+}
+
+/// Load again the certificate files. If any of them is not valid the current TLS context is kept.
+pub fn reload(cfg: &Config, cert: &TlsCert) -> Result<()> {
+    let modified = files_modified(cfg, cert);
+    let context = ssl_acceptor_builder(cfg, cert)?.build().into_context();
+    let certificate = CertInfo::from_x509(
+        context
+            .certificate()
+            .ok_or(FwcError::Internal("Server certificate not found"))?,
+    )?;
+
+    info!(
+        "TLS certificate loaded (subject: {}, expires: {}, sha256 fingerprint: {})",
+        certificate.subject, certificate.not_after, certificate.sha256_fingerprint
+    );
+    check_expiry(&certificate);
+
+    debug!("Locking TLS state mutex (thread id: {})", thread_id::get());
+    let mut tls = cert.state.lock().unwrap();
+    tls.context = Some(context);
+    tls.certificate = Some(certificate);
+    tls.modified = modified;
+    debug!(
+        "Releasing TLS state mutex (thread id: {})",
+        thread_id::get()
+    );
+
+    Ok(())
+}
+
+fn check_expiry(certificate: &CertInfo) {
+    if certificate.days_until_expiry < 0 {
+        error!("TLS certificate expired on {}", certificate.not_after);
+    } else if certificate.days_until_expiry <= CERT_EXPIRY_WARNING_DAYS {
+        warn!(
+            "TLS certificate expires in {} days ({})",
+            certificate.days_until_expiry, certificate.not_after
+        );
+    }
+}
+
+// Most recent modification time of the files used by the TLS context.
+fn files_modified(cfg: &Config, cert: &TlsCert) -> Option<SystemTime> {
+    let mut files = vec![cert.key_file.clone(), cert.cert_file.clone()];
+    if cfg.enable_client_cert {
+        files.push(format!("{}/ca.pem", cfg.etc_dir));
+        if cfg.client_cert_crl {
+            files.push(format!("{}/crl.pem", cfg.etc_dir));
+        }
+    }
+
+    files
+        .iter()
+        .filter_map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .max()
+}
+
+/// Reload the TLS context when the certificate files change.
+pub fn start_reloader(cfg: Arc<Config>) {
+    let check_interval = cfg.tls_reload_check_interval;
+    if check_interval == 0 {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(check_interval));
+
+        loop {
+            interval.tick().await;
+            for cert in cfg.tls_certs().iter() {
+                let current = cert.state.lock().unwrap().modified;
+                if files_modified(&cfg, cert) == current {
+                    continue;
+                }
+                info!(
+                    "TLS certificate files changed, reloading them ({})",
+                    cert.cert_file
+                );
+
+                if let Err(e) = reload(&cfg, cert) {
+                    error!("Error reloading TLS certificate, keeping the current one: {e}");
+                }
+            }
+        }
+    });
+}
+
+/// TLS acceptor with the configured profile, minimum protocol version and ciphers.
+pub fn tls_profile(cfg: &Config) -> Result<SslAcceptorBuilder> {
+    let mut builder = match cfg.tls_profile.as_str() {
+        "mozilla_modern" => SslAcceptor::mozilla_modern(SslMethod::tls())?,
+        _ => SslAcceptor::mozilla_intermediate(SslMethod::tls())?,
+    };
+
+    let min_version = match cfg.tls_min_version.as_str() {
+        "1.3" => SslVersion::TLS1_3,
+        _ => SslVersion::TLS1_2,
+    };
+    builder.set_min_proto_version(Some(min_version))?;
+
+    if !cfg.tls_cipher_list.is_empty() {
+        builder.set_cipher_list(&cfg.tls_cipher_list).map_err(|e| {
+            FwcError::TlsConfig(format!(
+                "Invalid TLS_CIPHER_LIST '{}' ({e})",
+                cfg.tls_cipher_list
+            ))
+        })?;
+    }
+    if !cfg.tls_ciphersuites.is_empty() {
+        builder
+            .set_ciphersuites(&cfg.tls_ciphersuites)
+            .map_err(|e| {
+                FwcError::TlsConfig(format!(
+                    "Invalid TLS_CIPHERSUITES '{}' ({e})",
+                    cfg.tls_ciphersuites
+                ))
+            })?;
+    }
+
+    Ok(builder)
+}
+
+fn ssl_acceptor_builder(cfg: &Config, cert: &TlsCert) -> Result<SslAcceptorBuilder> {
+    let mut builder = tls_profile(cfg)?;
+    builder
+        .set_private_key_file(&cert.key_file, SslFiletype::PEM)
+        .map_err(|e| {
+            FwcError::TlsConfig(format!(
+                "Error loading the private key file '{}' ({e})",
+                cert.key_file
+            ))
+        })?;
+    builder
+        .set_certificate_chain_file(&cert.cert_file)
+        .map_err(|e| {
+            FwcError::TlsConfig(format!(
+                "Error loading the certificate chain file '{}' ({e})",
+                cert.cert_file
+            ))
+        })?;
+    builder.check_private_key().map_err(|_| {
+        FwcError::TlsConfig(String::from(
+            "The private key doesn't match the certificate",
+        ))
+    })?;
+
+    if cfg.enable_client_cert {
+        let ca_file = format!("{}/ca.pem", cfg.etc_dir);
+
+        let mut store = X509StoreBuilder::new()?;
+        let lookup = store.add_lookup(X509Lookup::file())?;
+        lookup.load_cert_file(&ca_file, SslFiletype::PEM)?;
+        if cfg.client_cert_crl {
+            lookup.load_crl_file(format!("{}/crl.pem", cfg.etc_dir), SslFiletype::PEM)?;
+            store.set_flags(X509VerifyFlags::CRL_CHECK | X509VerifyFlags::CRL_CHECK_ALL)?;
+        }
+
+        builder.set_verify_cert_store(store.build())?;
+        builder.set_client_ca_list(X509Name::load_client_ca_file(&ca_file)?);
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+
+    Ok(builder)
+}
+
+/// Keep the identity of the client certificate in the connection data, this way it will
+/// be available for the authorization middleware in all the requests of the connection.
+///
+/// Connections to the local Unix socket are marked, they are authorized by the file system permissions of the
+/// socket instead of by the API key.
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(tls) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+        if let Some(cert) = tls.ssl().peer_certificate() {
+            data.insert(ClientCert::from_x509(&cert));
+        }
+    } else if let Some(stream) = connection.downcast_ref::<UnixStream>() {
+        data.insert(UnixPeer {
+            uid: stream.peer_cred().ok().map(|cred| cred.uid()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn cert_factory(cn: &str, san: &[&str]) -> X509 {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        if !san.is_empty() {
+            let mut ext = SubjectAlternativeName::new();
+            for name in san {
+                ext.dns(name);
+            }
+            let ext = ext.build(&builder.x509v3_context(None, None)).unwrap();
+            builder.append_extension(ext).unwrap();
+        }
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+
+        builder.build()
+    }
+
+    #[test]
+    fn extracts_client_cert_names() {
+        let cert = ClientCert::from_x509(&cert_factory(
+            "fwcloud-console",
+            &["console.example.com", "console2.example.com"],
+        ));
+
+        assert_eq!(cert.cn, Some(String::from("fwcloud-console")));
+        assert_eq!(
+            cert.san,
+            vec![
+                String::from("console.example.com"),
+                String::from("console2.example.com")
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_client_cert_names() {
+        let cert = ClientCert::from_x509(&cert_factory("monitoring", &["mon.example.com"]));
+
+        assert!(cert.is_allowed(&[String::from("monitoring")]));
+        assert!(cert.is_allowed(&[String::from("other"), String::from("mon.example.com")]));
+        assert!(!cert.is_allowed(&[String::from("fwcloud-console")]));
+        assert!(!cert.is_allowed(&[]));
+    }
+
+    #[test]
+    fn summarizes_server_cert() {
+        let cert = cert_factory("fwcloud-agent", &[]);
+        let info = CertInfo::from_x509(&cert).unwrap();
+
+        assert_eq!(info.subject, "CN=fwcloud-agent");
+        assert_eq!(info.issuer, "CN=fwcloud-agent");
+        assert!((0..=1).contains(&info.days_until_expiry));
+        assert_eq!(info.sha256_fingerprint.len(), 95);
+        assert_eq!(
+            info.sha256_fingerprint.replace(':', "").to_lowercase(),
+            hex::encode(cert.digest(MessageDigest::sha256()).unwrap())
+        );
+    }
+
+    #[test]
+    fn generates_self_signed_cert() {
+        let dir = std::env::temp_dir().join(format!("fwcloud-agent-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let key_file = dir.join("key.pem").to_string_lossy().to_string();
+        let cert_file = dir.join("cert.pem").to_string_lossy().to_string();
+
+        let info = generate_self_signed(&key_file, &cert_file, "fw1.example.com").unwrap();
+        assert_eq!(info.subject, "CN=fw1.example.com");
+        assert!(info.days_until_expiry >= 3649);
+
+        let cert = X509::from_pem(&fs::read(&cert_file).unwrap()).unwrap();
+        assert_eq!(ClientCert::from_x509(&cert).san, vec!["fw1.example.com"]);
+        let pkey = PKey::private_key_from_pem(&fs::read(&key_file).unwrap()).unwrap();
+        assert!(cert.verify(&pkey).unwrap());
+        assert_eq!(
+            fs::metadata(&key_file).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        // Never overwrite an existing private key.
+        assert!(generate_self_signed(&key_file, &cert_file, "fw1.example.com").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::errors::Result;
+
+// Size of the blocks read from the end of the log files.
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+/// One line of the audit log.
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub peer_ip: Option<IpAddr>,
+    pub key: Option<String>,
+    pub client_cert: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub params: Map<String, Value>,
+    pub status: u16,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    /// There are older entries after this page.
+    pub more: bool,
+}
+
+/// Append-only JSON lines log with size based rotation.
+///
+/// When the log file would exceed its maximum size it is renamed to `<path>.1`, the previous
+/// `<path>.1` to `<path>.2` and so on, up to `max_files` rotated files.
+pub struct AuditLog {
+    path: String,
+}
+
+impl AuditLog {
+    pub fn new(path: String) -> Self {
+        AuditLog { path }
+    }
+
+    pub fn append(&self, entry: &AuditEntry, max_size: u64, max_files: usize) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > max_size {
+            self.rotate(max_files)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Entries of the log, including the rotated files, from the newest to the oldest one.
+    ///
+    /// The files are read backwards and only up to the end of the page, the older entries are not
+    /// read at all.
+    pub fn page(&self, offset: usize, limit: usize, max_files: usize) -> Result<AuditPage> {
+        let mut page = AuditPage {
+            entries: vec![],
+            more: false,
+        };
+
+        let mut read = 0;
+        'files: for path in self.files(max_files).iter() {
+            let file = match File::open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for line in RevLines::new(file, BLOCK_SIZE)? {
+                let line = line?;
+                if read == offset + limit {
+                    page.more = true;
+                    break 'files;
+                }
+                if read >= offset {
+                    if let Ok(entry) = serde_json::from_str(&line) {
+                        page.entries.push(entry);
+                    }
+                }
+                read += 1;
+            }
+        }
+
+        Ok(page)
+    }
+
+    fn rotate(&self, max_files: usize) -> Result<()> {
+        let files = self.files(max_files);
+        for i in (1..files.len()).rev() {
+            if Path::new(&files[i - 1]).is_file() {
+                fs::rename(&files[i - 1], &files[i])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The log file followed by its rotated files.
+    fn files(&self, max_files: usize) -> Vec<String> {
+        let mut files = vec![self.path.clone()];
+        for i in 1..=max_files {
+            files.push(format!("{}.{}", self.path, i));
+        }
+
+        files
+    }
+}
+
+// Non empty lines of a file from the last one to the first one, the file is read backwards in
+// blocks of `block_size` bytes.
+struct RevLines {
+    file: File,
+    pos: u64,
+    block_size: u64,
+    // Start of the file not split into lines yet, from the last read block.
+    pending: Vec<u8>,
+}
+
+impl RevLines {
+    fn new(file: File, block_size: u64) -> io::Result<Self> {
+        Ok(RevLines {
+            pos: file.metadata()?.len(),
+            file,
+            block_size,
+            pending: vec![],
+        })
+    }
+
+    fn read_block(&mut self) -> io::Result<()> {
+        let size = self.block_size.min(self.pos);
+        self.pos -= size;
+        let mut block = vec![0; size as usize];
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        self.file.read_exact(&mut block)?;
+        block.append(&mut self.pending);
+        self.pending = block;
+
+        Ok(())
+    }
+}
+
+impl Iterator for RevLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.pending.iter().rposition(|&b| b == b'\n') {
+                Some(i) => {
+                    let line = self.pending.split_off(i + 1);
+                    self.pending.truncate(i);
+                    line
+                }
+                None if self.pos > 0 => {
+                    if let Err(e) = self.read_block() {
+                        return Some(Err(e));
+                    }
+                    continue;
+                }
+                None if self.pending.is_empty() => return None,
+                None => std::mem::take(&mut self.pending),
+            };
+            if !line.is_empty() {
+                return Some(Ok(String::from_utf8_lossy(&line).into_owned()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn entry_factory(timestamp: u64) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            peer_ip: Some("10.20.30.40".parse().unwrap()),
+            key: Some(String::from("default")),
+            client_cert: None,
+            method: String::from("POST"),
+            route: String::from("/api/v1/plugin"),
+            params: Map::new(),
+            status: 200,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn rotates_and_pages_entries() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", Uuid::new_v4()));
+        let log = AuditLog::new(path.to_string_lossy().to_string());
+
+        // Every entry is a bit less than 200 bytes, only two of them fit in each file.
+        for ts in 0..10 {
+            log.append(&entry_factory(ts), 400, 2).unwrap();
+        }
+
+        let page = log.page(0, 100, 2).unwrap();
+        assert!(!page.more);
+        let timestamps: Vec<u64> = page.entries.iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![9, 8, 7, 6, 5, 4]);
+
+        let page = log.page(1, 2, 2).unwrap();
+        assert!(page.more);
+        let timestamps: Vec<u64> = page.entries.iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![8, 7]);
+
+        let page = log.page(4, 2, 2).unwrap();
+        assert!(!page.more);
+        let timestamps: Vec<u64> = page.entries.iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![5, 4]);
+
+        for file in log.files(2) {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn reads_lines_backwards() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", Uuid::new_v4()));
+        fs::write(&path, "a\nbb\n\nccc\nlonger line\n").unwrap();
+
+        let lines: Vec<String> = RevLines::new(File::open(&path).unwrap(), 4)
+            .unwrap()
+            .map(|line| line.unwrap())
+            .collect();
+        assert_eq!(lines, vec!["longer line", "ccc", "bb", "a"]);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use ipnet::IpNet;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// Upper limit for the amount of sources tracked, this way a scan from many
+// different addresses can not exhaust the memory.
+const MAX_TRACKED_IPS: usize = 65_536;
+
+// IPv6 sources are tracked by network, a single host usually has a whole /64 for itself.
+const IPV6_SOURCE_PREFIX: u8 = 64;
+
+pub struct BanPolicy {
+    /// Failed authentications before banning the source IP (0 disables the bans).
+    pub max_failures: u32,
+    /// Duration in seconds of the first ban, it is doubled for every new ban of the same IP.
+    pub ban_time: u64,
+    pub max_ban_time: u64,
+    /// Seconds without failures after which the IP history is forgotten.
+    pub failures_window: u64,
+}
+
+struct FailureRecord {
+    failures: u32,
+    bans: u32,
+    last_failure: u64,
+    banned_until: u64,
+}
+
+#[derive(Serialize)]
+pub struct Ban {
+    pub ip: IpNet,
+    pub bans: u32,
+    pub banned_until: u64,
+    pub remaining_seconds: u64,
+}
+
+/// Per source failed authentications counter with exponential backoff bans. The source is the
+/// IPv4 address or the /64 network of the IPv6 address.
+#[derive(Default)]
+pub struct AuthFailures {
+    records: HashMap<IpNet, FailureRecord>,
+}
+
+fn source(ip: IpAddr) -> IpNet {
+    let prefix = match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => IPV6_SOURCE_PREFIX,
+    };
+
+    IpNet::new(ip, prefix).unwrap().trunc()
+}
+
+impl AuthFailures {
+    pub fn new() -> Self {
+        AuthFailures::default()
+    }
+
+    pub fn is_banned(&self, ip: IpAddr, now: u64) -> bool {
+        self.records
+            .get(&source(ip))
+            .map(|r| r.banned_until > now)
+            .unwrap_or(false)
+    }
+
+    /// Register a failed authentication. Returns the ban duration if the IP has been banned.
+    pub fn failure(&mut self, ip: IpAddr, now: u64, policy: &BanPolicy) -> Option<u64> {
+        if policy.max_failures == 0 {
+            return None;
+        }
+
+        let ip = source(ip);
+        if self.records.len() >= MAX_TRACKED_IPS && !self.records.contains_key(&ip) {
+            self.purge(now, policy);
+            // Make room forgetting the oldest failures, the sources already banned are kept.
+            if self.records.len() >= MAX_TRACKED_IPS && !self.evict_oldest(now) {
+                return None;
+            }
+        }
+
+        let record = self.records.entry(ip).or_insert(FailureRecord {
+            failures: 0,
+            bans: 0,
+            last_failure: now,
+            banned_until: 0,
+        });
+
+        if Self::expired(record, now, policy) {
+            record.failures = 0;
+            record.bans = 0;
+        }
+
+        record.failures += 1;
+        record.last_failure = now;
+
+        if record.failures < policy.max_failures {
+            return None;
+        }
+
+        // Exponential backoff: every new ban of the same IP doubles the previous one.
+        let ban_time = policy
+            .ban_time
+            .saturating_mul(1u64 << record.bans.min(32))
+            .min(policy.max_ban_time);
+        record.failures = 0;
+        record.bans += 1;
+        record.banned_until = now + ban_time;
+
+        Some(ban_time)
+    }
+
+    /// A successful authentication forgets the failures history of the IP.
+    pub fn success(&mut self, ip: IpAddr) {
+        self.records.remove(&source(ip));
+    }
+
+    pub fn bans(&self, now: u64) -> Vec<Ban> {
+        let mut bans: Vec<Ban> = self
+            .records
+            .iter()
+            .filter(|(_, r)| r.banned_until > now)
+            .map(|(ip, r)| Ban {
+                ip: *ip,
+                bans: r.bans,
+                banned_until: r.banned_until,
+                remaining_seconds: r.banned_until - now,
+            })
+            .collect();
+        bans.sort_by_key(|b| b.banned_until);
+
+        bans
+    }
+
+    fn expired(record: &FailureRecord, now: u64, policy: &BanPolicy) -> bool {
+        now >= record.last_failure.max(record.banned_until) + policy.failures_window
+    }
+
+    fn purge(&mut self, now: u64, policy: &BanPolicy) {
+        self.records
+            .retain(|_, record| !Self::expired(record, now, policy));
+    }
+
+    fn evict_oldest(&mut self, now: u64) -> bool {
+        let oldest = self
+            .records
+            .iter()
+            .filter(|(_, record)| record.banned_until <= now)
+            .min_by_key(|(_, record)| record.last_failure)
+            .map(|(ip, _)| *ip);
+
+        match oldest {
+            Some(ip) => self.records.remove(&ip).is_some(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: BanPolicy = BanPolicy {
+        max_failures: 3,
+        ban_time: 60,
+        max_ban_time: 200,
+        failures_window: 600,
+    };
+
+    #[test]
+    fn bans_after_max_failures() {
+        let mut failures = AuthFailures::new();
+        let ip: IpAddr = "10.20.30.40".parse().unwrap();
+
+        assert_eq!(failures.failure(ip, 1000, &POLICY), None);
+        assert_eq!(failures.failure(ip, 1001, &POLICY), None);
+        assert!(!failures.is_banned(ip, 1001));
+        assert_eq!(failures.failure(ip, 1002, &POLICY), Some(60));
+        assert!(failures.is_banned(ip, 1002));
+        assert!(failures.is_banned(ip, 1061));
+        assert!(!failures.is_banned(ip, 1062));
+        assert!(!failures.is_banned("10.20.30.41".parse().unwrap(), 1002));
+
+        let bans = failures.bans(1002);
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].ip, "10.20.30.40/32".parse::<IpNet>().unwrap());
+        assert_eq!(bans[0].remaining_seconds, 60);
+    }
+
+    #[test]
+    fn ban_time_grows_exponentially() {
+        let mut failures = AuthFailures::new();
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let mut now = 1000;
+        for expected in [60, 120, 200, 200] {
+            failures.failure(ip, now, &POLICY);
+            failures.failure(ip, now, &POLICY);
+            assert_eq!(failures.failure(ip, now, &POLICY), Some(expected));
+            now += expected;
+        }
+    }
+
+    #[test]
+    fn success_and_window_reset_failures() {
+        let mut failures = AuthFailures::new();
+        let ip: IpAddr = "10.20.30.40".parse().unwrap();
+
+        failures.failure(ip, 1000, &POLICY);
+        failures.failure(ip, 1000, &POLICY);
+        failures.success(ip);
+        assert_eq!(failures.failure(ip, 1000, &POLICY), None);
+
+        failures.failure(ip, 1000, &POLICY);
+        assert_eq!(failures.failure(ip, 1600, &POLICY), None);
+    }
+
+    #[test]
+    fn bans_ipv6_sources_by_network() {
+        let mut failures = AuthFailures::new();
+
+        for host in 1..=3 {
+            let ip: IpAddr = format!("2001:db8:0:1::{host}").parse().unwrap();
+            failures.failure(ip, 1000, &POLICY);
+        }
+        assert!(failures.is_banned("2001:db8:0:1::ffff".parse().unwrap(), 1000));
+        assert!(!failures.is_banned("2001:db8:0:2::1".parse().unwrap(), 1000));
+        assert_eq!(
+            failures.bans(1000)[0].ip,
+            "2001:db8:0:1::/64".parse::<IpNet>().unwrap()
+        );
+    }
+
+    #[test]
+    fn tracks_new_sources_with_full_table() {
+        let mut failures = AuthFailures::new();
+        let banned: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..3 {
+            failures.failure(banned, 1000, &POLICY);
+        }
+        for n in 1..MAX_TRACKED_IPS as u32 {
+            let ip = IpAddr::from(std::net::Ipv4Addr::from(0x0b00_0000 + n));
+            failures.failure(ip, 1000 + n as u64 % 50, &POLICY);
+        }
+        assert_eq!(failures.records.len(), MAX_TRACKED_IPS);
+
+        // The oldest failures not banned are forgotten, the new source is still banned.
+        let ip: IpAddr = "192.168.1.1".parse().unwrap();
+        assert_eq!(failures.failure(ip, 1050, &POLICY), None);
+        assert_eq!(failures.failure(ip, 1050, &POLICY), None);
+        assert_eq!(failures.failure(ip, 1050, &POLICY), Some(60));
+        assert_eq!(failures.records.len(), MAX_TRACKED_IPS);
+        assert!(failures.is_banned(banned, 1050));
+    }
+}
@@ -20,36 +20,361 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use actix_web::{http::header, HttpResponse};
+use actix_web::{http::header, web, HttpResponse};
 use log::debug;
-use log::error;
+use log::{error, warn};
+use std::io;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::{thread, time::Duration};
-use subprocess::{Exec, Redirection};
+use subprocess::{ExitStatus, Popen, PopenConfig, Redirection};
 
+use crate::config::Config;
 use crate::errors::{FwcError, Result};
+use crate::utils::jobs::JobRun;
 use crate::utils::ws::WsData;
 
-pub fn run_cmd(cmd: &str, args: &[&str]) -> Result<HttpResponse> {
-    let output = Exec::cmd(cmd)
-        .args(args)
-        .stdout(Redirection::Pipe)
-        .stderr(Redirection::Merge)
-        .capture()?;
+// Interval for checking if a job has been cancelled while its command runs.
+const JOB_CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(200);
 
-    if !output.exit_status.success() && cmd != "systemctl" {
+// Time given to the processes of a timed out or cancelled command for finishing after SIGTERM
+// before killing them.
+const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+const TERMINATE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Output of a command for the HTTP response, `None` if the output was streamed to a WebSocket.
+///
+/// Unlike `HttpResponse` it can be returned from the blocking thread pool where the commands run.
+pub struct CmdOutput(Option<String>);
+
+impl From<CmdOutput> for HttpResponse {
+    fn from(output: CmdOutput) -> Self {
+        match output.0 {
+            Some(stdout) => {
+                let mut res = HttpResponse::Ok().body(stdout);
+                res.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    header::HeaderValue::from_static("text/plain"),
+                );
+                res
+            }
+            None => HttpResponse::Ok().finish(),
+        }
+    }
+}
+
+/// Run commands in the blocking thread pool, this way the actix worker that serves the request
+/// can serve other ones meanwhile.
+pub async fn run_blocking<F>(f: F) -> Result<HttpResponse>
+where
+    F: FnOnce() -> Result<CmdOutput> + Send + 'static,
+{
+    Ok(web::block(f).await??.into())
+}
+
+/// Type of operation of a command, each one has its own default timeout.
+#[derive(Clone, Copy)]
+pub enum CmdKind {
+    Plugin,
+    FwcloudScript,
+    Systemctl,
+    Other,
+}
+
+impl CmdKind {
+    /// Timeout for a command of this type, `requested` overrides the configured one. A timeout of 0
+    /// seconds means no timeout.
+    pub fn timeout(self, cfg: &Config, requested: Option<u64>) -> Option<Duration> {
+        let secs = requested.unwrap_or(match self {
+            CmdKind::Plugin => cfg.plugin_timeout,
+            CmdKind::FwcloudScript => cfg.fwcloud_script_timeout,
+            CmdKind::Systemctl => cfg.systemctl_timeout,
+            CmdKind::Other => cfg.cmd_timeout,
+        });
+
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+// Running command. It is the leader of its own process group, this way the processes it starts
+// are terminated with it when it times out or is cancelled.
+struct Child {
+    cmd: String,
+    popen: Popen,
+    pgid: libc::pid_t,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl Child {
+    fn spawn(
+        cmd: &str,
+        args: &[&str],
+        stdin: Redirection,
+        stdout: Redirection,
+        stderr: Redirection,
+        timeout: Option<Duration>,
+    ) -> Result<Child> {
+        let mut argv = vec![cmd];
+        argv.extend_from_slice(args);
+        let popen = Popen::create(
+            &argv,
+            PopenConfig {
+                stdin,
+                stdout,
+                stderr,
+                setpgid: true,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(Child {
+            cmd: String::from(cmd),
+            pgid: popen.pid().unwrap_or_default() as libc::pid_t,
+            popen,
+            timeout,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        })
+    }
+
+    // Time left before the timeout, `None` if the command has no timeout.
+    fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    // Wait for the command to finish, it is terminated if the timeout expires.
+    fn wait(&mut self) -> Result<ExitStatus> {
+        match self.remaining() {
+            Some(remaining) => match self.popen.wait_timeout(remaining)? {
+                Some(status) => Ok(status),
+                None => Err(self.timed_out()),
+            },
+            None => Ok(self.popen.wait()?),
+        }
+    }
+
+    // Terminate the command and the rest of the processes of its group, the ones still running
+    // after the grace period are killed.
+    fn terminate(&mut self) -> Result<()> {
+        if self.pgid <= 0 {
+            return Err(FwcError::Internal("Process group of the command not known"));
+        }
+
+        debug!("Sending SIGTERM to the process group {}", self.pgid);
+        unsafe { libc::killpg(self.pgid, libc::SIGTERM) };
+
+        let deadline = Instant::now() + TERMINATE_GRACE_PERIOD;
+        while Instant::now() < deadline {
+            if self.popen.wait_timeout(TERMINATE_CHECK_INTERVAL)?.is_some() {
+                // Once the leader is gone, wait for the rest of the group.
+                if unsafe { libc::killpg(self.pgid, 0) } != 0 {
+                    return Ok(());
+                }
+                thread::sleep(TERMINATE_CHECK_INTERVAL);
+            }
+        }
+
+        warn!(
+            "Process group {} still running after SIGTERM, killing it",
+            self.pgid
+        );
+        unsafe { libc::killpg(self.pgid, libc::SIGKILL) };
+        self.popen.wait()?;
+
+        Ok(())
+    }
+
+    // Terminate the command because its timeout expired and return the error to report.
+    fn timed_out(&mut self) -> FwcError {
+        let secs = self.timeout.unwrap_or_default().as_secs();
+        error!(
+            "Error: Command '{}' timed out after {} seconds",
+            self.cmd, secs
+        );
+        if let Err(e) = self.terminate() {
+            error!("Error terminating the command '{}': {}", self.cmd, e);
+        }
+
+        FwcError::CmdTimeout(secs)
+    }
+
+    // Read all the output of the command and wait for it to finish.
+    fn capture(&mut self, input: Option<&str>) -> Result<(ExitStatus, String, String)> {
+        let mut communicator = self
+            .popen
+            .communicate_start(input.map(|input| input.as_bytes().to_vec()));
+        if let Some(remaining) = self.remaining() {
+            communicator = communicator.limit_time(remaining);
+        }
+
+        let (stdout, stderr) = match communicator.read() {
+            Ok(data) => data,
+            Err(e) if e.error.kind() == io::ErrorKind::TimedOut => return Err(self.timed_out()),
+            Err(e) => return Err(e.error.into()),
+        };
+        let to_string =
+            |data: Option<Vec<u8>>| String::from_utf8_lossy(&data.unwrap_or_default()).to_string();
+
+        Ok((self.wait()?, to_string(stdout), to_string(stderr)))
+    }
+}
+
+pub fn run_cmd(cmd: &str, args: &[&str], timeout: Option<Duration>) -> Result<CmdOutput> {
+    let (status, stdout, _) = Child::spawn(
+        cmd,
+        args,
+        Redirection::None,
+        Redirection::Pipe,
+        Redirection::Merge,
+        timeout,
+    )?
+    .capture(None)?;
+
+    if !status.success() && cmd != "systemctl" {
         // If the process doesn't exits with exit status 0.
         error!("Error: Command exit status not 0");
         return Err(FwcError::CmdExitStatusNotZero);
     }
 
-    let mut res = HttpResponse::Ok().body(output.stdout_str());
-    res.headers_mut().insert(
-        header::CONTENT_TYPE,
-        header::HeaderValue::from_static("text/plain"),
-    );
+    Ok(CmdOutput(Some(stdout)))
+}
 
-    Ok(res)
+/// Run a command and return its standard output, for commands whose output we parse.
+pub fn run_cmd_output(cmd: &str, args: &[&str], timeout: Option<Duration>) -> Result<String> {
+    let (status, stdout, stderr) = Child::spawn(
+        cmd,
+        args,
+        Redirection::None,
+        Redirection::Pipe,
+        Redirection::Pipe,
+        timeout,
+    )?
+    .capture(None)?;
+
+    if !status.success() {
+        error!("Error: Command exit status not 0 ({})", stderr.trim());
+        return Err(FwcError::CmdExitStatusNotZero);
+    }
+
+    Ok(stdout)
+}
+
+/// Run a command feeding `input` to its standard input, like iptables-restore.
+pub fn run_cmd_input(
+    cmd: &str,
+    args: &[&str],
+    input: &str,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let (status, stdout, _) = Child::spawn(
+        cmd,
+        args,
+        Redirection::Pipe,
+        Redirection::Pipe,
+        Redirection::Merge,
+        timeout,
+    )?
+    .capture(Some(input))?;
+
+    if !status.success() {
+        error!("Error: Command exit status not 0 ({})", stdout.trim());
+        return Err(FwcError::CmdExitStatusNotZero);
+    }
+
+    Ok(())
+}
+
+/// Run a command and return its exit status, standard output and standard error, whatever the exit
+/// status is.
+pub fn run_cmd_status(
+    cmd: &str,
+    args: &[&str],
+    timeout: Option<Duration>,
+) -> Result<(ExitStatus, String, String)> {
+    Child::spawn(
+        cmd,
+        args,
+        Redirection::None,
+        Redirection::Pipe,
+        Redirection::Pipe,
+        timeout,
+    )?
+    .capture(None)
+}
+
+/// Unified diff between two files, empty if they are equal.
+pub fn run_diff(
+    from: &str,
+    to: &str,
+    from_label: &str,
+    to_label: &str,
+    timeout: Option<Duration>,
+) -> Result<String> {
+    let (status, stdout, stderr) = run_cmd_status(
+        "diff",
+        &["-u", "--label", from_label, "--label", to_label, from, to],
+        timeout,
+    )?;
+
+    // Exit status 1 means that there are differences.
+    match status {
+        ExitStatus::Exited(0) | ExitStatus::Exited(1) => Ok(stdout),
+        _ => {
+            error!("Error: diff failed ({})", stderr.trim());
+            Err(FwcError::CmdExitStatusNotZero)
+        }
+    }
+}
+
+/// Run a command of a job with its output appended to the job output, the command is terminated if
+/// the job is cancelled or the timeout expires.
+pub fn run_cmd_job(
+    cmd: &str,
+    args: &[&str],
+    job: &mut JobRun,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    if job.cancelled() {
+        return Err(FwcError::JobCancelled);
+    }
+
+    let mut child = Child::spawn(
+        cmd,
+        args,
+        Redirection::None,
+        Redirection::File(job.output_file()?),
+        Redirection::Merge,
+        timeout,
+    )?;
+
+    let status = loop {
+        let interval = child
+            .remaining()
+            .map_or(JOB_CANCEL_CHECK_INTERVAL, |remaining| {
+                remaining.min(JOB_CANCEL_CHECK_INTERVAL)
+            });
+        if let Some(status) = child.popen.wait_timeout(interval)? {
+            break status;
+        }
+        if job.cancelled() {
+            child.terminate()?;
+            return Err(FwcError::JobCancelled);
+        }
+        if child.remaining() == Some(Duration::ZERO) {
+            return Err(child.timed_out());
+        }
+    };
+
+    if let ExitStatus::Exited(code) = status {
+        job.set_exit_code(code as i32);
+    }
+    if !status.success() && cmd != "systemctl" {
+        error!("Error: Command exit status not 0");
+        return Err(FwcError::CmdExitStatusNotZero);
+    }
+
+    Ok(())
 }
 
 pub fn run_cmd_ws(
@@ -57,14 +382,18 @@ pub fn run_cmd_ws(
     args: &[&str],
     ws_data: &Arc<Mutex<WsData>>,
     finish_ws: bool,
-) -> Result<HttpResponse> {
-    let popen = Exec::cmd(cmd)
-        .args(args)
-        .stdout(Redirection::Pipe)
-        .stderr(Redirection::Merge) // Redirect stderr too stdout.
-        .popen();
-
-    let mut popen = match popen {
+    timeout: Option<Duration>,
+) -> Result<CmdOutput> {
+    let child = Child::spawn(
+        cmd,
+        args,
+        Redirection::None,
+        Redirection::Pipe,
+        Redirection::Merge, // Redirect stderr too stdout.
+        timeout,
+    );
+
+    let mut child = match child {
         Ok(data) => data,
         Err(e) => {
             error!("Error: {}", e);
@@ -72,13 +401,25 @@ pub fn run_cmd_ws(
         }
     };
 
-    let mut communicator = popen.communicate_start(Option::None).limit_size(1); // IMPORTANT: Read the output byte by byte.
+    let mut communicator = child.popen.communicate_start(Option::None).limit_size(1); // IMPORTANT: Read the output byte by byte.
 
     let mut previous_char_is_cr = false;
     let mut line_u8: Vec<u8> = Vec::new();
     loop {
+        if let Some(remaining) = child.remaining() {
+            communicator = communicator.limit_time(remaining);
+        }
+
         let (stdout, _stderr) = match communicator.read() {
             Ok(data) => data,
+            Err(e) if e.error.kind() == io::ErrorKind::TimedOut => {
+                if finish_ws {
+                    debug!("Locking ws data mutex (thread id: {})", thread_id::get());
+                    ws_data.lock().unwrap().finished = true;
+                    debug!("Releasing ws data mutex (thread id: {})", thread_id::get());
+                }
+                return Err(child.timed_out());
+            }
             Err(e) => {
                 error!("Subprocess communication error: {}", e);
                 break;
@@ -130,8 +471,8 @@ pub fn run_cmd_ws(
         previous_char_is_cr = c == 13;
     }
 
-    if popen.wait()?.success() {
-        Ok(HttpResponse::Ok().finish())
+    if child.wait()?.success() {
+        Ok(CmdOutput(None))
     } else {
         error!("Error: Command exit status not 0");
 
@@ -142,3 +483,43 @@ pub fn run_cmd_ws(
         Err(FwcError::CmdExitStatusNotZero)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // The process is gone, or it is a zombie waiting for being reaped by init.
+    fn finished(pid: &str) -> bool {
+        match fs::read_to_string(format!("/proc/{pid}/stat")) {
+            Ok(stat) => stat.split_whitespace().nth(2) == Some("Z"),
+            Err(_) => true,
+        }
+    }
+
+    #[test]
+    fn terminates_the_process_group_on_timeout() {
+        let pid_file = std::env::temp_dir().join(format!("fwcloud-agent-{}", uuid::Uuid::new_v4()));
+        let script = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+
+        let started = Instant::now();
+        let res = run_cmd_output("sh", &["-c", &script], Some(Duration::from_secs(1)));
+        assert!(matches!(res, Err(FwcError::CmdTimeout(1))));
+        assert!(started.elapsed() < TERMINATE_GRACE_PERIOD);
+
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        assert!(finished(pid.trim()));
+        fs::remove_file(&pid_file).unwrap();
+    }
+
+    #[test]
+    fn runs_commands_before_the_timeout() {
+        let output = run_cmd_output("echo", &["hello"], Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(output, "hello\n");
+        assert!(run_cmd_input("cat", &[], "hello", None).is_ok());
+        assert!(matches!(
+            run_cmd_output("false", &[], None),
+            Err(FwcError::CmdExitStatusNotZero)
+        ));
+    }
+}
@@ -0,0 +1,427 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::config_file::{dotenv_path, Settings, DEFAULT_CONFIG_FILE};
+use crate::errors::{FwcError, Result};
+
+/// Configuration file replaced through the API that has not been confirmed yet.
+///
+/// It is also saved in data_dir/pending_config.json, this way it is rolled back even if the agent is restarted
+/// before confirming it.
+#[derive(Serialize, Deserialize)]
+pub struct PendingConfig {
+    id: Uuid,
+    target: String,
+    backup: Option<String>,
+    // Time (seconds since the epoch) when it is rolled back if it is not confirmed.
+    deadline: u64,
+}
+
+/// Applied configuration change, it must be confirmed before `rollback_timeout` seconds.
+#[derive(Serialize)]
+pub struct ConfigUpdate {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub file: String,
+    pub rollback_timeout: u64,
+}
+
+// Path of the file replaced by an uploaded configuration document.
+fn target_file(cfg: &Config, name: &str) -> Result<String> {
+    match name {
+        ".env" => Ok(dotenv_path()?.to_string_lossy().to_string()),
+        "agent.toml" => Ok(cfg
+            .config_file
+            .clone()
+            .unwrap_or_else(|| String::from(DEFAULT_CONFIG_FILE))),
+        _ => Err(FwcError::NotExpectedFileName),
+    }
+}
+
+/// Validate a configuration document (`.env` or `agent.toml`) with the same rules used when the agent starts.
+///
+/// Its live options are applied right away, but its listeners and client certificate settings only on restart, so
+/// the access policy is checked against the running ones too.
+pub fn check(cfg: &Config, name: &str, data: &str) -> Result<()> {
+    let settings = match name {
+        ".env" => Settings::with_dotenv(cfg.config_file.as_deref(), data),
+        "agent.toml" => Settings::with_file(data),
+        _ => return Err(FwcError::NotExpectedFileName),
+    };
+
+    settings
+        .and_then(|settings| Config::from_settings(settings, cfg.config_file.as_deref()))
+        .and_then(|new_cfg| {
+            new_cfg.check_access_policy()?;
+            cfg.check_live_access_policy(&new_cfg.live())
+        })
+        .map_err(|e| FwcError::ConfigNotValid(e.to_string()))?;
+
+    Ok(())
+}
+
+/// Validate and install a configuration document, keeping a backup of the previous file, and reload the
+/// configuration.
+///
+/// The change stays pending until it is confirmed, if it is not confirmed in time it is rolled back.
+pub fn apply(cfg: &Config, name: &str, data: &str) -> Result<ConfigUpdate> {
+    let target = target_file(cfg, name)?;
+    check(cfg, name, data)?;
+
+    debug!(
+        "Locking pending config mutex (thread id: {})",
+        thread_id::get()
+    );
+    let mut pending = cfg.pending_config.lock().unwrap();
+    if pending.is_some() {
+        return Err(FwcError::ConfigChangePending);
+    }
+
+    let backup = if Path::new(&target).is_file() {
+        let backup = format!("{target}.bak");
+        fs::copy(&target, &backup)?;
+        Some(backup)
+    } else {
+        None
+    };
+    let change = PendingConfig {
+        id: Uuid::new_v4(),
+        target,
+        backup,
+        deadline: now() + cfg.config_rollback_timeout,
+    };
+
+    if let Err(e) = save_pending(cfg, &change)
+        .and_then(|_| write_file(&change.target, data))
+        .and_then(|_| cfg.reload())
+    {
+        restore(cfg, &change);
+        return Err(e);
+    }
+    info!(
+        "Configuration file '{}' replaced, waiting for confirmation",
+        change.target
+    );
+
+    let update = ConfigUpdate {
+        id: change.id,
+        file: change.target.clone(),
+        rollback_timeout: cfg.config_rollback_timeout,
+    };
+    *pending = Some(change);
+    debug!(
+        "Releasing pending config mutex (thread id: {})",
+        thread_id::get()
+    );
+
+    Ok(update)
+}
+
+/// Confirm the pending configuration change, its backup is kept.
+pub fn confirm(cfg: &Config) -> Result<()> {
+    debug!(
+        "Locking pending config mutex (thread id: {})",
+        thread_id::get()
+    );
+    let change = cfg
+        .pending_config
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or(FwcError::NoConfigChangePending)?;
+    debug!(
+        "Releasing pending config mutex (thread id: {})",
+        thread_id::get()
+    );
+    remove_pending(cfg);
+
+    info!("Configuration file '{}' change confirmed", change.target);
+    Ok(())
+}
+
+/// Roll back the configuration change `id` after `timeout` seconds if it has not been confirmed.
+pub fn schedule_rollback(cfg: &Arc<Config>, id: Uuid, timeout: u64) {
+    let cfg = Arc::clone(cfg);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(timeout)).await;
+        rollback(&cfg, id);
+    });
+}
+
+/// Take again the configuration change left pending by a previous run of the agent, it is rolled back when its
+/// deadline expires as if the agent had not been restarted.
+pub fn recover(cfg: &Arc<Config>) {
+    let change = match fs::read_to_string(pending_file(cfg)) {
+        Ok(data) => match serde_json::from_str::<PendingConfig>(&data) {
+            Ok(change) => change,
+            Err(e) => {
+                error!("Error reading the pending configuration change: {e}");
+                return;
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Error reading the pending configuration change: {e}");
+            return;
+        }
+    };
+
+    let remaining = change.deadline.saturating_sub(now());
+    warn!(
+        "Configuration file '{}' change not confirmed before the restart, rolling it back in {remaining} seconds",
+        change.target
+    );
+    let id = change.id;
+    *cfg.pending_config.lock().unwrap() = Some(change);
+    schedule_rollback(cfg, id, remaining);
+}
+
+/// Roll back the configuration change `id` if it is still pending of confirmation.
+pub fn rollback(cfg: &Config, id: Uuid) -> bool {
+    debug!(
+        "Locking pending config mutex (thread id: {})",
+        thread_id::get()
+    );
+    let mut pending = cfg.pending_config.lock().unwrap();
+    let change = match pending.take_if(|change| change.id == id) {
+        Some(change) => change,
+        None => return false,
+    };
+
+    warn!(
+        "Configuration file '{}' change not confirmed, rolling it back",
+        change.target
+    );
+    restore(cfg, &change);
+    remove_pending(cfg);
+    debug!(
+        "Releasing pending config mutex (thread id: {})",
+        thread_id::get()
+    );
+
+    true
+}
+
+// Put back the previous configuration file and reload it.
+fn restore(cfg: &Config, change: &PendingConfig) {
+    let res = match &change.backup {
+        Some(backup) => fs::copy(backup, &change.target).map(|_| ()),
+        None if Path::new(&change.target).exists() => fs::remove_file(&change.target),
+        None => Ok(()),
+    };
+
+    if let Err(e) = res.map_err(FwcError::from).and_then(|_| cfg.reload()) {
+        error!(
+            "Error restoring the configuration file '{}': {e}",
+            change.target
+        );
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn pending_file(cfg: &Config) -> String {
+    format!("{}/pending_config.json", cfg.data_dir)
+}
+
+fn save_pending(cfg: &Config, change: &PendingConfig) -> Result<()> {
+    write_file(&pending_file(cfg), &serde_json::to_string(change)?)
+}
+
+fn remove_pending(cfg: &Config) {
+    if let Err(e) = fs::remove_file(pending_file(cfg)) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!("Error removing the pending configuration change: {e}");
+        }
+    }
+}
+
+// Replace the file atomically, configuration files can contain secrets so only the owner can read them.
+fn write_file(path: &str, data: &str) -> Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp_path = format!("{path}.new");
+    let _ = fs::remove_file(&tmp_path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipnet::IpNet;
+    use serial_test::serial;
+
+    fn toml(dir: &Path, allowed_ips: &str) -> String {
+        format!(
+            "[paths]\ndata_dir = \"{}/data\"\n[listener]\nbind_ip = \"127.0.0.1\"\n[auth]\nallowed_ips = [\"{allowed_ips}\"]\n",
+            dir.display()
+        )
+    }
+
+    #[test]
+    #[serial]
+    fn applies_and_rolls_back_config_file() {
+        let dir = std::env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("agent.toml");
+        fs::write(&config_file, toml(&dir, "10.0.0.0/8")).unwrap();
+        let cfg = Config::load(config_file.to_str()).unwrap();
+
+        assert!(matches!(
+            apply(&cfg, "agent.toml", "[listener]\nbind_port = 0\n"),
+            Err(FwcError::ConfigNotValid(_))
+        ));
+        assert!(matches!(
+            apply(&cfg, "other.toml", ""),
+            Err(FwcError::NotExpectedFileName)
+        ));
+
+        let update = apply(&cfg, "agent.toml", &toml(&dir, "192.168.0.0/16")).unwrap();
+        assert_eq!(update.file, config_file.to_str().unwrap());
+        assert_eq!(
+            cfg.live().allowed_ips,
+            vec!["192.168.0.0/16".parse::<IpNet>().unwrap()]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("agent.toml.bak")).unwrap(),
+            toml(&dir, "10.0.0.0/8")
+        );
+        assert!(matches!(
+            apply(&cfg, "agent.toml", &toml(&dir, "172.16.0.0/12")),
+            Err(FwcError::ConfigChangePending)
+        ));
+
+        assert!(!rollback(&cfg, Uuid::new_v4()));
+        assert!(rollback(&cfg, update.id));
+        assert_eq!(
+            fs::read_to_string(&config_file).unwrap(),
+            toml(&dir, "10.0.0.0/8")
+        );
+        assert_eq!(
+            cfg.live().allowed_ips,
+            vec!["10.0.0.0/8".parse::<IpNet>().unwrap()]
+        );
+
+        let update = apply(&cfg, "agent.toml", &toml(&dir, "192.168.0.0/16")).unwrap();
+        assert!(Path::new(&pending_file(&cfg)).exists());
+        confirm(&cfg).unwrap();
+        assert!(!Path::new(&pending_file(&cfg)).exists());
+        assert!(!rollback(&cfg, update.id));
+        assert!(matches!(
+            confirm(&cfg),
+            Err(FwcError::NoConfigChangePending)
+        ));
+        assert_eq!(
+            fs::read_to_string(&config_file).unwrap(),
+            toml(&dir, "192.168.0.0/16")
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn refuses_config_file_without_access_control_until_restart() {
+        let dir = std::env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("agent.toml");
+        let data = |auth: &str| {
+            format!(
+                "[paths]\ndata_dir = \"{}/data\"\n[listener]\nbind_ip = \"0.0.0.0\"\n{auth}",
+                dir.display()
+            )
+        };
+        fs::write(&config_file, data("")).unwrap();
+        let cfg = Config::load(config_file.to_str()).unwrap();
+
+        // The client certificates are only enabled on restart, meanwhile the agent would have no API key.
+        assert!(matches!(
+            apply(
+                &cfg,
+                "agent.toml",
+                &data("[tls]\nclient_cert = true\n[auth]\nenable_api_key = false\n")
+            ),
+            Err(FwcError::ConfigNotValid(_))
+        ));
+        assert!(cfg.live().enable_api_key);
+        assert_eq!(fs::read_to_string(&config_file).unwrap(), data(""));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn rolls_back_config_change_pending_before_restart() {
+        let dir = std::env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("agent.toml");
+        fs::write(&config_file, toml(&dir, "10.0.0.0/8")).unwrap();
+        let cfg = Config::load(config_file.to_str()).unwrap();
+        apply(&cfg, "agent.toml", &toml(&dir, "192.168.0.0/16")).unwrap();
+
+        // The agent is restarted with the new configuration after the rollback deadline.
+        let mut change: PendingConfig =
+            serde_json::from_str(&fs::read_to_string(pending_file(&cfg)).unwrap()).unwrap();
+        change.deadline = 0;
+        save_pending(&cfg, &change).unwrap();
+        let cfg = Arc::new(Config::load(config_file.to_str()).unwrap());
+        recover(&cfg);
+        assert!(cfg.pending_config.lock().unwrap().is_some());
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(cfg.pending_config.lock().unwrap().is_none());
+        assert!(!Path::new(&pending_file(&cfg)).exists());
+        assert_eq!(
+            cfg.live().allowed_ips,
+            vec!["10.0.0.0/8".parse::<IpNet>().unwrap()]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
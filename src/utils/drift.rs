@@ -0,0 +1,287 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::errors::Result;
+use crate::utils::cmd::{run_diff, CmdKind};
+use crate::utils::policy_apply::Snapshot;
+
+/*
+  Drift detection between the ruleset installed by FWCloud and the running one.
+
+  Right after a FWCloud script is started the normalized ruleset (without comments and counters) is
+  saved as baseline in data_dir/ruleset_baseline. The drift detector worker compares it periodically
+  with the running ruleset for detecting changes made by hand.
+*/
+
+/// Result of the last comparison between the baseline and the running ruleset.
+#[derive(Clone, Default, Serialize)]
+pub struct DriftStatus {
+    pub baseline_timestamp: Option<u64>,
+    pub baseline_fingerprint: Option<String>,
+    pub last_check: Option<u64>,
+    pub fingerprint: Option<String>,
+    pub drift: bool,
+    /// Unified diff from the baseline to the running ruleset.
+    pub diff: String,
+    pub error: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn baseline_file(cfg: &Config) -> String {
+    format!("{}/ruleset_baseline", cfg.data_dir)
+}
+
+fn fingerprint(ruleset: &str) -> String {
+    format!("{:x}", Sha256::digest(ruleset.as_bytes()))
+}
+
+/// Ruleset without the comments, the packet and byte counters and the timeouts of the dynamic set
+/// elements, which change without anybody modifying the ruleset.
+fn normalize(snapshot: &Snapshot) -> String {
+    let mut res = String::new();
+    let sections = [
+        ("iptables", &snapshot.iptables),
+        ("ip6tables", &snapshot.ip6tables),
+        ("nftables", &snapshot.nftables),
+    ];
+
+    for (name, output) in sections {
+        let Some(output) = output else { continue };
+        res.push_str(&format!("### {name}\n"));
+
+        for line in output.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            // iptables-save: "[12:720] -A INPUT ..." and ":INPUT DROP [10:600]".
+            let line = match line.strip_prefix('[').and_then(|l| l.split_once("] ")) {
+                Some((_, rule)) => rule,
+                None => line,
+            };
+            let line = match line.starts_with(':') {
+                true => line.rsplit_once(" [").map_or(line, |(chain, _)| chain),
+                false => line,
+            };
+
+            let line = match name {
+                "nftables" => normalize_nft_line(line),
+                _ => line.to_string(),
+            };
+            if !line.trim().is_empty() {
+                res.push_str(&line);
+                res.push('\n');
+            }
+        }
+    }
+
+    res
+}
+
+// nft: "counter packets 12 bytes 720" in rules and counter objects, and "expires 1m12s" in the
+// elements of dynamic sets.
+fn normalize_nft_line(line: &str) -> String {
+    let mut words: Vec<&str> = Vec::new();
+    let mut iter = line.split(' ').peekable();
+    while let Some(word) = iter.next() {
+        let counter = words
+            .last()
+            .is_none_or(|w| w.trim() == "counter" || w.trim().is_empty());
+        match word.trim_start() {
+            "packets" if counter => {
+                iter.next();
+                if iter.peek() == Some(&"bytes") {
+                    iter.next();
+                    iter.next();
+                }
+            }
+            "expires" => {
+                iter.next();
+            }
+            _ => words.push(word),
+        }
+    }
+    words.join(" ").trim_end().to_string()
+}
+
+/// Save the running ruleset as the baseline for the drift detection.
+pub fn record_baseline(cfg: &Config) {
+    let res = Snapshot::take(cfg).and_then(|snapshot| {
+        let ruleset = normalize(&snapshot);
+        fs::write(baseline_file(cfg), &ruleset)?;
+        Ok(fingerprint(&ruleset))
+    });
+
+    debug!("Locking drift mutex (thread id: {})", thread_id::get());
+    let mut status = cfg.drift.lock().unwrap();
+    match res {
+        Ok(fingerprint) => {
+            info!("Ruleset baseline recorded for drift detection");
+            *status = DriftStatus {
+                baseline_timestamp: Some(now()),
+                baseline_fingerprint: Some(fingerprint.clone()),
+                last_check: Some(now()),
+                fingerprint: Some(fingerprint),
+                ..Default::default()
+            };
+        }
+        Err(e) => {
+            error!("Error recording the ruleset baseline: {e}");
+            status.error = Some(e.to_string());
+        }
+    }
+    debug!("Releasing drift mutex (thread id: {})", thread_id::get());
+}
+
+/// Compare the running ruleset with the baseline and update the drift status.
+pub fn check(cfg: &Config) -> DriftStatus {
+    let res = compare(cfg);
+
+    debug!("Locking drift mutex (thread id: {})", thread_id::get());
+    let mut status = cfg.drift.lock().unwrap();
+    match res {
+        Ok(new_status) => {
+            if new_status.drift && !status.drift {
+                warn!("The running ruleset doesn't match the one installed by FWCloud");
+            }
+            *status = new_status;
+        }
+        Err(e) => {
+            error!("Error checking the ruleset drift: {e}");
+            status.last_check = Some(now());
+            status.error = Some(e.to_string());
+        }
+    }
+    let res = status.clone();
+    debug!("Releasing drift mutex (thread id: {})", thread_id::get());
+
+    res
+}
+
+fn compare(cfg: &Config) -> Result<DriftStatus> {
+    let baseline_file = baseline_file(cfg);
+    let baseline = match fs::read_to_string(&baseline_file) {
+        Ok(baseline) => baseline,
+        // Nothing to compare with until a FWCloud script is installed.
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(DriftStatus {
+                last_check: Some(now()),
+                ..Default::default()
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let baseline_timestamp = fs::metadata(&baseline_file)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .ok();
+
+    let ruleset = normalize(&Snapshot::take(cfg)?);
+    let drift = ruleset != baseline;
+    let diff = if drift {
+        let current_file = format!("{}/ruleset_current", cfg.tmp_dir);
+        fs::write(&current_file, &ruleset)?;
+        let diff = run_diff(
+            &baseline_file,
+            &current_file,
+            "baseline",
+            "running",
+            CmdKind::Other.timeout(cfg, None),
+        );
+        let _ = fs::remove_file(&current_file);
+        diff?
+    } else {
+        String::new()
+    };
+
+    Ok(DriftStatus {
+        baseline_timestamp,
+        baseline_fingerprint: Some(fingerprint(&baseline)),
+        last_check: Some(now()),
+        fingerprint: Some(fingerprint(&ruleset)),
+        drift,
+        diff,
+        error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_rulesets() {
+        let snapshot = Snapshot {
+            iptables: Some(String::from(
+                "# Generated by iptables-save v1.8.9 (nf_tables) on Sat Oct 17 10:12:44 2026\n*filter\n:INPUT DROP [10:600]\n:FWCRULE.LOG - [0:0]\n[3200:412000] -A INPUT -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT\n-A INPUT -i lo -j ACCEPT\nCOMMIT\n# Completed on Sat Oct 17 10:12:44 2026\n",
+            )),
+            ip6tables: None,
+            nftables: Some(String::from(
+                "table inet filter {\n\tset dyn {\n\t\ttype ipv4_addr\n\t\telements = { 10.0.0.1 expires 52s }\n\t}\n\tchain input {\n\t\ttcp dport 22 counter packets 12 bytes 720 accept\n\t}\n}\n",
+            )),
+            ..Snapshot::default()
+        };
+
+        assert_eq!(
+            normalize(&snapshot),
+            "### iptables\n*filter\n:INPUT DROP\n:FWCRULE.LOG -\n-A INPUT -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT\n-A INPUT -i lo -j ACCEPT\nCOMMIT\n### nftables\ntable inet filter {\n\tset dyn {\n\t\ttype ipv4_addr\n\t\telements = { 10.0.0.1 }\n\t}\n\tchain input {\n\t\ttcp dport 22 counter accept\n\t}\n}\n"
+        );
+
+        // Only the counters changed.
+        let mut other = Snapshot {
+            iptables: snapshot
+                .iptables
+                .as_ref()
+                .map(|r| r.replace("[3200:412000]", "[3300:412900]")),
+            ip6tables: None,
+            nftables: snapshot
+                .nftables
+                .as_ref()
+                .map(|r| r.replace("packets 12 bytes 720", "packets 15 bytes 900")),
+            ..Snapshot::default()
+        };
+        assert_eq!(normalize(&snapshot), normalize(&other));
+
+        other.iptables = other.iptables.map(|r| {
+            r.replace(
+                "-A INPUT -i lo -j ACCEPT\n",
+                "-A INPUT -i lo -j ACCEPT\n-A INPUT -j ACCEPT\n",
+            )
+        });
+        assert_ne!(normalize(&snapshot), normalize(&other));
+    }
+}
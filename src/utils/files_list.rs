@@ -20,7 +20,7 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs::{self, File};
 use std::io::{self, prelude::*, BufReader, BufWriter};
@@ -28,7 +28,7 @@ use std::path::Path;
 
 use crate::errors::{FwcError, Result};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct FilesList {
     dir: String,
     files: Vec<String>,
@@ -200,7 +200,7 @@ mod tests {
     }
 
     fn create_files(fl: &FilesList) -> Result<()> {
-        fs::create_dir(&fl.dir())?;
+        fs::create_dir(fl.dir())?;
 
         for inx in 0..fl.len() {
             let fw = File::create(fl.path(inx))?;
@@ -227,7 +227,7 @@ mod tests {
     fn sha256_cvs_string(fl: &mut FilesList) -> Result<String> {
         let mut cvs = String::from("file,sha256\n");
         for inx in 0..fl.len() {
-            let mut file = File::open(&fl.path(inx))?;
+            let mut file = File::open(fl.path(inx))?;
             let mut sha256 = Sha256::new();
             io::copy(&mut file, &mut sha256)?;
             let line = format!("{},{}\n", &fl.name(inx), hex::encode(sha256.finalize()));
@@ -274,10 +274,10 @@ mod tests {
 
         let file_content = fl.dump(inx)?;
         let file_lines_count = file_content.len();
-        for i in 0..file_lines_count {
+        for (i, line) in file_content.iter().enumerate() {
             let result = fl.head_remove(inx, 1)?;
             let new_lines_count = fl.dump(inx)?.len();
-            assert_eq!(file_content[i as usize], result[0]);
+            assert_eq!(*line, result[0]);
             assert_eq!(new_lines_count, file_lines_count - (i + 1));
         }
 
@@ -333,7 +333,7 @@ mod tests {
     #[test]
     fn directory_exists() {
         let fl = files_list_factory(0);
-        fs::create_dir(&fl.dir()).unwrap();
+        fs::create_dir(fl.dir()).unwrap();
         assert!(fl.dir_exists());
         fs::remove_dir(fl.dir()).unwrap();
     }
@@ -394,10 +394,7 @@ mod tests {
         let fl = files_list_factory(0);
 
         match fl.remove() {
-            Err(e) => match e {
-                FwcError::DirNotFound => assert!(true),
-                _ => return assert!(false),
-            },
+            Err(e) => assert!(matches!(e, FwcError::DirNotFound)),
             Ok(_) => panic!("Error expected"),
         }
     }
@@ -413,10 +410,12 @@ mod tests {
         fl2.chdir(&fl1.dir());
         fl2.get_files_in_dir()?;
         fl1.remove()?;
-        fs::remove_dir(&fl1.dir())?;
+        fs::remove_dir(fl1.dir())?;
 
         // Check that all directory files have been read.
-        if fl1.files.sort() == fl2.files.sort() {
+        fl1.files.sort();
+        fl2.files.sort();
+        if fl1.files == fl2.files {
             Ok(())
         } else {
             Err(FwcError::Internal("Getting files"))
@@ -428,10 +427,7 @@ mod tests {
         let mut fl = files_list_factory(0);
 
         match fl.get_files_in_dir() {
-            Err(e) => match e {
-                FwcError::DirNotFound => assert!(true),
-                _ => return assert!(false),
-            },
+            Err(e) => assert!(matches!(e, FwcError::DirNotFound)),
             Ok(_) => panic!("Error expected"),
         }
     }
@@ -440,7 +436,7 @@ mod tests {
     fn sha256_gives_empty_result_if_dir_is_empty() {
         let fl = files_list_factory(0);
 
-        fs::create_dir(&fl.dir()).unwrap();
+        fs::create_dir(fl.dir()).unwrap();
         assert_eq!(fl.sha256(false).unwrap(), String::from("file,sha256\n"));
         fs::remove_dir(fl.dir()).unwrap();
     }
@@ -476,11 +472,7 @@ mod tests {
         create_files(&fl)?;
 
         // Add comments to one file.
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(fl.path(3))
-            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(fl.path(3)).unwrap();
         writeln!(file, "# First comment line!")?;
         writeln!(file, "# Second comment line!")?;
 
@@ -505,7 +497,7 @@ mod tests {
         create_files(&fl)?;
 
         // Modify one of the files.
-        let mut fw = File::create(&fl.path(inx))?;
+        let mut fw = File::create(fl.path(inx))?;
         let compare = format!("{}\n{}", Uuid::new_v4(), Uuid::new_v4());
         fw.write_all(compare.as_bytes())?;
         drop(fw);
@@ -21,33 +21,62 @@
 */
 
 use actix_multipart::{Field, Multipart};
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use futures::{StreamExt, TryStreamExt};
 use log::debug;
-use std::fs;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{io::Write, os::unix::prelude::PermissionsExt};
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::audit::{audit_param, Requester};
 use crate::config::Config;
 use crate::errors::{FwcError, Result};
-use crate::utils::cmd::{run_cmd, run_cmd_ws};
+use crate::utils::cmd::{run_cmd, run_cmd_job, run_cmd_ws, CmdOutput};
+use crate::utils::jobs::JobRun;
+use crate::utils::policy_apply::{self, PolicyApply};
+use crate::utils::policy_validate::{self, Validation};
+use crate::utils::script_history;
+use crate::utils::script_signature::ScriptSignature;
 
 use super::ws::WsData;
 
+// Detached signature of the FWCloud script, uploaded in the same multipart stream.
+const SCRIPT_SIGNATURE_FILE: &str = "fwcloud.sh.sig";
+
 struct FileData {
     src_path: String,
     src_name: String,
     dst_path: String,
     dst_name: String,
     size: usize,
+    sha256: String,
+}
+
+#[derive(Serialize)]
+struct AuditFile<'a> {
+    name: &'a str,
+    size: usize,
+    sha256: &'a str,
+}
+
+/// FWCloud script received for installing it later, in a job.
+pub struct ScriptUpload {
+    src_path: String,
+    pub dst_path: String,
+    perms: u32,
+    pub signed: bool,
 }
 
 #[derive(Validate)]
 pub struct HttpFiles {
-    tmp_dir: &'static str,
+    tmp_dir: String,
     dst_dir: String,
     create_dst_dir: bool,
     files: Vec<FileData>,
@@ -63,12 +92,13 @@ pub struct HttpFiles {
     n_files: u32,
     ws_id: Uuid,
     ws_id_buf: String,
+    audit_req: Option<HttpRequest>,
 }
 
 impl HttpFiles {
-    pub fn new(tmp_dir: &'static str, create_dst_dir: bool) -> Self {
+    pub fn new(tmp_dir: &str, create_dst_dir: bool) -> Self {
         HttpFiles {
-            tmp_dir,
+            tmp_dir: String::from(tmp_dir),
             dst_dir: String::from(""),
             create_dst_dir,
             files: Vec::new(),
@@ -80,84 +110,192 @@ impl HttpFiles {
             n_files: 0,
             ws_id: Uuid::nil(),
             ws_id_buf: String::from(""),
+            audit_req: None,
         }
     }
 
+    /// Record the destination directory and the received files in the audit log entry of the request.
+    pub fn audit(mut self, req: &HttpRequest) -> Self {
+        self.audit_req = Some(req.clone());
+        self
+    }
+
+    /// Receive files and install them in the `dst_dir` given by the client, for the OpenVPN,
+    /// WireGuard and IPsec routes.
+    ///
+    /// The destination is not restricted to any directory, the files are written as root wherever
+    /// the client asks. The script signatures don't protect these routes.
     pub async fn files_upload(&mut self, payload: Multipart) -> Result<()> {
-        self.extract_multipart_data(payload).await?;
+        let res = self.extract_multipart_data(payload).await;
+        self.audit_files();
+        res?;
         self.check_data()?;
         self.move_tmp_files()?;
 
         Ok(())
     }
 
+    /// Receive a configuration document (`.env` or `agent.toml`) and return its name and content.
+    ///
+    /// The file is not installed, it must be validated before.
+    pub async fn config_document(&mut self, payload: Multipart) -> Result<(String, String)> {
+        self.expected_files = 1;
+        self.max_file_size = 1_048_576; // One megabyte.
+        self.dst_dir = self.tmp_dir.clone();
+        let res = self.extract_multipart_data(payload).await;
+        self.audit_files();
+        res?;
+        self.check_data()?;
+
+        let file = &self.files[0];
+        let data = fs::read_to_string(&file.src_path)
+            .map_err(|_| FwcError::ConfigNotValid(String::from("Not a text file")))?;
+
+        Ok((file.dst_name.clone(), data))
+    }
+
+    /// Validate the uploaded FWCloud script without installing it.
+    ///
+    /// The script is run in the validation too, so its signature is checked the same way as when it
+    /// is installed.
+    pub async fn fwcloud_script_validate(
+        &mut self,
+        payload: Multipart,
+        cfg: &Arc<Config>,
+    ) -> Result<Validation> {
+        self.max_files = 2;
+        self.dst_dir = self.tmp_dir.clone();
+        let res = self.extract_multipart_data(payload).await;
+        self.audit_files();
+        res?;
+        self.check_data()?;
+        self.check_fwcloud_script(cfg, false)?;
+
+        let (cfg, script) = (Arc::clone(cfg), self.files[0].src_path.clone());
+        web::block(move || policy_validate::validate(&cfg, &script)).await?
+    }
+
+    /// Install and load the uploaded FWCloud script.
+    ///
+    /// With `rollback_timeout` the current ruleset is saved before and restored if the script fails or
+    /// the policy is not confirmed in time.
+    ///
+    /// The script can come with its detached signature (`fwcloud.sh.sig`), that is mandatory if
+    /// REQUIRE_SIGNED_SCRIPTS is enabled. The signature is not installed.
+    ///
+    /// The script is installed in the blocking thread pool.
     pub async fn fwcloud_script(
         &mut self,
         payload: Multipart,
         cfg: &web::Data<Arc<Config>>,
-    ) -> Result<HttpResponse> {
-        self.expected_files = 1;
-        self.extract_multipart_data(payload).await?;
+        rollback_timeout: Option<u64>,
+        timeout: Option<Duration>,
+    ) -> Result<(HttpResponse, Option<PolicyApply>)> {
+        self.max_files = 2;
+        let res = self.extract_multipart_data(payload).await;
+        self.audit_files();
+        res?;
         self.check_data()?;
+        let signed = self.check_fwcloud_script(cfg, true)?;
+
+        let file = &self.files[0];
+        let (src, script) = (file.src_path.clone(), file.dst_path.clone());
+        let (perms, ws_id, uploader) = (self.perms_u32, self.ws_id, self.requester());
+        let cfg = Arc::clone(cfg);
+        let (output, apply) = web::block(move || {
+            let snapshot = policy_apply::prepare(&cfg, rollback_timeout)?;
+            move_file(&src, &script, perms)?;
+
+            let res = install_fwcloud_script(&cfg, &script, ws_id, timeout);
+            script_history::record(&cfg, &script, &uploader, &res, None, signed);
+            policy_apply::finish(&cfg, snapshot, rollback_timeout, res)
+        })
+        .await??;
+
+        Ok((output.into(), apply))
+    }
+
+    // Check that the only files are the FWCloud script and its optional signature, and verify it.
+    // The signature file is removed from the list of files. Returns if the script is signed.
+    //
+    // For installing it, the script path must be the one that was signed (and one of
+    // FWCLOUD_SCRIPT_PATHS with REQUIRE_SIGNED_SCRIPTS), and the requested permissions can't make it
+    // writable by group or others.
+    fn check_fwcloud_script(&mut self, cfg: &Config, install: bool) -> Result<bool> {
+        let signature = match self
+            .files
+            .iter()
+            .position(|file| file.dst_name == SCRIPT_SIGNATURE_FILE)
+        {
+            Some(pos) => {
+                let file = self.files.remove(pos);
+                let data = fs::read(&file.src_path);
+                let _ = fs::remove_file(&file.src_path);
+                Some(data?)
+            }
+            None => None,
+        };
 
-        if self.files[0].dst_name != "fwcloud.sh" {
+        if self.files.len() != 1 || self.files[0].dst_name != "fwcloud.sh" {
             return Err(FwcError::NotExpectedFileName);
         }
 
-        self.move_tmp_files()?;
-
-        // Install de FWCloud script.
-        let mut res: HttpResponse;
-        if self.ws_id != Uuid::nil() {
-            let ws_data: Arc<Mutex<WsData>>;
-            {
-                debug!("Locking ws map mutex (thread id: {})", thread_id::get());
-                let ws_map = cfg.ws_map.lock().unwrap();
-                ws_data = ws_map
-                    .get(&self.ws_id)
-                    .ok_or(FwcError::WebSocketIdNotFound)?
-                    .clone();
-                debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
-            }
-            res = run_cmd_ws(
-                "sh",
-                &[&self.files[0].dst_path[..], "install"],
-                &ws_data,
-                false,
-            )?;
-        } else {
-            res = run_cmd("sh", &[&self.files[0].dst_path[..], "install"])?;
+        let path = Path::new(&self.files[0].dst_path);
+        // With signatures required the scripts are only installed in the configured paths too.
+        if install && cfg.require_signed_scripts && !fwcloud_script_path_allowed(cfg, path) {
+            return Err(FwcError::ScriptPathNotAllowed);
+        }
+        if install && self.perms_u32 & 0o022 != 0 {
+            return Err(FwcError::ScriptPermsNotAllowed);
         }
 
-        // Load policy.
-        for file in cfg.fwcloud_script_paths.iter() {
-            if Path::new(file).is_file() {
-                if self.ws_id != Uuid::nil() {
-                    let ws_data: Arc<Mutex<WsData>>;
-                    {
-                        debug!("Locking ws map mutex (thread id: {})", thread_id::get());
-                        let ws_map = cfg.ws_map.lock().unwrap();
-                        ws_data = ws_map
-                            .get(&self.ws_id)
-                            .ok_or(FwcError::WebSocketIdNotFound)?
-                            .clone();
-                        debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
-                    }
-                    res = run_cmd_ws("sh", &[&file[..], "start"], &ws_data, true)?;
-                    {
-                        debug!("Locking ws map mutex (thread id: {})", thread_id::get());
-                        let mut ws_map = cfg.ws_map.lock().unwrap();
-                        ws_map.remove(&self.ws_id);
-                        debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
-                    }
-                } else {
-                    res = run_cmd("sh", &[&file[..], "start"])?;
+        let signed = match signature {
+            Some(signature) => {
+                let signature = ScriptSignature::parse(&signature)?;
+                let script = fs::read(&self.files[0].src_path)?;
+                signature.verify(&cfg.script_signing_key, &script)?;
+                if install && Path::new(&signature.path) != path {
+                    return Err(FwcError::ScriptSignaturePath);
                 }
-                break;
+                true
             }
+            None if cfg.require_signed_scripts => return Err(FwcError::ScriptSignatureRequired),
+            None => false,
+        };
+
+        if let Some(req) = &self.audit_req {
+            audit_param(req, "signed", signed);
         }
 
-        Ok(res)
+        Ok(signed)
+    }
+
+    /// Receive and check a FWCloud script the same way as `fwcloud_script`, but keep it for
+    /// installing it later, in a job. WebSocket output is not available for jobs.
+    pub async fn fwcloud_script_upload(
+        &mut self,
+        payload: Multipart,
+        cfg: &Config,
+    ) -> Result<ScriptUpload> {
+        self.max_files = 2;
+        let res = self.extract_multipart_data(payload).await;
+        self.audit_files();
+        res?;
+        self.check_data()?;
+        let signed = self.check_fwcloud_script(cfg, true)?;
+
+        if self.ws_id != Uuid::nil() {
+            return Err(FwcError::NotAllowedParameter);
+        }
+
+        // Not removed when this object goes out of scope, but when the upload does.
+        let file = self.files.remove(0);
+        Ok(ScriptUpload {
+            src_path: file.src_path,
+            dst_path: file.dst_path,
+            perms: self.perms_u32,
+            signed,
+        })
     }
 
     async fn extract_multipart_data(&mut self, mut payload: Multipart) -> Result<()> {
@@ -200,7 +338,9 @@ impl HttpFiles {
                 dst_path: format!("{}/{}", self.dst_dir, filename),
                 dst_name: filename,
                 size: 0,
+                sha256: String::new(),
             };
+            let mut hasher = Sha256::new();
 
             // File::create is blocking operation, use threadpool
             let file_path = file_data.src_path.clone();
@@ -217,6 +357,7 @@ impl HttpFiles {
                 if self.max_file_size > 0 && file_data.size > self.max_file_size {
                     return Err(FwcError::TooBigFile);
                 }
+                hasher.update(&data);
 
                 // filesystem operations are blocking, we have to use threadpool
                 f = web::block(move || f.write_all(&data).map(|_| f))
@@ -224,6 +365,7 @@ impl HttpFiles {
                     .unwrap();
             }
 
+            file_data.sha256 = hex::encode(hasher.finalize());
             self.files.push(file_data);
         }
 
@@ -233,7 +375,10 @@ impl HttpFiles {
     async fn extract_field_data(&mut self, mut field: Field, name: String) -> Result<()> {
         // We only accept these NO file parameter in the multipart stream and it must be the destination directory.
         let buf: &mut String;
-        if name == "dst_dir" {
+        if name == "dst_dir" && !self.dst_dir.is_empty() {
+            // Already given or fixed by the request handler.
+            return Err(FwcError::NotAllowedParameter);
+        } else if name == "dst_dir" {
             buf = &mut self.dst_dir;
         } else if name == "perms" {
             self.perms.clear();
@@ -277,14 +422,36 @@ impl HttpFiles {
         Ok(())
     }
 
+    fn requester(&self) -> Requester {
+        self.audit_req
+            .as_ref()
+            .map(Requester::of)
+            .unwrap_or_default()
+    }
+
+    fn audit_files(&self) {
+        let req = match &self.audit_req {
+            Some(req) => req,
+            None => return,
+        };
+
+        let files: Vec<AuditFile> = self
+            .files
+            .iter()
+            .map(|file| AuditFile {
+                name: &file.dst_name,
+                size: file.size,
+                sha256: &file.sha256,
+            })
+            .collect();
+
+        audit_param(req, "dst_dir", &self.dst_dir);
+        audit_param(req, "files", files);
+    }
+
     fn move_tmp_files(&mut self) -> Result<()> {
         for file_data in self.files.iter() {
-            fs::copy(&file_data.src_path, &file_data.dst_path)?;
-            fs::remove_file(&file_data.src_path)?;
-
-            let mut perms = fs::metadata(&file_data.dst_path)?.permissions();
-            perms.set_mode(self.perms_u32);
-            fs::set_permissions(&file_data.dst_path, perms)?;
+            move_file(&file_data.src_path, &file_data.dst_path, self.perms_u32)?;
         }
 
         Ok(())
@@ -335,6 +502,148 @@ impl Drop for HttpFiles {
     }
 }
 
+impl ScriptUpload {
+    /// Move the script to its destination path.
+    pub fn install(&self) -> Result<()> {
+        move_file(&self.src_path, &self.dst_path, self.perms)
+    }
+}
+
+impl Drop for ScriptUpload {
+    fn drop(&mut self) {
+        // Ignore the error if it has already been installed.
+        let _ = fs::remove_file(&self.src_path);
+    }
+}
+
+fn move_file(src: &str, dst: &str, mode: u32) -> Result<()> {
+    write_file(dst, &fs::read(src)?, mode)?;
+    fs::remove_file(src)?;
+
+    Ok(())
+}
+
+/// Write a file with the `mode` permissions. It is written next to its destination and then
+/// renamed, this way it is never run or read half written.
+pub fn write_file(path: &str, data: &[u8], mode: u32) -> Result<()> {
+    let tmp_path = format!("{path}.new");
+    let _ = fs::remove_file(&tmp_path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::set_permissions(&tmp_path, fs::Permissions::from_mode(mode))?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+/// Check if a FWCloud script can be installed in `path`, that must be one of FWCLOUD_SCRIPT_PATHS.
+/// The paths are compared by components, a trailing slash in the destination directory doesn't
+/// matter.
+pub fn fwcloud_script_path_allowed(cfg: &Config, path: &Path) -> bool {
+    cfg.live()
+        .fwcloud_script_paths
+        .iter()
+        .any(|allowed| Path::new(allowed) == path)
+}
+
+/// Install and load a FWCloud script in a job, with the same sequence of `fwcloud_script`.
+pub fn install_fwcloud_script_job(
+    cfg: &Config,
+    upload: ScriptUpload,
+    rollback_timeout: Option<u64>,
+    timeout: Option<Duration>,
+    job: &mut JobRun,
+) -> Result<Option<PolicyApply>> {
+    // Not at the same time as other installs or the drift checks, until the end of the job.
+    debug!("Locking script mutex (thread id: {})", thread_id::get());
+    let _mutex_data = cfg.mutex.fwcloud_script.blocking_lock();
+    debug!("Script mutex locked (thread id: {})", thread_id::get());
+    job.start()?;
+
+    let snapshot = policy_apply::prepare(cfg, rollback_timeout)?;
+    upload.install()?;
+
+    let script = upload.dst_path.as_str();
+    let res = run_cmd_job("sh", &[script, "install"], job, timeout).and_then(|_| {
+        match cfg
+            .live()
+            .fwcloud_script_paths
+            .iter()
+            .find(|file| Path::new(file).is_file())
+        {
+            Some(file) => run_cmd_job("sh", &[file, "start"], job, timeout),
+            None => Ok(()),
+        }
+    });
+    script_history::record(cfg, script, job.requester(), &res, None, upload.signed);
+    let (_, apply) = policy_apply::finish(cfg, snapshot, rollback_timeout, res)?;
+
+    debug!("Releasing script mutex (thread id: {})", thread_id::get());
+    Ok(apply)
+}
+
+/// Run the install and start sequence of a FWCloud script, with its output sent to the WebSocket
+/// `ws_id` if it is not nil. Each command is terminated if it doesn't finish in `timeout`.
+pub fn install_fwcloud_script(
+    cfg: &Config,
+    script: &str,
+    ws_id: Uuid,
+    timeout: Option<Duration>,
+) -> Result<CmdOutput> {
+    // Install de FWCloud script.
+    let mut res: CmdOutput;
+    if ws_id != Uuid::nil() {
+        let ws_data: Arc<Mutex<WsData>>;
+        {
+            debug!("Locking ws map mutex (thread id: {})", thread_id::get());
+            let ws_map = cfg.ws_map.lock().unwrap();
+            ws_data = ws_map
+                .get(&ws_id)
+                .ok_or(FwcError::WebSocketIdNotFound)?
+                .clone();
+            debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
+        }
+        res = run_cmd_ws("sh", &[script, "install"], &ws_data, false, timeout)?;
+    } else {
+        res = run_cmd("sh", &[script, "install"], timeout)?;
+    }
+
+    // Load policy.
+    for file in cfg.live().fwcloud_script_paths.iter() {
+        if Path::new(file).is_file() {
+            if ws_id != Uuid::nil() {
+                let ws_data: Arc<Mutex<WsData>>;
+                {
+                    debug!("Locking ws map mutex (thread id: {})", thread_id::get());
+                    let ws_map = cfg.ws_map.lock().unwrap();
+                    ws_data = ws_map
+                        .get(&ws_id)
+                        .ok_or(FwcError::WebSocketIdNotFound)?
+                        .clone();
+                    debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
+                }
+                res = run_cmd_ws("sh", &[&file[..], "start"], &ws_data, true, timeout)?;
+                {
+                    debug!("Locking ws map mutex (thread id: {})", thread_id::get());
+                    let mut ws_map = cfg.ws_map.lock().unwrap();
+                    ws_map.remove(&ws_id);
+                    debug!("Releasing ws map mutex (thread id: {})", thread_id::get());
+                }
+            } else {
+                res = run_cmd("sh", &[&file[..], "start"], timeout)?;
+            }
+            break;
+        }
+    }
+
+    Ok(res)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
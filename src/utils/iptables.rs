@@ -0,0 +1,500 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::errors::{FwcError, Result};
+use crate::utils::cmd::run_cmd_output;
+
+/// Options of the iptables-save/ip6tables-save command.
+#[derive(Default)]
+pub struct SaveOptions<'a> {
+    pub ipv6: bool,
+    pub table: Option<&'a str>,
+    pub counters: bool,
+    /// `legacy` or `nft` for using the `*-legacy-save` or `*-nft-save` variants of the command,
+    /// when it is not given the one selected by the system alternatives is used.
+    pub backend: Option<&'a str>,
+}
+
+impl SaveOptions<'_> {
+    pub fn cmd(&self) -> String {
+        let prefix = if self.ipv6 { "ip6tables" } else { "iptables" };
+        match self.backend {
+            Some(backend) => format!("{prefix}-{backend}-save"),
+            None => format!("{prefix}-save"),
+        }
+    }
+
+    pub fn args(&self) -> Vec<&str> {
+        let mut args = Vec::new();
+        if let Some(table) = self.table {
+            args.push("-t");
+            args.push(table);
+        }
+        if self.counters {
+            args.push("-c");
+        }
+        args
+    }
+}
+
+/// Version and backend (`legacy` or `nft`) of the iptables tools in use.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BackendInfo {
+    pub version: String,
+    pub backend: String,
+}
+
+impl BackendInfo {
+    /// Parse the output of `iptables -V`, for example: `iptables v1.8.9 (nf_tables)`.
+    /// Versions before 1.8 don't show the backend because only the legacy one exists.
+    pub fn parse(output: &str) -> Option<BackendInfo> {
+        let mut words = output.split_whitespace().skip(1);
+        let version = words.next()?.strip_prefix('v')?.to_string();
+        let backend = match words.next() {
+            Some("(nf_tables)") => "nft",
+            Some("(legacy)") | None => "legacy",
+            Some(_) => return None,
+        };
+
+        Some(BackendInfo {
+            version,
+            backend: backend.to_string(),
+        })
+    }
+}
+
+/// Detect the backend used by the `iptables` (or `ip6tables`) command, `None` if it is not
+/// installed.
+pub fn detect_backend(ipv6: bool, timeout: Option<Duration>) -> Result<Option<BackendInfo>> {
+    let cmd = if ipv6 { "ip6tables" } else { "iptables" };
+    match run_cmd_output(cmd, &["-V"], timeout) {
+        Ok(output) => Ok(BackendInfo::parse(&output)),
+        Err(FwcError::PopenError(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/*
+  Typed model of the output of iptables-save/ip6tables-save, with or without the -c option.
+
+  *filter
+  :INPUT DROP [10:600]
+  :FWCRULE.LOG - [0:0]
+  [7:420] -A INPUT -p tcp -m tcp --dport 22 -m comment --comment "ssh" -j ACCEPT
+  COMMIT
+*/
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct Ruleset {
+    pub tables: Vec<Table>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub chains: Vec<Chain>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Chain {
+    pub name: String,
+    pub builtin: bool,
+    /// Policy of the built-in chains, user chains don't have one.
+    pub policy: Option<String>,
+    pub counters: Option<Counters>,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone, Copy)]
+pub struct Counters {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Rule {
+    pub counters: Option<Counters>,
+    /// Generic options that don't belong to any match extension (-s, -d, -i, -o, -p, ...).
+    pub args: Vec<String>,
+    pub matches: Vec<Extension>,
+    pub target: Option<Extension>,
+    /// The target is a chain we go to (-g) instead of jump to (-j).
+    pub goto: bool,
+}
+
+/// Match (-m) or target (-j/-g) extension with its arguments.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Extension {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl Ruleset {
+    /// Parse the output of iptables-save/ip6tables-save.
+    pub fn parse(output: &str) -> Result<Ruleset> {
+        let mut ruleset = Ruleset::default();
+        let mut table: Option<Table> = None;
+
+        for (n, line) in output.lines().enumerate() {
+            let line = line.trim();
+            let error = |msg: &str| FwcError::IptablesSaveParse(format!("line {}: {msg}", n + 1));
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('*') {
+                if table.is_some() {
+                    return Err(error("table without COMMIT"));
+                }
+                table = Some(Table {
+                    name: name.to_string(),
+                    chains: Vec::new(),
+                });
+                continue;
+            }
+
+            let Some(current) = table.as_mut() else {
+                return Err(error("line outside of a table"));
+            };
+
+            if line == "COMMIT" {
+                ruleset.tables.extend(table.take());
+            } else if let Some(chain) = line.strip_prefix(':') {
+                current
+                    .chains
+                    .push(parse_chain(chain).ok_or_else(|| error("bad chain"))?);
+            } else {
+                let (counters, rule) = match line.strip_prefix('[') {
+                    Some(rest) => {
+                        let (counters, rule) =
+                            rest.split_once(']').ok_or_else(|| error("bad counters"))?;
+                        (
+                            Some(parse_counters(counters).ok_or_else(|| error("bad counters"))?),
+                            rule,
+                        )
+                    }
+                    None => (None, line),
+                };
+
+                let tokens = tokenize(rule).ok_or_else(|| error("unterminated quote"))?;
+                let (chain_name, rule) = match tokens.split_first() {
+                    Some((append, rest)) if append == "-A" && !rest.is_empty() => {
+                        (&rest[0], parse_rule(counters, &rest[1..]))
+                    }
+                    _ => return Err(error("expected -A <chain>")),
+                };
+                current
+                    .chains
+                    .iter_mut()
+                    .find(|c| &c.name == chain_name)
+                    .ok_or_else(|| error("rule for an undeclared chain"))?
+                    .rules
+                    .push(rule);
+            }
+        }
+
+        if table.is_some() {
+            return Err(FwcError::IptablesSaveParse(
+                "table without COMMIT".to_string(),
+            ));
+        }
+
+        Ok(ruleset)
+    }
+}
+
+// Chain declaration without the leading ':', for example: "INPUT DROP [10:600]".
+fn parse_chain(line: &str) -> Option<Chain> {
+    let mut fields = line.split_whitespace();
+    let name = fields.next()?.to_string();
+    let policy = fields.next()?;
+    let counters = match fields.next() {
+        Some(c) => Some(parse_counters(c.strip_prefix('[')?.strip_suffix(']')?)?),
+        None => None,
+    };
+
+    Some(Chain {
+        name,
+        builtin: policy != "-",
+        policy: (policy != "-").then(|| policy.to_string()),
+        counters,
+        rules: Vec::new(),
+    })
+}
+
+// Counters without the brackets: "packets:bytes".
+fn parse_counters(counters: &str) -> Option<Counters> {
+    let (packets, bytes) = counters.split_once(':')?;
+    Some(Counters {
+        packets: packets.parse().ok()?,
+        bytes: bytes.parse().ok()?,
+    })
+}
+
+fn parse_rule(counters: Option<Counters>, tokens: &[String]) -> Rule {
+    let mut rule = Rule {
+        counters,
+        args: Vec::new(),
+        matches: Vec::new(),
+        target: None,
+        goto: false,
+    };
+
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "-m" | "--match" if rule.target.is_none() => {
+                if let Some(name) = tokens.next() {
+                    rule.matches.push(Extension {
+                        name: name.clone(),
+                        args: Vec::new(),
+                    });
+                }
+            }
+            "-j" | "--jump" | "-g" | "--goto" if rule.target.is_none() => {
+                if let Some(name) = tokens.next() {
+                    rule.goto = token == "-g" || token == "--goto";
+                    rule.target = Some(Extension {
+                        name: name.clone(),
+                        args: Vec::new(),
+                    });
+                }
+            }
+            _ => {
+                // Arguments belong to the last extension we have found.
+                let args = match (rule.target.as_mut(), rule.matches.last_mut()) {
+                    (Some(target), _) => &mut target.args,
+                    (None, Some(m)) => &mut m.args,
+                    (None, None) => &mut rule.args,
+                };
+                args.push(token.clone());
+            }
+        }
+    }
+
+    rule
+}
+
+// Split a rule in words the same way iptables-restore does: double quotes group words and
+// backslash escapes the next character inside them.
+fn tokenize(line: &str) -> Option<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars();
+    let mut token = String::new();
+    let mut in_token = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_token = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => token.push(chars.next()?),
+                        c => token.push(c),
+                    }
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            c => {
+                in_token = true;
+                token.push(c);
+            }
+        }
+    }
+    if in_token {
+        tokens.push(token);
+    }
+
+    Some(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IPTABLES_SAVE: &str = include_str!("../../tests/templates/iptables-save-counters.txt");
+    const IP6TABLES_SAVE: &str = include_str!("../../tests/templates/ip6tables-save.txt");
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_iptables_save_with_counters() {
+        let ruleset = Ruleset::parse(IPTABLES_SAVE).unwrap();
+        assert_eq!(ruleset.tables.len(), 2);
+
+        let nat = &ruleset.tables[0];
+        assert_eq!(nat.name, "nat");
+        assert_eq!(nat.chains.len(), 4);
+        let dnat = &nat.chains[0].rules[0];
+        assert_eq!(dnat.args, strings(&["-d", "203.0.113.10/32", "-p", "tcp"]));
+        assert_eq!(dnat.matches[0].name, "tcp");
+        assert_eq!(dnat.matches[0].args, strings(&["--dport", "8080"]));
+        let target = dnat.target.as_ref().unwrap();
+        assert_eq!(target.name, "DNAT");
+        assert_eq!(
+            target.args,
+            strings(&["--to-destination", "192.168.10.20:80"])
+        );
+
+        let filter = &ruleset.tables[1];
+        let input = &filter.chains[0];
+        assert_eq!(input.name, "INPUT");
+        assert!(input.builtin);
+        assert_eq!(input.policy.as_deref(), Some("DROP"));
+        assert_eq!(
+            input.counters,
+            Some(Counters {
+                packets: 10,
+                bytes: 600
+            })
+        );
+        assert_eq!(input.rules.len(), 5);
+
+        let ssh = &input.rules[2];
+        assert_eq!(
+            ssh.counters,
+            Some(Counters {
+                packets: 7,
+                bytes: 420
+            })
+        );
+        assert_eq!(ssh.args, strings(&["!", "-s", "10.0.0.0/8", "-p", "tcp"]));
+        assert_eq!(ssh.matches.len(), 2);
+        assert_eq!(ssh.matches[1].name, "comment");
+        assert_eq!(
+            ssh.matches[1].args,
+            strings(&["--comment", "ssh \"admin\" access"])
+        );
+        assert_eq!(ssh.target.as_ref().unwrap().name, "ACCEPT");
+        assert!(!ssh.goto);
+
+        let log = &filter.chains[3];
+        assert_eq!(log.name, "FWCRULE.LOG");
+        assert!(!log.builtin);
+        assert_eq!(log.policy, None);
+        assert_eq!(
+            log.rules[0].target.as_ref().unwrap().args,
+            strings(&["--log-prefix", "RULE ID 1 [DROP] ", "--log-level", "6"])
+        );
+
+        let forward = &filter.chains[1].rules[0];
+        assert!(forward.goto);
+        assert_eq!(forward.target.as_ref().unwrap().name, "FWCRULE.LOG");
+    }
+
+    #[test]
+    fn parses_iptables_save_without_counters() {
+        let ruleset = Ruleset::parse(IP6TABLES_SAVE).unwrap();
+        assert_eq!(ruleset.tables.len(), 1);
+
+        let input = &ruleset.tables[0].chains[0];
+        assert_eq!(
+            input.counters,
+            Some(Counters {
+                packets: 0,
+                bytes: 0
+            })
+        );
+        assert_eq!(input.rules.len(), 2);
+        assert_eq!(input.rules[0].counters, None);
+        assert_eq!(
+            input.rules[0].args,
+            strings(&["-s", "fe80::/10", "-p", "ipv6-icmp"])
+        );
+    }
+
+    #[test]
+    fn rejects_bad_iptables_save_output() {
+        for output in [
+            "-A INPUT -j ACCEPT",
+            "*filter\n:INPUT ACCEPT [0:0]\n",
+            "*filter\n:INPUT ACCEPT [0:0]\n-A OUTPUT -j ACCEPT\nCOMMIT",
+            "*filter\n:INPUT ACCEPT [0:0]\n[1:x] -A INPUT -j ACCEPT\nCOMMIT",
+            "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -m comment --comment \"x -j ACCEPT\nCOMMIT",
+        ] {
+            assert!(Ruleset::parse(output).is_err(), "{}", output);
+        }
+        assert_eq!(Ruleset::parse("").unwrap(), Ruleset::default());
+    }
+
+    #[test]
+    fn builds_save_commands() {
+        let opts = SaveOptions::default();
+        assert_eq!(opts.cmd(), "iptables-save");
+        assert!(opts.args().is_empty());
+
+        let opts = SaveOptions {
+            ipv6: true,
+            table: Some("nat"),
+            counters: true,
+            backend: Some("legacy"),
+        };
+        assert_eq!(opts.cmd(), "ip6tables-legacy-save");
+        assert_eq!(opts.args(), vec!["-t", "nat", "-c"]);
+
+        let opts = SaveOptions {
+            backend: Some("nft"),
+            counters: true,
+            ..Default::default()
+        };
+        assert_eq!(opts.cmd(), "iptables-nft-save");
+        assert_eq!(opts.args(), vec!["-c"]);
+    }
+
+    #[test]
+    fn parses_backend() {
+        assert_eq!(
+            BackendInfo::parse("iptables v1.8.9 (nf_tables)\n"),
+            Some(BackendInfo {
+                version: "1.8.9".to_string(),
+                backend: "nft".to_string()
+            })
+        );
+        assert_eq!(
+            BackendInfo::parse("ip6tables v1.8.7 (legacy)")
+                .unwrap()
+                .backend,
+            "legacy"
+        );
+        assert_eq!(
+            BackendInfo::parse("iptables v1.6.1").unwrap(),
+            BackendInfo {
+                version: "1.6.1".to_string(),
+                backend: "legacy".to_string()
+            }
+        );
+        assert_eq!(BackendInfo::parse(""), None);
+        assert_eq!(BackendInfo::parse("iptables 1.8.9"), None);
+    }
+}
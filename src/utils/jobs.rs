@@ -0,0 +1,547 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::audit::Requester;
+use crate::config::Config;
+use crate::errors::{FwcError, Result};
+
+/*
+  Long-running operations (plugins, FWCloud scripts and systemctl commands) can be run in the
+  background as jobs. Every job is kept in data_dir/jobs as <id>.json, with the output of its
+  commands in <id>.log, and the last JOB_HISTORY_SIZE finished ones are kept.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    TimedOut,
+}
+
+impl JobState {
+    pub fn finished(self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    /// Operation: plugin, fwcloud_script or systemctl.
+    pub kind: String,
+    /// Details of the operation, like the plugin name and action.
+    pub description: String,
+    #[serde(flatten)]
+    pub requester: Requester,
+    /// Process of the agent that runs the job.
+    pub agent_pid: u32,
+    pub state: JobState,
+    pub submitted: u64,
+    pub started: Option<u64>,
+    pub finished: Option<u64>,
+    /// Exit code of the last command run by the job.
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,
+}
+
+/// Job with the output of its commands from `output_offset`.
+#[derive(Serialize)]
+pub struct JobOutput {
+    #[serde(flatten)]
+    pub job: Job,
+    pub output_offset: u64,
+    pub output: String,
+}
+
+/// Cancel flags of the jobs of this agent that have not finished.
+#[derive(Default)]
+pub struct Jobs {
+    active: HashMap<Uuid, Arc<AtomicBool>>,
+}
+
+impl Jobs {
+    pub fn active(&self) -> usize {
+        self.active.len()
+    }
+}
+
+/// Handle of a job for its operation.
+pub struct JobRun {
+    cfg: Arc<Config>,
+    job: Job,
+    cancel: Arc<AtomicBool>,
+}
+
+fn jobs_dir(cfg: &Config) -> String {
+    format!("{}/jobs", cfg.data_dir)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn save(cfg: &Config, job: &Job) -> Result<()> {
+    let dir = jobs_dir(cfg);
+    fs::create_dir_all(&dir)?;
+
+    // Replaced with a rename, the job can be read while it is being saved.
+    let tmp = format!("{dir}/{}.json.tmp", job.id);
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)?
+        .write_all(&serde_json::to_vec(job)?)?;
+    fs::rename(&tmp, format!("{dir}/{}.json", job.id))?;
+
+    Ok(())
+}
+
+/// Jobs from the newest to the oldest one.
+pub fn list(cfg: &Config) -> Result<Vec<Job>> {
+    let mut jobs = Vec::new();
+    let dir = match fs::read_dir(jobs_dir(cfg)) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(jobs),
+        Err(e) => return Err(e.into()),
+    };
+
+    for file in dir {
+        let path = file?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            // It can be removed by another job pruning the old ones.
+            let data = match fs::read_to_string(&path) {
+                Ok(data) => data,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            // A corrupt job file doesn't hide the rest of the jobs.
+            match serde_json::from_str::<Job>(&data) {
+                Ok(job) => jobs.push(job),
+                Err(e) => error!("Error reading job file '{}': {e}", path.display()),
+            }
+        }
+    }
+    jobs.sort_by_key(|job| std::cmp::Reverse(job.submitted));
+
+    Ok(jobs)
+}
+
+/// Job with the output of its commands from the byte `offset`, for polling only the new output.
+pub fn get(cfg: &Config, id: Uuid, offset: u64) -> Result<JobOutput> {
+    let base = format!("{}/{id}", jobs_dir(cfg));
+    let job = fs::read_to_string(format!("{base}.json")).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => FwcError::JobNotFound,
+        _ => e.into(),
+    })?;
+
+    let mut output = Vec::new();
+    match File::open(format!("{base}.log")) {
+        Ok(mut file) => {
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_to_end(&mut output)?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(JobOutput {
+        job: serde_json::from_str(&job)?,
+        output_offset: offset,
+        output: String::from_utf8_lossy(&output).to_string(),
+    })
+}
+
+/// Run `op` in the background as a new job, returned right away in the queued state.
+pub fn submit<F>(
+    cfg: &Arc<Config>,
+    kind: &str,
+    description: String,
+    requester: Requester,
+    op: F,
+) -> Result<Job>
+where
+    F: FnOnce(&mut JobRun) -> Result<()> + Send + 'static,
+{
+    let job = Job {
+        id: Uuid::new_v4(),
+        kind: String::from(kind),
+        description,
+        requester,
+        agent_pid: std::process::id(),
+        state: JobState::Queued,
+        submitted: now(),
+        started: None,
+        finished: None,
+        exit_code: None,
+        error: None,
+    };
+    save(cfg, &job)?;
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    debug!("Locking jobs mutex (thread id: {})", thread_id::get());
+    cfg.jobs
+        .lock()
+        .unwrap()
+        .active
+        .insert(job.id, Arc::clone(&cancel));
+    debug!("Releasing jobs mutex (thread id: {})", thread_id::get());
+    info!(
+        "Job {} submitted ({} {})",
+        job.id, job.kind, job.description
+    );
+
+    let mut run = JobRun {
+        cfg: Arc::clone(cfg),
+        job: job.clone(),
+        cancel,
+    };
+    tokio::task::spawn_blocking(move || {
+        let res = op(&mut run);
+        run.finish(res);
+    });
+
+    Ok(job)
+}
+
+/// Ask a job of this agent to stop. A queued job doesn't start and the commands of a running one
+/// are terminated.
+pub fn cancel(cfg: &Config, id: Uuid) -> Result<()> {
+    debug!("Locking jobs mutex (thread id: {})", thread_id::get());
+    let cancel = cfg.jobs.lock().unwrap().active.get(&id).cloned();
+    debug!("Releasing jobs mutex (thread id: {})", thread_id::get());
+
+    match cancel {
+        Some(cancel) => {
+            info!("Cancelling job {id}");
+            cancel.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        None => {
+            get(cfg, id, 0)?;
+            Err(FwcError::JobFinished)
+        }
+    }
+}
+
+/// Mark as failed the unfinished jobs of a previous run of the agent. It must be called at startup,
+/// before any job is submitted.
+pub fn recover(cfg: &Config) {
+    let jobs = match list(cfg) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Error reading the jobs: {e}");
+            return;
+        }
+    };
+
+    for mut job in jobs.into_iter().filter(|job| !job.state.finished()) {
+        warn!("Job {} interrupted by an agent restart", job.id);
+        job.state = JobState::Failed;
+        job.finished = Some(now());
+        job.error = Some(String::from("Interrupted by an agent restart"));
+        if let Err(e) = save(cfg, &job) {
+            error!("Error saving job {}: {e}", job.id);
+        }
+    }
+}
+
+fn prune(cfg: &Config) -> Result<()> {
+    let dir = jobs_dir(cfg);
+
+    // Ordered by the last update of their files, the timestamps of the jobs only have seconds.
+    let mut finished = Vec::new();
+    for job in list(cfg)?.into_iter().filter(|job| job.state.finished()) {
+        match fs::metadata(format!("{dir}/{}.json", job.id)).and_then(|m| m.modified()) {
+            Ok(modified) => finished.push((modified, job.id)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    finished.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    for (_, id) in finished.iter().skip(cfg.job_history_size) {
+        match fs::remove_file(format!("{dir}/{id}.json")) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        let _ = fs::remove_file(format!("{dir}/{id}.log"));
+    }
+
+    Ok(())
+}
+
+impl JobRun {
+    pub fn requester(&self) -> &Requester {
+        &self.job.requester
+    }
+
+    pub fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Mark the job as running, once the resources it needs are available. Fails if the job has
+    /// been cancelled while it was queued.
+    pub fn start(&mut self) -> Result<()> {
+        if self.cancelled() {
+            return Err(FwcError::JobCancelled);
+        }
+
+        self.job.state = JobState::Running;
+        self.job.started = Some(now());
+        self.save();
+        Ok(())
+    }
+
+    /// File where the output of the commands of the job is appended.
+    pub fn output_file(&self) -> Result<File> {
+        fs::create_dir_all(jobs_dir(&self.cfg))?;
+
+        Ok(OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(format!("{}/{}.log", jobs_dir(&self.cfg), self.job.id))?)
+    }
+
+    pub fn set_exit_code(&mut self, code: i32) {
+        self.job.exit_code = Some(code);
+        self.save();
+    }
+
+    fn finish(mut self, res: Result<()>) {
+        self.job.state = match &res {
+            Ok(_) => JobState::Succeeded,
+            Err(FwcError::JobCancelled) => JobState::Cancelled,
+            Err(FwcError::CmdTimeout(_)) => JobState::TimedOut,
+            Err(_) => JobState::Failed,
+        };
+        self.job.error = res.err().map(|e| e.to_string());
+        self.job.finished = Some(now());
+        self.save();
+        info!("Job {} finished ({:?})", self.job.id, self.job.state);
+
+        if let Err(e) = prune(&self.cfg) {
+            error!("Error removing old jobs: {e}");
+        }
+
+        debug!("Locking jobs mutex (thread id: {})", thread_id::get());
+        self.cfg.jobs.lock().unwrap().active.remove(&self.job.id);
+        debug!("Releasing jobs mutex (thread id: {})", thread_id::get());
+    }
+
+    // Errors are logged, they must not change the result of the job.
+    fn save(&self) {
+        if let Err(e) = save(&self.cfg, &self.job) {
+            error!("Error saving job {}: {e}", self.job.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::cmd::run_cmd_job;
+    use serial_test::serial;
+    use std::env;
+    use std::time::Duration;
+
+    async fn wait_finished(cfg: &Config, id: Uuid) -> JobOutput {
+        for _ in 0..100 {
+            if !cfg.jobs.lock().unwrap().active.contains_key(&id) {
+                let job = get(cfg, id, 0).unwrap();
+                assert!(job.job.state.finished());
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Job {} not finished", id);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn runs_and_cancels_jobs() {
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        env::set_var("DATA_DIR", dir.to_str().unwrap());
+        let mut cfg = Config::new().unwrap();
+        env::remove_var("API_KEY");
+        env::remove_var("DATA_DIR");
+        cfg.job_history_size = 2;
+        let cfg = Arc::new(cfg);
+
+        let job = submit(
+            &cfg,
+            "test",
+            String::from("exit 3"),
+            Requester::default(),
+            |job| {
+                job.start()?;
+                run_cmd_job(
+                    "sh",
+                    &["-c", "echo hello; echo world >&2; exit 3"],
+                    job,
+                    None,
+                )
+            },
+        )
+        .unwrap();
+        assert_eq!(job.state, JobState::Queued);
+
+        let job = wait_finished(&cfg, job.id).await;
+        assert_eq!(job.job.state, JobState::Failed);
+        assert_eq!(job.job.exit_code, Some(3));
+        assert_eq!(job.job.error.as_deref(), Some("Command exit status not 0"));
+        assert!(job.job.started.is_some());
+        assert_eq!(job.output, "hello\nworld\n");
+        assert_eq!(get(&cfg, job.job.id, 6).unwrap().output, "world\n");
+        assert!(matches!(
+            cancel(&cfg, job.job.id),
+            Err(FwcError::JobFinished)
+        ));
+        assert!(matches!(
+            cancel(&cfg, Uuid::new_v4()),
+            Err(FwcError::JobNotFound)
+        ));
+
+        let job = submit(
+            &cfg,
+            "test",
+            String::from("sleep"),
+            Requester::default(),
+            |job| {
+                job.start()?;
+                run_cmd_job("sleep", &["30"], job, None)
+            },
+        )
+        .unwrap();
+        assert_eq!(cfg.jobs.lock().unwrap().active(), 1);
+        cancel(&cfg, job.id).unwrap();
+        let job = wait_finished(&cfg, job.id).await;
+        assert_eq!(job.job.state, JobState::Cancelled);
+        assert_eq!(cfg.jobs.lock().unwrap().active(), 0);
+
+        let job = submit(
+            &cfg,
+            "test",
+            String::from("sleep"),
+            Requester::default(),
+            |job| {
+                job.start()?;
+                run_cmd_job("sleep", &["30"], job, Some(Duration::from_secs(1)))
+            },
+        )
+        .unwrap();
+        let job = wait_finished(&cfg, job.id).await;
+        assert_eq!(job.job.state, JobState::TimedOut);
+        assert_eq!(
+            job.job.error.as_deref(),
+            Some("Command timed out after 1 seconds")
+        );
+
+        // Only the last two finished jobs are kept.
+        let job = submit(&cfg, "test", String::new(), Requester::default(), |job| {
+            job.start()
+        })
+        .unwrap();
+        assert_eq!(
+            wait_finished(&cfg, job.id).await.job.state,
+            JobState::Succeeded
+        );
+        let jobs = list(&cfg).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs.iter().any(|j| j.id == job.id));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn fails_jobs_interrupted_by_a_restart() {
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        env::set_var("DATA_DIR", dir.to_str().unwrap());
+        let cfg = Config::new().unwrap();
+        env::remove_var("API_KEY");
+        env::remove_var("DATA_DIR");
+
+        let mut job = Job {
+            id: Uuid::new_v4(),
+            kind: String::from("plugin"),
+            description: String::from("test enable"),
+            requester: Requester::default(),
+            agent_pid: 0,
+            state: JobState::Running,
+            submitted: now(),
+            started: Some(now()),
+            finished: None,
+            exit_code: None,
+            error: None,
+        };
+        save(&cfg, &job).unwrap();
+        // The PID of the agent can be the same after the restart.
+        job.id = Uuid::new_v4();
+        job.agent_pid = std::process::id();
+        save(&cfg, &job).unwrap();
+        let succeeded = Uuid::new_v4();
+        job.id = succeeded;
+        job.state = JobState::Succeeded;
+        job.finished = Some(now());
+        save(&cfg, &job).unwrap();
+        // A corrupt job file is skipped.
+        fs::write(format!("{}/{}.json", jobs_dir(&cfg), Uuid::new_v4()), "{").unwrap();
+
+        recover(&cfg);
+        let jobs = list(&cfg).unwrap();
+        assert_eq!(jobs.len(), 3);
+        for job in jobs {
+            if job.id == succeeded {
+                assert_eq!(job.state, JobState::Succeeded);
+            } else {
+                assert_eq!(job.state, JobState::Failed);
+                assert!(job.finished.is_some());
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -20,8 +20,21 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+pub mod audit_log;
+pub mod auth_failures;
 pub mod cmd;
+pub mod config_update;
+pub mod drift;
 pub mod files_list;
 pub mod http_files;
+pub mod iptables;
+pub mod jobs;
 pub mod myregex;
+pub mod net;
+pub mod nftables;
+pub mod policy_apply;
+pub mod policy_validate;
+pub mod script_history;
+pub mod script_signature;
+pub mod signature;
 pub mod ws;
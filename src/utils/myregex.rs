@@ -34,6 +34,18 @@ lazy_static! {
   pub static ref ALPHA_NUM: Regex = Regex::new("^[a-zA-Z0-9]*$").unwrap();
   pub static ref ALPHA_NUM_2: Regex = Regex::new("^[a-zA-Z0-9\\-_]*$").unwrap();
 
+  pub static ref API_KEY_SCOPE: Regex = Regex::new("^(\\*|[a-z0-9\\-]+:(\\*|[a-z0-9\\-_]+))$").unwrap();
+
+  pub static ref CERT_NAMES_LIST: Regex = Regex::new("^([a-zA-Z0-9\\-_.@]+ ?)*$").unwrap();
+
+  pub static ref TLS_PROFILES: Regex = Regex::new("^(mozilla_modern|mozilla_intermediate)$").unwrap();
+  pub static ref TLS_VERSIONS: Regex = Regex::new("^(1\\.2|1\\.3)$").unwrap();
+
+  pub static ref IPTABLES_TABLES: Regex = Regex::new("^(filter|nat|mangle|raw|security)$").unwrap();
+  pub static ref IPTABLES_BACKENDS: Regex = Regex::new("^(legacy|nft)$").unwrap();
+  pub static ref OUTPUT_FORMATS: Regex = Regex::new("^(text|json)$").unwrap();
+  pub static ref NFT_FAMILIES: Regex = Regex::new("^(ip|ip6|inet|arp|bridge|netdev)$").unwrap();
+
   pub static ref FILE_PERMISSIONS: Regex = Regex::new("^[0-7]{3}$").unwrap();
 
   pub static ref ABSOLUTE_PATH: Regex = Regex::new("^/{1}(((/{1}\\.{1})?[a-zA-Z0-9 -_]+/?)+(\\.{1}[a-zA-Z0-9]{2,4})?)$").unwrap();
@@ -46,6 +58,18 @@ lazy_static! {
   pub static ref SYSTEMCTL_SERVICES: Regex = Regex::new("^(openvpn|openvpn@[a-zA-Z0-9\\-_]+|wg-quick|wg-quick@[a-zA-Z0-9\\-_]+|strongswan|strongswan-starter|isc-dhcp-server|keepalived|haproxy)$").unwrap();
 }
 
+impl AsRegex for TLS_PROFILES {
+    fn as_regex(&self) -> Cow<'_, regex::Regex> {
+        std::borrow::Cow::Borrowed(self)
+    }
+}
+
+impl AsRegex for TLS_VERSIONS {
+    fn as_regex(&self) -> Cow<'_, regex::Regex> {
+        std::borrow::Cow::Borrowed(self)
+    }
+}
+
 impl AsRegex for IPV4 {
     fn as_regex(&self) -> Cow<'_, regex::Regex> {
         std::borrow::Cow::Borrowed(self)
@@ -73,12 +97,42 @@ impl AsRegex for ALPHA_NUM {
         std::borrow::Cow::Borrowed(self)
     }
 }
+impl AsRegex for IPTABLES_TABLES {
+    fn as_regex(&self) -> Cow<'_, regex::Regex> {
+        std::borrow::Cow::Borrowed(self)
+    }
+}
+
+impl AsRegex for IPTABLES_BACKENDS {
+    fn as_regex(&self) -> Cow<'_, regex::Regex> {
+        std::borrow::Cow::Borrowed(self)
+    }
+}
+
+impl AsRegex for OUTPUT_FORMATS {
+    fn as_regex(&self) -> Cow<'_, regex::Regex> {
+        std::borrow::Cow::Borrowed(self)
+    }
+}
+
+impl AsRegex for NFT_FAMILIES {
+    fn as_regex(&self) -> Cow<'_, regex::Regex> {
+        std::borrow::Cow::Borrowed(self)
+    }
+}
+
 impl AsRegex for ALPHA_NUM_2 {
     fn as_regex(&self) -> Cow<'_, regex::Regex> {
         std::borrow::Cow::Borrowed(self)
     }
 }
 
+impl AsRegex for CERT_NAMES_LIST {
+    fn as_regex(&self) -> Cow<'_, regex::Regex> {
+        std::borrow::Cow::Borrowed(self)
+    }
+}
+
 impl AsRegex for FILE_PERMISSIONS {
     fn as_regex(&self) -> Cow<'_, regex::Regex> {
         std::borrow::Cow::Borrowed(self)
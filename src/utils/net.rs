@@ -0,0 +1,209 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use validator::ValidationError;
+
+/// Additional TCP listener: `ADDRESS:PORT[;tls=BOOL][;key_file=PATH][;cert_file=PATH]`.
+///
+/// IPv6 addresses go between brackets, for example `[::]:33033;tls=true`. The TLS settings not indicated are
+/// the ones of the main listener.
+#[derive(Debug, PartialEq)]
+pub struct ListenerSpec {
+    pub address: SocketAddr,
+    pub tls: Option<bool>,
+    pub key_file: Option<String>,
+    pub cert_file: Option<String>,
+}
+
+pub fn parse_listener(s: &str) -> Option<ListenerSpec> {
+    let mut items = s.split(';');
+    let mut spec = ListenerSpec {
+        address: items.next()?.parse::<SocketAddr>().ok()?,
+        tls: None,
+        key_file: None,
+        cert_file: None,
+    };
+
+    for item in items {
+        match item.split_once('=')? {
+            ("tls", value) => spec.tls = Some(value.parse::<bool>().ok()?),
+            ("key_file", value) if value.starts_with('/') => {
+                spec.key_file = Some(String::from(value))
+            }
+            ("cert_file", value) if value.starts_with('/') => {
+                spec.cert_file = Some(String::from(value))
+            }
+            _ => return None,
+        }
+    }
+
+    Some(spec)
+}
+
+/// Parse a space separated list of listeners.
+pub fn parse_listener_list(list: &str) -> Option<Vec<ListenerSpec>> {
+    list.split(' ')
+        .filter(|&x| !x.is_empty())
+        .map(parse_listener)
+        .collect()
+}
+
+pub fn validate_listener_list(list: &str) -> std::result::Result<(), ValidationError> {
+    match parse_listener_list(list) {
+        Some(_) => Ok(()),
+        None => Err(ValidationError::new("listener_list")),
+    }
+}
+
+pub fn validate_ip(ip: &str) -> std::result::Result<(), ValidationError> {
+    match ip.parse::<IpAddr>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("ip")),
+    }
+}
+
+/// Parse an IPv4/IPv6 address or network in CIDR notation.
+///
+/// A plain address is converted into a host network (`/32` for IPv4 and `/128` for IPv6).
+/// IPv4-mapped IPv6 addresses and networks are converted into their IPv4 equivalent, this
+/// way `::ffff:10.0.0.0/104` and `10.0.0.0/8` are the same network.
+pub fn parse_ip_net(s: &str) -> Option<IpNet> {
+    let net = match s.parse::<IpNet>() {
+        Ok(net) => net,
+        Err(_) => IpNet::from(s.parse::<IpAddr>().ok()?),
+    };
+
+    match net {
+        IpNet::V6(net6) => match net6.addr().to_ipv4_mapped() {
+            Some(ip4) if net6.prefix_len() >= 96 => {
+                IpNet::new(IpAddr::V4(ip4), net6.prefix_len() - 96).ok()
+            }
+            _ => Some(net),
+        },
+        IpNet::V4(_) => Some(net),
+    }
+    .map(|net| net.trunc())
+}
+
+/// Parse a space separated list of addresses and networks.
+pub fn parse_ip_net_list(list: &str) -> Option<Vec<IpNet>> {
+    list.split(' ')
+        .filter(|&x| !x.is_empty())
+        .map(parse_ip_net)
+        .collect()
+}
+
+pub fn validate_ip_net_list(list: &str) -> std::result::Result<(), ValidationError> {
+    match parse_ip_net_list(list) {
+        Some(_) => Ok(()),
+        None => Err(ValidationError::new("ip_net_list")),
+    }
+}
+
+/// Check if the IP belongs to any of the networks of the list.
+pub fn ip_in_list(ip: IpAddr, list: &[IpNet]) -> bool {
+    // Connections over dual-stack sockets report IPv4 peers as IPv4-mapped IPv6 addresses.
+    let ip = ip.to_canonical();
+
+    list.iter().any(|net| net.contains(&ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listeners() {
+        assert_eq!(
+            parse_listener("[::]:33033"),
+            Some(ListenerSpec {
+                address: "[::]:33033".parse().unwrap(),
+                tls: None,
+                key_file: None,
+                cert_file: None,
+            })
+        );
+        assert_eq!(
+            parse_listener("10.0.0.1:33034;tls=true;key_file=/etc/k.pem;cert_file=/etc/c.pem"),
+            Some(ListenerSpec {
+                address: "10.0.0.1:33034".parse().unwrap(),
+                tls: Some(true),
+                key_file: Some(String::from("/etc/k.pem")),
+                cert_file: Some(String::from("/etc/c.pem")),
+            })
+        );
+        assert_eq!(parse_listener("::1:33033"), None);
+        assert_eq!(parse_listener("10.0.0.1"), None);
+        assert_eq!(parse_listener("10.0.0.1:33034;tls=yes"), None);
+        assert_eq!(parse_listener("10.0.0.1:33034;key_file=k.pem"), None);
+        assert_eq!(parse_listener("10.0.0.1:33034;port=1"), None);
+
+        assert_eq!(parse_listener_list("").map(|l| l.len()), Some(0));
+        assert_eq!(
+            parse_listener_list("[::1]:33033  127.0.0.1:33034;tls=false").map(|l| l.len()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn parses_addresses_and_networks() {
+        assert_eq!(parse_ip_net("10.1.2.3"), "10.1.2.3/32".parse().ok());
+        assert_eq!(parse_ip_net("10.1.2.3/8"), "10.0.0.0/8".parse().ok());
+        assert_eq!(parse_ip_net("2001:db8::1"), "2001:db8::1/128".parse().ok());
+        assert_eq!(parse_ip_net("2001:db8::/32"), "2001:db8::/32".parse().ok());
+        assert_eq!(
+            parse_ip_net("::ffff:10.0.0.0/104"),
+            "10.0.0.0/8".parse().ok()
+        );
+        assert_eq!(parse_ip_net("::ffff:10.1.2.3"), "10.1.2.3/32".parse().ok());
+        assert_eq!(parse_ip_net("10.1.2.300"), None);
+        assert_eq!(parse_ip_net("10.1.2.3/33"), None);
+        assert_eq!(parse_ip_net("fwcloud"), None);
+    }
+
+    #[test]
+    fn parses_lists() {
+        assert_eq!(parse_ip_net_list(""), Some(vec![]));
+        assert_eq!(
+            parse_ip_net_list("127.0.0.1  192.168.0.0/16 ::1").map(|l| l.len()),
+            Some(3)
+        );
+        assert!(parse_ip_net_list("127.0.0.1 bad 10.0.0.0/8").is_none());
+        assert!(validate_ip_net_list("127.0.0.1,10.0.0.1").is_err());
+    }
+
+    #[test]
+    fn matches_ips_against_list() {
+        let list = parse_ip_net_list("192.168.1.0/24 10.20.30.40 2001:db8::/32").unwrap();
+
+        assert!(ip_in_list("192.168.1.77".parse().unwrap(), &list));
+        assert!(ip_in_list("10.20.30.40".parse().unwrap(), &list));
+        assert!(ip_in_list("2001:db8:1::5".parse().unwrap(), &list));
+        assert!(ip_in_list("::ffff:192.168.1.77".parse().unwrap(), &list));
+        assert!(!ip_in_list("192.168.2.1".parse().unwrap(), &list));
+        assert!(!ip_in_list("10.20.30.41".parse().unwrap(), &list));
+        assert!(!ip_in_list("2001:db9::1".parse().unwrap(), &list));
+        assert!(!ip_in_list("::ffff:192.168.2.1".parse().unwrap(), &list));
+    }
+}
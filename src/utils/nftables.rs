@@ -0,0 +1,291 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::errors::Result;
+
+/*
+  Typed model of the output of `nft -j list ruleset`.
+
+  The nft JSON output is a flat array of objects (tables, chains, rules, sets, maps, ...) where
+  each object references its table and chain by name. Here we nest them (table -> chains -> rules)
+  which is much easier to consume. Rule expressions and set elements are kept as raw JSON values
+  because their schema is huge and they are only imported and compared by the console.
+*/
+
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct Ruleset {
+    pub metainfo: Option<MetaInfo>,
+    pub tables: Vec<Table>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct MetaInfo {
+    pub version: Option<String>,
+    pub release_name: Option<String>,
+    pub json_schema_version: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Table {
+    pub family: String,
+    pub name: String,
+    pub handle: u64,
+    #[serde(default)]
+    pub flags: Vec<String>,
+    #[serde(default)]
+    pub chains: Vec<Chain>,
+    #[serde(default)]
+    pub sets: Vec<Set>,
+    #[serde(default)]
+    pub maps: Vec<Set>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Chain {
+    pub family: String,
+    pub table: String,
+    pub name: String,
+    pub handle: u64,
+    #[serde(rename = "type")]
+    pub chain_type: Option<String>,
+    pub hook: Option<String>,
+    pub prio: Option<Value>,
+    pub policy: Option<String>,
+    pub dev: Option<Value>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Rule {
+    pub family: String,
+    pub table: String,
+    pub chain: String,
+    pub handle: u64,
+    pub index: Option<u64>,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub expr: Vec<Value>,
+}
+
+/// A named set or map. Maps are sets whose elements are key/value pairs and have the `map` field
+/// with the type of the values.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct Set {
+    pub family: String,
+    pub table: String,
+    pub name: String,
+    pub handle: u64,
+    #[serde(rename = "type")]
+    pub set_type: SetType,
+    pub map: Option<SetType>,
+    pub policy: Option<String>,
+    #[serde(default)]
+    pub flags: Vec<String>,
+    pub timeout: Option<u64>,
+    #[serde(rename = "gc-interval")]
+    pub gc_interval: Option<u64>,
+    pub size: Option<u64>,
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub elem: Vec<Value>,
+}
+
+/// Data type of a set, concatenations (like `ipv4_addr . inet_service`) are arrays of types.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum SetType {
+    Single(String),
+    Concat(Vec<String>),
+}
+
+// Every entry of the nftables array is an object with only one of these keys. Objects we are not
+// interested in (flowtables, counters, quotas, ct helpers, ...) are ignored.
+#[derive(Deserialize)]
+struct Entry {
+    metainfo: Option<MetaInfo>,
+    table: Option<Table>,
+    chain: Option<Chain>,
+    rule: Option<Rule>,
+    set: Option<Set>,
+    map: Option<Set>,
+}
+
+#[derive(Deserialize)]
+struct NftOutput {
+    nftables: Vec<Entry>,
+}
+
+impl Ruleset {
+    /// Parse the output of `nft -j list ruleset`.
+    pub fn parse(json: &str) -> Result<Ruleset> {
+        let output: NftOutput = serde_json::from_str(json)?;
+        let mut ruleset = Ruleset::default();
+        let mut chains: Vec<Chain> = Vec::new();
+        let mut rules: Vec<Rule> = Vec::new();
+        let mut sets: Vec<Set> = Vec::new();
+        let mut maps: Vec<Set> = Vec::new();
+
+        for entry in output.nftables {
+            if let Some(metainfo) = entry.metainfo {
+                ruleset.metainfo = Some(metainfo);
+            } else if let Some(table) = entry.table {
+                ruleset.tables.push(table);
+            } else if let Some(chain) = entry.chain {
+                chains.push(chain);
+            } else if let Some(rule) = entry.rule {
+                rules.push(rule);
+            } else if let Some(set) = entry.set {
+                sets.push(set);
+            } else if let Some(map) = entry.map {
+                maps.push(map);
+            }
+        }
+
+        // nft lists the objects of a table always after the table itself and the rules of a
+        // chain after the chain, but don't rely on it and keep orphans out of the result.
+        for rule in rules {
+            if let Some(chain) = chains
+                .iter_mut()
+                .find(|c| c.family == rule.family && c.table == rule.table && c.name == rule.chain)
+            {
+                chain.rules.push(rule);
+            }
+        }
+        for chain in chains {
+            if let Some(table) = ruleset.table_mut(&chain.family, &chain.table) {
+                table.chains.push(chain);
+            }
+        }
+        for set in sets {
+            if let Some(table) = ruleset.table_mut(&set.family, &set.table) {
+                table.sets.push(set);
+            }
+        }
+        for map in maps {
+            if let Some(table) = ruleset.table_mut(&map.family, &map.table) {
+                table.maps.push(map);
+            }
+        }
+
+        Ok(ruleset)
+    }
+
+    /// Keep only the tables of the given family and/or with the given name.
+    pub fn filter(&mut self, family: Option<&str>, table: Option<&str>) {
+        self.tables
+            .retain(|t| family.is_none_or(|f| t.family == f) && table.is_none_or(|n| t.name == n));
+    }
+
+    fn table_mut(&mut self, family: &str, name: &str) -> Option<&mut Table> {
+        self.tables
+            .iter_mut()
+            .find(|t| t.family == family && t.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULESET: &str = include_str!("../../tests/templates/nft-ruleset.json");
+
+    #[test]
+    fn parses_nft_json_ruleset() {
+        let ruleset = Ruleset::parse(RULESET).unwrap();
+
+        assert_eq!(
+            ruleset.metainfo.as_ref().unwrap().version.as_deref(),
+            Some("1.0.6")
+        );
+        assert_eq!(ruleset.tables.len(), 3);
+
+        let filter = &ruleset.tables[0];
+        assert_eq!(
+            (filter.family.as_str(), filter.name.as_str()),
+            ("inet", "filter")
+        );
+        assert_eq!(filter.chains.len(), 3);
+
+        let input = &filter.chains[0];
+        assert_eq!(input.name, "input");
+        assert_eq!(input.chain_type.as_deref(), Some("filter"));
+        assert_eq!(input.hook.as_deref(), Some("input"));
+        assert_eq!(input.policy.as_deref(), Some("drop"));
+        assert_eq!(input.rules.len(), 3);
+        assert_eq!(input.rules[2].comment.as_deref(), Some("fwcloud agent"));
+        assert_eq!(input.rules[2].expr.len(), 3);
+
+        assert_eq!(filter.sets.len(), 2);
+        assert_eq!(
+            filter.sets[0].set_type,
+            SetType::Single("ipv4_addr".to_string())
+        );
+        assert_eq!(filter.sets[0].flags, vec!["interval"]);
+        assert_eq!(filter.sets[0].elem.len(), 2);
+        assert_eq!(
+            filter.sets[1].set_type,
+            SetType::Concat(vec!["ipv4_addr".to_string(), "inet_service".to_string()])
+        );
+
+        assert_eq!(filter.maps.len(), 1);
+        assert_eq!(
+            filter.maps[0].map,
+            Some(SetType::Single("verdict".to_string()))
+        );
+        assert_eq!(filter.maps[0].elem.len(), 2);
+
+        let nat = &ruleset.tables[1];
+        assert_eq!((nat.family.as_str(), nat.name.as_str()), ("ip", "nat"));
+        assert_eq!(nat.chains[0].rules.len(), 1);
+        assert!(nat.sets.is_empty() && nat.maps.is_empty());
+    }
+
+    #[test]
+    fn filters_by_family_and_table() {
+        let mut ruleset = Ruleset::parse(RULESET).unwrap();
+        ruleset.filter(Some("ip6"), None);
+        assert_eq!(ruleset.tables.len(), 1);
+        assert_eq!(ruleset.tables[0].name, "filter");
+
+        let mut ruleset = Ruleset::parse(RULESET).unwrap();
+        ruleset.filter(None, Some("filter"));
+        assert_eq!(ruleset.tables.len(), 2);
+
+        let mut ruleset = Ruleset::parse(RULESET).unwrap();
+        ruleset.filter(Some("inet"), Some("nat"));
+        assert!(ruleset.tables.is_empty());
+    }
+
+    #[test]
+    fn parses_empty_ruleset() {
+        let ruleset = Ruleset::parse(
+            r#"{"nftables": [{"metainfo": {"version": "1.0.6", "release_name": "Lester Gooch #5", "json_schema_version": 1}}]}"#,
+        )
+        .unwrap();
+        assert!(ruleset.tables.is_empty());
+        assert!(Ruleset::parse("not json").is_err());
+    }
+}
@@ -0,0 +1,576 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::audit::audit_event;
+use crate::config::Config;
+use crate::errors::{FwcError, Result};
+use crate::utils::cmd::{run_cmd_input, run_cmd_output, CmdKind};
+use crate::utils::drift;
+use crate::utils::iptables;
+
+/// Firewall ruleset saved before installing a policy, for restoring it if the policy is not
+/// confirmed.
+///
+/// Both the iptables rules (IPv4 and IPv6) and the nftables ruleset are saved, the commands that
+/// are not installed are skipped. The iptables rules are restored first and then the nftables
+/// ruleset. With the iptables nft backend the nft output of the iptables tables often can't be
+/// loaded back (xt matches and targets), so then the nftables restore is only a best effort.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub nftables: Option<String>,
+    pub iptables: Option<String>,
+    pub ip6tables: Option<String>,
+    // Backend (`legacy` or `nft`) of iptables when the snapshot was taken.
+    #[serde(default)]
+    pub iptables_backend: Option<String>,
+}
+
+/// Installed policy that has not been confirmed yet.
+///
+/// It is also saved in data_dir/pending_policy.json, this way it is rolled back even if the agent
+/// is restarted before confirming it. If the rollback fails the policy stays pending with the
+/// error, until it is confirmed.
+#[derive(Serialize, Deserialize)]
+pub struct PendingPolicy {
+    id: Uuid,
+    snapshot: Snapshot,
+    started: u64,
+    rollback_timeout: u64,
+    // Time (seconds since the epoch) when it is rolled back if it is not confirmed.
+    deadline: u64,
+    rollback_error: Option<String>,
+}
+
+/// State of the policy apply pending of confirmation.
+#[derive(Serialize)]
+pub struct PolicyApply {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub started: u64,
+    pub rollback_timeout: u64,
+    pub remaining: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollback_error: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Output of a command, `None` if it is not installed.
+fn saved_output(cmd: &str, args: &[&str], timeout: Option<Duration>) -> Result<Option<String>> {
+    match run_cmd_output(cmd, args, timeout) {
+        Ok(output) => Ok(Some(output)),
+        Err(FwcError::PopenError(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl Snapshot {
+    pub fn take(cfg: &Config) -> Result<Snapshot> {
+        let timeout = CmdKind::Other.timeout(cfg, None);
+        let snapshot = Snapshot {
+            nftables: saved_output("nft", &["list", "ruleset"], timeout)?,
+            iptables: saved_output("iptables-save", &[], timeout)?,
+            ip6tables: saved_output("ip6tables-save", &[], timeout)?,
+            iptables_backend: iptables::detect_backend(false, timeout)?.map(|info| info.backend),
+        };
+
+        if snapshot.nftables.is_none() && snapshot.iptables.is_none() {
+            return Err(FwcError::Internal(
+                "Neither nft nor iptables-save are available for saving the ruleset",
+            ));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Restore the saved ruleset. After an error the rest of the ruleset is restored anyway, and
+    /// all the errors are returned.
+    pub fn restore(&self, cfg: &Config) -> Result<()> {
+        let timeout = CmdKind::Other.timeout(cfg, None);
+        self.restore_with(|cmd, args, input| run_cmd_input(cmd, args, input, timeout))
+    }
+
+    // Restore running the commands with `run`, the tests use a fake one instead of changing the
+    // firewall of the host.
+    fn restore_with(&self, run: impl Fn(&str, &[&str], &str) -> Result<()>) -> Result<()> {
+        let steps: [(&str, &[&str], Option<String>); 3] = [
+            ("iptables-restore", &[], self.iptables.clone()),
+            ("ip6tables-restore", &[], self.ip6tables.clone()),
+            (
+                "nft",
+                &["-f", "-"],
+                self.nftables
+                    .as_ref()
+                    .map(|r| format!("flush ruleset\n{r}")),
+            ),
+        ];
+        // The iptables tables are already restored, the nft load is atomic and a failure leaves them as they are.
+        let nft_best_effort =
+            self.iptables.is_some() && self.iptables_backend.as_deref() == Some("nft");
+
+        let mut errors = Vec::new();
+        for (cmd, args, input) in steps {
+            if let Some(input) = input {
+                match run(cmd, args, &input) {
+                    Err(e) if cmd == "nft" && nft_best_effort => {
+                        warn!("The nftables ruleset could not be restored, only the iptables rules have been restored: {e}");
+                    }
+                    Err(e) => {
+                        error!("Error restoring the ruleset with {cmd}: {e}");
+                        errors.push(format!("{cmd}: {e}"));
+                    }
+                    Ok(()) => {}
+                }
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(FwcError::RulesetNotRestored(errors.join(", "))),
+        }
+    }
+}
+
+/// Check that there is no policy pending of confirmation and save the current ruleset if the policy
+/// is going to be installed with rollback.
+pub fn prepare(cfg: &Config, rollback_timeout: Option<u64>) -> Result<Option<Snapshot>> {
+    if cfg.pending_policy.lock().unwrap().is_some() {
+        return Err(FwcError::PolicyApplyPending);
+    }
+
+    match rollback_timeout {
+        Some(_) => Ok(Some(Snapshot::take(cfg)?)),
+        None => Ok(None),
+    }
+}
+
+/// Finish the installation of a policy. If it was installed with rollback, the ruleset is restored
+/// right away when it failed, otherwise the snapshot is kept until the policy is confirmed or
+/// rolled back.
+pub fn finish<T>(
+    cfg: &Config,
+    snapshot: Option<Snapshot>,
+    rollback_timeout: Option<u64>,
+    res: Result<T>,
+) -> Result<(T, Option<PolicyApply>)> {
+    let res = match (snapshot, rollback_timeout) {
+        (Some(snapshot), Some(timeout)) => match res {
+            Ok(res) => Ok((res, Some(wait_confirmation(cfg, snapshot, timeout)))),
+            Err(e) => {
+                warn!("FWCloud script failed, restoring the previous ruleset");
+                let _ = snapshot.restore(cfg);
+                drift::record_baseline(cfg);
+                return Err(e);
+            }
+        },
+        _ => Ok((res?, None)),
+    };
+
+    drift::record_baseline(cfg);
+    res
+}
+
+// Keep the snapshot of a just installed policy until it is confirmed or rolled back.
+fn wait_confirmation(cfg: &Config, snapshot: Snapshot, rollback_timeout: u64) -> PolicyApply {
+    let started = now();
+    let pending = PendingPolicy {
+        id: Uuid::new_v4(),
+        snapshot,
+        started,
+        rollback_timeout,
+        deadline: started + rollback_timeout,
+        rollback_error: None,
+    };
+    let apply = pending.state();
+    info!("Policy installed, waiting for confirmation");
+    if let Err(e) = save(cfg, &pending) {
+        error!("Error saving the pending policy, it will not be rolled back after a restart: {e}");
+    }
+
+    debug!(
+        "Locking pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+    *cfg.pending_policy.lock().unwrap() = Some(pending);
+    debug!(
+        "Releasing pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+
+    apply
+}
+
+impl PendingPolicy {
+    fn state(&self) -> PolicyApply {
+        PolicyApply {
+            id: self.id,
+            started: self.started,
+            rollback_timeout: self.rollback_timeout,
+            remaining: self.deadline.saturating_sub(now()),
+            rollback_error: self.rollback_error.clone(),
+        }
+    }
+}
+
+fn pending_file(cfg: &Config) -> String {
+    format!("{}/pending_policy.json", cfg.data_dir)
+}
+
+// Saved with a rename, the snapshot can contain details of the network that only root may read.
+fn save(cfg: &Config, pending: &PendingPolicy) -> Result<()> {
+    let path = pending_file(cfg);
+    let tmp = format!("{path}.tmp");
+    let _ = fs::remove_file(&tmp);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)?;
+    file.write_all(&serde_json::to_vec(pending)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+
+    Ok(())
+}
+
+fn remove(cfg: &Config) {
+    if let Err(e) = fs::remove_file(pending_file(cfg)) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!("Error removing the pending policy: {e}");
+        }
+    }
+}
+
+/// State of the policy pending of confirmation, if any.
+pub fn pending(cfg: &Config) -> Option<PolicyApply> {
+    debug!(
+        "Locking pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+    let apply = cfg
+        .pending_policy
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(PendingPolicy::state);
+    debug!(
+        "Releasing pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+
+    apply
+}
+
+/// Confirm the installed policy, its snapshot is discarded.
+pub fn confirm(cfg: &Config) -> Result<()> {
+    debug!(
+        "Locking pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+    cfg.pending_policy
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or(FwcError::NoPolicyApplyPending)?;
+    debug!(
+        "Releasing pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+    remove(cfg);
+
+    info!("Installed policy confirmed");
+    Ok(())
+}
+
+/// Roll back the policy `id` after `timeout` seconds if it has not been confirmed.
+pub fn schedule_rollback(cfg: &Arc<Config>, id: Uuid, timeout: u64) {
+    let cfg = Arc::clone(cfg);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(timeout)).await;
+        let _ = tokio::task::spawn_blocking(move || rollback(&cfg, id)).await;
+    });
+}
+
+/// Take again the policy left pending by a previous run of the agent, it is rolled back when its
+/// deadline expires as if the agent had not been restarted.
+pub fn recover(cfg: &Arc<Config>) {
+    let pending = match fs::read(pending_file(cfg)) {
+        Ok(data) => match serde_json::from_slice::<PendingPolicy>(&data) {
+            Ok(pending) => pending,
+            Err(e) => {
+                error!("Error reading the pending policy: {e}");
+                return;
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            error!("Error reading the pending policy: {e}");
+            return;
+        }
+    };
+
+    let (id, remaining) = (pending.id, pending.deadline.saturating_sub(now()));
+    let failed = pending.rollback_error.is_some();
+    match failed {
+        true => warn!("Rollback of the installed policy failed, it must be confirmed"),
+        false => warn!(
+            "Installed policy not confirmed before the restart, rolling it back in {remaining} seconds"
+        ),
+    }
+    *cfg.pending_policy.lock().unwrap() = Some(pending);
+    if !failed {
+        schedule_rollback(cfg, id, remaining);
+    }
+}
+
+/// Restore the ruleset saved before installing the policy `id` if it is still pending of
+/// confirmation. The result is recorded in the audit log, and if the ruleset can't be restored the
+/// policy stays pending with the error.
+pub fn rollback(cfg: &Config, id: Uuid) -> bool {
+    // Not at the same time as the installs, the policy is not pending while it is rolled back.
+    debug!("Locking script mutex (thread id: {})", thread_id::get());
+    let _mutex_data = cfg.mutex.fwcloud_script.blocking_lock();
+    debug!("Script mutex locked (thread id: {})", thread_id::get());
+
+    debug!(
+        "Locking pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+    let policy = cfg
+        .pending_policy
+        .lock()
+        .unwrap()
+        .take_if(|policy| policy.id == id && policy.rollback_error.is_none());
+    debug!(
+        "Releasing pending policy mutex (thread id: {})",
+        thread_id::get()
+    );
+    let mut policy = match policy {
+        Some(policy) => policy,
+        None => return false,
+    };
+
+    warn!("Installed policy not confirmed, restoring the previous ruleset");
+    let res = policy.snapshot.restore(cfg);
+    drift::record_baseline(cfg);
+
+    let mut params = Map::new();
+    params.insert(String::from("started"), policy.started.into());
+    audit_event(
+        cfg,
+        "policy_rollback",
+        params,
+        res.as_ref().err().map(|e| e.to_string()),
+    );
+
+    match res {
+        Ok(()) => remove(cfg),
+        Err(e) => {
+            policy.rollback_error = Some(e.to_string());
+            if let Err(e) = save(cfg, &policy) {
+                error!("Error saving the pending policy: {e}");
+            }
+            *cfg.pending_policy.lock().unwrap() = Some(policy);
+        }
+    }
+    debug!("Releasing script mutex (thread id: {})", thread_id::get());
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use std::env;
+    use std::path::Path;
+
+    #[test]
+    #[serial]
+    fn confirms_and_rolls_back_pending_policy() {
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        env::set_var("DATA_DIR", dir.to_str().unwrap());
+        let cfg = Config::new().unwrap();
+        env::remove_var("API_KEY");
+        env::remove_var("DATA_DIR");
+        assert!(pending(&cfg).is_none());
+        assert!(prepare(&cfg, None).unwrap().is_none());
+
+        let apply = wait_confirmation(&cfg, Snapshot::default(), 30);
+        let state = pending(&cfg).unwrap();
+        assert_eq!(state.rollback_timeout, 30);
+        assert!(state.remaining <= 30 && state.remaining >= 29);
+        assert!(matches!(
+            prepare(&cfg, None),
+            Err(FwcError::PolicyApplyPending)
+        ));
+
+        assert!(!rollback(&cfg, Uuid::new_v4()));
+        assert!(rollback(&cfg, apply.id));
+        assert!(pending(&cfg).is_none());
+        assert!(matches!(confirm(&cfg), Err(FwcError::NoPolicyApplyPending)));
+
+        let apply = wait_confirmation(&cfg, Snapshot::default(), 30);
+        assert!(Path::new(&pending_file(&cfg)).exists());
+        confirm(&cfg).unwrap();
+        assert!(!Path::new(&pending_file(&cfg)).exists());
+        assert!(!rollback(&cfg, apply.id));
+        assert!(pending(&cfg).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn keeps_pending_policy_after_failed_rollback() {
+        // Only where nft is not installed, this way the restore fails without touching the
+        // firewall of the host.
+        if !matches!(
+            run_cmd_output("nft", &["--version"], None),
+            Err(FwcError::PopenError(_))
+        ) {
+            return;
+        }
+
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        env::set_var("DATA_DIR", dir.to_str().unwrap());
+        let cfg = Config::new().unwrap();
+        env::remove_var("API_KEY");
+        env::remove_var("DATA_DIR");
+
+        // A failed rollback keeps the policy pending with the error until it is confirmed.
+        let snapshot = Snapshot {
+            nftables: Some(String::from("table inet filter {}")),
+            ..Snapshot::default()
+        };
+        let apply = wait_confirmation(&cfg, snapshot, 30);
+        assert!(rollback(&cfg, apply.id));
+        assert!(pending(&cfg).unwrap().rollback_error.is_some());
+        assert!(!rollback(&cfg, apply.id));
+        assert!(Path::new(&pending_file(&cfg)).exists());
+        confirm(&cfg).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restores_iptables_first_and_nftables_as_best_effort() {
+        let ran = std::cell::RefCell::new(Vec::new());
+        let fail_nft = |cmd: &str, _: &[&str], _: &str| {
+            ran.borrow_mut().push(cmd.to_string());
+            match cmd {
+                "nft" => Err(FwcError::Internal("Could not process rule")),
+                _ => Ok(()),
+            }
+        };
+
+        let mut snapshot = Snapshot {
+            nftables: Some(String::from("table ip filter {}")),
+            iptables: Some(String::from("*filter\nCOMMIT\n")),
+            ip6tables: Some(String::from("*filter\nCOMMIT\n")),
+            iptables_backend: Some(String::from("nft")),
+        };
+        snapshot.restore_with(fail_nft).unwrap();
+        assert_eq!(
+            *ran.borrow(),
+            vec!["iptables-restore", "ip6tables-restore", "nft"]
+        );
+
+        // With the legacy backend, or without iptables, the nftables ruleset is only in the nft
+        // output.
+        snapshot.iptables_backend = Some(String::from("legacy"));
+        assert!(matches!(
+            snapshot.restore_with(fail_nft),
+            Err(FwcError::RulesetNotRestored(_))
+        ));
+        let snapshot = Snapshot {
+            nftables: Some(String::from("table inet filter {}")),
+            ..Snapshot::default()
+        };
+        assert!(matches!(
+            snapshot.restore_with(fail_nft),
+            Err(FwcError::RulesetNotRestored(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn rolls_back_policy_pending_before_restart() {
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        env::set_var("DATA_DIR", dir.to_str().unwrap());
+        let cfg = Config::new().unwrap();
+        let restarted = Arc::new(Config::new().unwrap());
+        env::remove_var("API_KEY");
+        env::remove_var("DATA_DIR");
+
+        let mut policy = PendingPolicy {
+            id: Uuid::new_v4(),
+            snapshot: Snapshot::default(),
+            started: now() - 60,
+            rollback_timeout: 30,
+            deadline: now() - 30,
+            rollback_error: None,
+        };
+        save(&cfg, &policy).unwrap();
+        recover(&restarted);
+        assert!(pending(&restarted).is_some());
+        for _ in 0..50 {
+            if pending(&restarted).is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(pending(&restarted).is_none());
+        assert!(!Path::new(&pending_file(&restarted)).exists());
+
+        // A failed rollback is not retried, the policy must be confirmed.
+        policy.rollback_error = Some(String::from("nft: Command exit status not 0"));
+        save(&cfg, &policy).unwrap();
+        recover(&restarted);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            pending(&restarted).unwrap().rollback_error,
+            policy.rollback_error
+        );
+        confirm(&restarted).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
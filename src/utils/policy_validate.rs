@@ -0,0 +1,288 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::errors::Result;
+use crate::utils::cmd::{run_cmd_status, CmdKind};
+
+/*
+  Dry-run validation of a FWCloud script.
+
+  First its syntax is checked (sh -n, the same shell that installs it) and then it is started
+  inside throwaway network, mount, PID, IPC and UTS namespaces. There iptables, nft and ipset
+  commands work over their own empty ruleset leaving the live firewall untouched, all the file
+  systems are read-only, /tmp, /var/tmp and /run are private tmpfs and only the processes of the
+  script are visible. If any file system can't be made read-only the script is not run. The script is run with tracing enabled for knowing
+  the line of every command, and whatever the commands write to stderr is reported as an error of
+  that line. The script is terminated if it runs for longer than FWCLOUD_SCRIPT_TIMEOUT.
+
+  Rules that need the real network interfaces (like nft iif with an interface index) can fail in
+  the namespace even if they are right. The line of the errors of the commands is only known if sh
+  supports LINENO (bash does, older dash versions don't), the errors of the shell itself always
+  have it.
+*/
+
+// The script is opened before the private tmpfs can hide it and sourced instead of run, this way
+// its line numbers are kept in LINENO. The script is not run if the namespace can't be isolated.
+// PS4 must be set here because bash ignores it from the environment when running as root.
+const SANDBOX_WRAPPER: &str = r#"exec 3< "$0" || exit 125
+mount -o remount,bind,ro / || exit 125
+for mnt in $(cut -d ' ' -f 5 /proc/self/mountinfo); do mount -o remount,bind,ro "$mnt" || exit 125; done
+for dir in /tmp /var/tmp /run; do [ ! -d "$dir" ] || mount -t tmpfs fwcloud-validate "$dir" || exit 125; done
+PS4='+[$LINENO] '; set -x; . /dev/fd/3"#;
+
+lazy_static! {
+    // Shell error messages, with the line after the name of the script. For example, from bash:
+    // "/tmp/fwcloud.sh: line 12: syntax error near unexpected token `fi'", and from dash:
+    // "/tmp/fwcloud.sh: 12: Syntax error: "fi" unexpected" or, for the sourced script,
+    // "/tmp/fwcloud.sh: 12: /dev/fd/3: ipsett: not found".
+    static ref BASH_ERROR: Regex =
+        Regex::new(r"^.*?: (?:line )?(\d+): (?:/dev/fd/3: )?(.*)$").unwrap();
+    // Trace of a command, "+" repeated for each nesting level.
+    static ref TRACE: Regex = Regex::new(r"^\++\[(\d*)\] ").unwrap();
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct ScriptError {
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Validation {
+    pub valid: bool,
+    pub exit_code: Option<u32>,
+    pub errors: Vec<ScriptError>,
+}
+
+/// Check the syntax of the script and, if it is right, run its `start` action in new network,
+/// mount, PID, IPC and UTS namespaces. It blocks until the script finishes, it must be run in the blocking thread pool.
+pub fn validate(cfg: &Config, script: &str) -> Result<Validation> {
+    let timeout = CmdKind::FwcloudScript.timeout(cfg, None);
+
+    let (status, _, stderr) = run_cmd_status("sh", &["-n", script], timeout)?;
+    let errors = syntax_errors(&stderr);
+    if !status.success() || !errors.is_empty() {
+        return Ok(Validation {
+            valid: false,
+            exit_code: None,
+            errors,
+        });
+    }
+
+    let (status, _, stderr) = run_cmd_status(
+        "unshare",
+        &[
+            "--net",
+            "--mount",
+            "--pid",
+            "--fork",
+            "--mount-proc",
+            "--ipc",
+            "--uts",
+            "--propagation",
+            "private",
+            "--",
+            "sh",
+            "-c",
+            SANDBOX_WRAPPER,
+            script,
+            "start",
+        ],
+        timeout,
+    )?;
+    let errors = trace_errors(&stderr);
+    let exit_code = match status {
+        subprocess::ExitStatus::Exited(code) => Some(code),
+        _ => None,
+    };
+
+    Ok(Validation {
+        valid: exit_code == Some(0) && errors.is_empty(),
+        exit_code,
+        errors,
+    })
+}
+
+fn bash_error(line: &str) -> ScriptError {
+    match BASH_ERROR.captures(line) {
+        Some(caps) => ScriptError {
+            line: caps[1].parse().ok(),
+            message: caps[2].to_string(),
+        },
+        None => ScriptError {
+            line: None,
+            message: line.to_string(),
+        },
+    }
+}
+
+// Errors of `sh -n`, bash repeats the line with the error after its message and it is skipped.
+fn syntax_errors(stderr: &str) -> Vec<ScriptError> {
+    let mut errors: Vec<ScriptError> = Vec::new();
+    for error in stderr.lines().filter(|l| !l.is_empty()).map(bash_error) {
+        let repeated = errors
+            .last()
+            .is_some_and(|last| last.line == error.line && error.message.starts_with('`'));
+        if !repeated {
+            errors.push(error);
+        }
+    }
+    errors
+}
+
+// Output lines of the traced script that are not traces are errors of the last traced command.
+fn trace_errors(stderr: &str) -> Vec<ScriptError> {
+    let mut errors = Vec::new();
+    let mut line = None;
+
+    for output in stderr.lines().filter(|l| !l.is_empty()) {
+        if let Some(caps) = TRACE.captures(output) {
+            line = caps[1].parse().ok();
+            continue;
+        }
+
+        let mut error = bash_error(output);
+        if error.line.is_none() {
+            error.line = line;
+        }
+        errors.push(error);
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    fn error(line: u32, message: &str) -> ScriptError {
+        ScriptError {
+            line: Some(line),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_syntax_errors() {
+        let stderr = "/tmp/a.tmp: line 6: syntax error near unexpected token `then'\n/tmp/a.tmp: line 6: `if then'\n";
+        assert_eq!(
+            syntax_errors(stderr),
+            vec![error(6, "syntax error near unexpected token `then'")]
+        );
+        assert_eq!(
+            syntax_errors("/tmp/a.tmp: 6: Syntax error: \"then\" unexpected\n"),
+            vec![error(6, "Syntax error: \"then\" unexpected")]
+        );
+        assert!(syntax_errors("").is_empty());
+    }
+
+    #[test]
+    fn parses_errors_of_traced_script() {
+        let stderr = "+[1] . /tmp/a.tmp start
+++[14] policy_load
+++[9] iptables -A INPUT -p tcp --dprt 22 -j ACCEPT
+iptables v1.8.9 (nf_tables): unknown option \"--dprt\"
+Try `iptables -h' or 'iptables --help' for more information.
+++[10] nft add rule inet filter input tcp dport 22 accept
+++[11] ipsett create trusted hash:ip
+/tmp/a.tmp: line 11: ipsett: command not found
++[] ipsett list
+/tmp/a.tmp: 12: /dev/fd/3: ipsett: not found
+++[15] exit 0
+";
+        assert_eq!(
+            trace_errors(stderr),
+            vec![
+                error(9, "iptables v1.8.9 (nf_tables): unknown option \"--dprt\""),
+                error(
+                    9,
+                    "Try `iptables -h' or 'iptables --help' for more information."
+                ),
+                error(11, "ipsett: command not found"),
+                error(12, "ipsett: not found"),
+            ]
+        );
+    }
+
+    fn config() -> Config {
+        std::env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        let cfg = Config::new().unwrap();
+        std::env::remove_var("API_KEY");
+        cfg
+    }
+
+    #[test]
+    #[serial]
+    fn reports_syntax_errors_without_running_the_script() {
+        let script = std::env::temp_dir().join(format!("{}.sh", uuid::Uuid::new_v4()));
+        std::fs::write(&script, "#!/bin/sh\necho start\nif then\n").unwrap();
+
+        let cfg = config();
+        let validation = validate(&cfg, script.to_str().unwrap()).unwrap();
+        assert!(!validation.valid);
+        assert_eq!(validation.exit_code, None);
+        assert_eq!(validation.errors.len(), 1);
+        assert_eq!(validation.errors[0].line, Some(3));
+
+        std::fs::remove_file(script).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn runs_the_script_without_writing_files() {
+        // The namespaces can only be created by root.
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        // Out of /tmp, that is a private tmpfs in the namespace.
+        let dir = std::env::current_dir()
+            .unwrap()
+            .join(format!("fwcloud-agent-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("fwcloud.sh");
+        let written = dir.join("written");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n[ $$ -eq 1 ] || exit 3\ntouch /tmp/fwcloud-validate && echo $1 > {}\n",
+                written.display()
+            ),
+        )
+        .unwrap();
+
+        let validation = validate(&config(), script.to_str().unwrap()).unwrap();
+        assert!(!validation.valid);
+        assert_eq!(validation.exit_code, Some(2));
+        assert!(validation.errors[0]
+            .message
+            .contains("Read-only file system"));
+        assert!(!written.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::audit::Requester;
+use crate::config::Config;
+use crate::errors::{FwcError, Result};
+use crate::utils::cmd::{run_diff, CmdKind};
+use crate::utils::http_files::write_file;
+
+/*
+  History of the installed FWCloud scripts, the last SCRIPT_HISTORY_SIZE ones are kept in
+  data_dir/script_history as <id>.sh with their metadata in <id>.json.
+*/
+
+/// Installed FWCloud script.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScriptEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    /// Path where the script was installed.
+    pub path: String,
+    pub size: usize,
+    pub sha256: String,
+    /// Identity of the uploader, the same data of the audit log entry of the request.
+    #[serde(flatten)]
+    pub uploader: Requester,
+    /// Result of the install and start sequence.
+    pub installed: bool,
+    pub error: Option<String>,
+    /// Id of the history entry reinstalled, if this is not a new upload.
+    pub reinstall_of: Option<u64>,
+    /// The script came with a valid signature of the console.
+    #[serde(default)]
+    pub signed: bool,
+    /// Permissions of the installed script, for installing it again with the same ones.
+    #[serde(default)]
+    pub perms: Option<u32>,
+}
+
+fn history_dir(cfg: &Config) -> String {
+    format!("{}/script_history", cfg.data_dir)
+}
+
+/// Entries of the history from the newest to the oldest one.
+pub fn list(cfg: &Config) -> Result<Vec<ScriptEntry>> {
+    let mut entries = Vec::new();
+    let dir = match fs::read_dir(history_dir(cfg)) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(e.into()),
+    };
+
+    for file in dir {
+        let path = file?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            // A corrupt entry doesn't hide the rest of the history.
+            match fs::read_to_string(&path)
+                .map_err(FwcError::from)
+                .and_then(|data| Ok(serde_json::from_str::<ScriptEntry>(&data)?))
+            {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("Error reading history entry '{}': {e}", path.display()),
+            }
+        }
+    }
+    entries.sort_by_key(|e| std::cmp::Reverse(e.id));
+
+    Ok(entries)
+}
+
+/// Metadata and content of a script of the history.
+pub fn get(cfg: &Config, id: u64) -> Result<(ScriptEntry, String)> {
+    let base = format!("{}/{id}", history_dir(cfg));
+    let metadata = fs::read_to_string(format!("{base}.json")).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => FwcError::ScriptNotFound,
+        _ => e.into(),
+    })?;
+
+    Ok((
+        serde_json::from_str(&metadata)?,
+        fs::read_to_string(format!("{base}.sh"))?,
+    ))
+}
+
+/// Unified diff between two scripts of the history, empty if they are equal. It blocks until diff
+/// finishes, it must be run in the blocking thread pool.
+pub fn diff(cfg: &Config, from: u64, to: u64) -> Result<String> {
+    // Check that both exist for not returning the diff error.
+    get(cfg, from)?;
+    get(cfg, to)?;
+
+    let dir = history_dir(cfg);
+    run_diff(
+        &format!("{dir}/{from}.sh"),
+        &format!("{dir}/{to}.sh"),
+        &format!("fwcloud.sh #{from}"),
+        &format!("fwcloud.sh #{to}"),
+        CmdKind::Other.timeout(cfg, None),
+    )
+}
+
+/// Add an installed script to the history and remove the oldest ones. Errors are logged, they must
+/// not change the result of the script installation.
+pub fn record<T>(
+    cfg: &Config,
+    script: &str,
+    uploader: &Requester,
+    res: &Result<T>,
+    reinstall_of: Option<u64>,
+    signed: bool,
+) {
+    if cfg.script_history_size == 0 {
+        return;
+    }
+
+    let perms = fs::metadata(script)
+        .ok()
+        .map(|metadata| metadata.permissions().mode() & 0o777);
+    let result = fs::read(script).map_err(FwcError::from).and_then(|data| {
+        let entry = ScriptEntry {
+            id: list(cfg)?.first().map(|e| e.id + 1).unwrap_or(1),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            path: String::from(script),
+            size: data.len(),
+            sha256: format!("{:x}", Sha256::digest(&data)),
+            uploader: uploader.clone(),
+            installed: res.is_ok(),
+            error: res.as_ref().err().map(|e| e.to_string()),
+            reinstall_of,
+            signed,
+            perms,
+        };
+        save(cfg, &entry, &data)?;
+        info!("FWCloud script {} added to the history", entry.id);
+        prune(cfg)
+    });
+
+    if let Err(e) = result {
+        error!("Error adding the FWCloud script to the history: {e}");
+    }
+}
+
+fn save(cfg: &Config, entry: &ScriptEntry, data: &[u8]) -> Result<()> {
+    let dir = history_dir(cfg);
+    fs::create_dir_all(&dir)?;
+
+    // The metadata goes last, the entry is not listed until the script is complete.
+    for (ext, content) in [("sh", data), ("json", &serde_json::to_vec(entry)?)] {
+        write_file(&format!("{dir}/{}.{ext}", entry.id), content, 0o600)?;
+    }
+
+    Ok(())
+}
+
+fn prune(cfg: &Config) -> Result<()> {
+    let dir = history_dir(cfg);
+    for entry in list(cfg)?.iter().skip(cfg.script_history_size) {
+        fs::remove_file(format!("{dir}/{}.sh", entry.id))?;
+        fs::remove_file(format!("{dir}/{}.json", entry.id))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use std::env;
+    use uuid::Uuid;
+
+    #[test]
+    #[serial]
+    fn records_lists_and_diffs_scripts() {
+        let dir = env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        env::set_var("API_KEY", "d64c88318c8f213f427af857d0013f93");
+        env::set_var("DATA_DIR", dir.to_str().unwrap());
+        let mut cfg = Config::new().unwrap();
+        env::remove_var("API_KEY");
+        env::remove_var("DATA_DIR");
+        cfg.script_history_size = 2;
+
+        assert!(list(&cfg).unwrap().is_empty());
+        assert!(matches!(get(&cfg, 1), Err(FwcError::ScriptNotFound)));
+
+        let script = dir.join("fwcloud.sh");
+        let script = script.to_str().unwrap();
+        let results = vec![Ok(()), Err(FwcError::CmdExitStatusNotZero), Ok(())];
+        for (n, res) in results.into_iter().enumerate() {
+            fs::write(script, format!("#!/bin/sh\necho policy {n}\n")).unwrap();
+            fs::set_permissions(script, fs::Permissions::from_mode(0o700)).unwrap();
+            record(&cfg, script, &Requester::default(), &res, None, n == 2);
+        }
+
+        let entries = list(&cfg).unwrap();
+        assert_eq!(entries.iter().map(|e| e.id).collect::<Vec<_>>(), vec![3, 2]);
+        assert!(entries[0].installed);
+        assert!(!entries[1].installed);
+        assert!(entries[0].signed);
+        assert!(!entries[1].signed);
+        assert_eq!(entries[0].perms, Some(0o700));
+        assert_eq!(
+            entries[1].error.as_deref(),
+            Some("Command exit status not 0")
+        );
+        assert_eq!(entries[0].path, script);
+        assert_eq!(
+            entries[0].sha256,
+            format!("{:x}", Sha256::digest(b"#!/bin/sh\necho policy 2\n"))
+        );
+        assert!(matches!(get(&cfg, 1), Err(FwcError::ScriptNotFound)));
+
+        let (entry, content) = get(&cfg, 2).unwrap();
+        assert_eq!(entry, entries[1]);
+        assert_eq!(content, "#!/bin/sh\necho policy 1\n");
+
+        let diff = diff(&cfg, 2, 3).unwrap();
+        assert!(diff.starts_with("--- fwcloud.sh #2\n+++ fwcloud.sh #3\n"));
+        assert!(diff.contains("\n-echo policy 1\n+echo policy 2\n"));
+        assert_eq!(super::diff(&cfg, 3, 3).unwrap(), "");
+        assert!(matches!(
+            super::diff(&cfg, 1, 3),
+            Err(FwcError::ScriptNotFound)
+        ));
+
+        // A corrupt entry is skipped, and the scripts are still recorded.
+        let history = dir.join("script_history");
+        fs::write(history.join("4.json"), "{\"id\":4,\"timest").unwrap();
+        assert_eq!(
+            list(&cfg).unwrap().iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+        record(&cfg, script, &Requester::default(), &Ok(()), None, false);
+        assert_eq!(
+            list(&cfg).unwrap().iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![4, 3]
+        );
+        assert!(!history.join("4.json.new").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use openssl::base64;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::sign::Verifier;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::{FwcError, Result};
+
+/*
+  Detached signatures of the FWCloud scripts, made by the console with its private key and verified
+  with its public key (PEM) before running the script. Ed25519 and RSA (PKCS#1 v1.5 with SHA-256)
+  keys are supported.
+
+  Besides the script, the signature covers the path where it must be installed and the time
+  (seconds since the epoch) when the signature expires. This way a signed script can't be installed
+  in another path, and it can't be replayed for going back to an old policy once it expires. The
+  signature file (fwcloud.sh.sig) has the signed path and expiry followed by the base64 signature:
+
+    path: /etc/fwcloud/fwcloud.sh
+    expires: 1767225600
+    signature: <base64>
+
+  The signed data are the path and expiry lines followed by the script, for example:
+
+    printf 'path: %s\nexpires: %s\n' /etc/fwcloud/fwcloud.sh 1767225600 | cat - fwcloud.sh > signed
+    openssl pkeyutl -sign -rawin -inkey console.key -in signed | base64 -w0   (Ed25519)
+    openssl dgst -sha256 -sign console.key signed | base64 -w0               (RSA)
+*/
+
+/// Detached signature of a FWCloud script, with the path and expiry signed along with the script.
+#[derive(Debug)]
+pub struct ScriptSignature {
+    pub path: String,
+    pub expires: u64,
+    signature: Vec<u8>,
+}
+
+impl ScriptSignature {
+    /// Parse a signature file, with its `path`, `expires` and `signature` lines in this order.
+    pub fn parse(data: &[u8]) -> Result<ScriptSignature> {
+        let data = std::str::from_utf8(data).map_err(|_| FwcError::ScriptSignatureNotValid)?;
+        let mut lines = data.lines();
+        let mut field = |name: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name))
+                .and_then(|line| line.strip_prefix(": "))
+                .map(str::trim)
+                .ok_or(FwcError::ScriptSignatureNotValid)
+        };
+
+        let path = field("path")?.to_string();
+        let expires = field("expires")?
+            .parse()
+            .map_err(|_| FwcError::ScriptSignatureNotValid)?;
+        let signature = base64::decode_block(field("signature")?)
+            .map_err(|_| FwcError::ScriptSignatureNotValid)?;
+
+        Ok(ScriptSignature {
+            path,
+            expires,
+            signature,
+        })
+    }
+
+    // The path and expiry lines followed by the script.
+    fn signed_data(&self, script: &[u8]) -> Vec<u8> {
+        let mut data = format!("path: {}\nexpires: {}\n", self.path, self.expires).into_bytes();
+        data.extend_from_slice(script);
+        data
+    }
+
+    /// Verify the signature of `script` with the public key in `key_file`, and that it has not
+    /// expired.
+    pub fn verify(&self, key_file: &str, script: &[u8]) -> Result<()> {
+        verify(key_file, &self.signed_data(script), &self.signature)?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if self.expires < now {
+            return Err(FwcError::ScriptSignatureExpired);
+        }
+
+        Ok(())
+    }
+}
+
+/// Load the public key used for verifying the script signatures.
+pub fn load_key(key_file: &str) -> Result<PKey<Public>> {
+    let pem =
+        fs::read(key_file).map_err(|e| FwcError::ScriptSigningKey(format!("{key_file}: {e}")))?;
+    let key = PKey::public_key_from_pem(&pem)
+        .map_err(|_| FwcError::ScriptSigningKey(format!("{key_file}: not a PEM public key")))?;
+
+    match key.id() {
+        Id::ED25519 | Id::RSA => Ok(key),
+        _ => Err(FwcError::ScriptSigningKey(format!(
+            "{key_file}: unsupported key type, Ed25519 or RSA expected"
+        ))),
+    }
+}
+
+/// Verify the raw signature of `data` with the public key in `key_file`.
+///
+/// The key is loaded in every verification, this way it can be replaced without restarting the
+/// agent.
+pub fn verify(key_file: &str, data: &[u8], signature: &[u8]) -> Result<()> {
+    let key = load_key(key_file)?;
+
+    let verified = || -> std::result::Result<bool, ErrorStack> {
+        if key.id() == Id::ED25519 {
+            Verifier::new_without_digest(&key)?.verify_oneshot(signature, data)
+        } else {
+            let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+            verifier.update(data)?;
+            verifier.verify(signature)
+        }
+    };
+
+    // A malformed signature is an OpenSSL error, not a verification failure.
+    match verified() {
+        Ok(true) => Ok(()),
+        _ => Err(FwcError::ScriptSignatureNotValid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use std::env;
+    use uuid::Uuid;
+
+    const SCRIPT: &[u8] = b"#!/bin/sh\niptables -P INPUT DROP\n";
+
+    fn key_file(key: &PKey<Private>) -> String {
+        let file = env::temp_dir().join(format!("fwcloud-agent-{}.pem", Uuid::new_v4()));
+        fs::write(&file, key.public_key_to_pem().unwrap()).unwrap();
+        String::from(file.to_str().unwrap())
+    }
+
+    #[test]
+    fn verifies_ed25519_signatures() {
+        let key = PKey::generate_ed25519().unwrap();
+        let file = key_file(&key);
+        let signature = Signer::new_without_digest(&key)
+            .unwrap()
+            .sign_oneshot_to_vec(SCRIPT)
+            .unwrap();
+
+        assert!(verify(&file, SCRIPT, &signature).is_ok());
+        assert!(matches!(
+            verify(&file, b"#!/bin/sh\niptables -P INPUT ACCEPT\n", &signature),
+            Err(FwcError::ScriptSignatureNotValid)
+        ));
+        assert!(matches!(
+            verify(&file, SCRIPT, &signature[1..]),
+            Err(FwcError::ScriptSignatureNotValid)
+        ));
+
+        // Signed with another key.
+        let other = PKey::generate_ed25519().unwrap();
+        let signature = Signer::new_without_digest(&other)
+            .unwrap()
+            .sign_oneshot_to_vec(SCRIPT)
+            .unwrap();
+        assert!(matches!(
+            verify(&file, SCRIPT, &signature),
+            Err(FwcError::ScriptSignatureNotValid)
+        ));
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn verifies_rsa_signatures() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let file = key_file(&key);
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        signer.update(SCRIPT).unwrap();
+        let signature = signer.sign_to_vec().unwrap();
+
+        assert!(verify(&file, SCRIPT, &signature).is_ok());
+        assert!(matches!(
+            verify(&file, b"#!/bin/sh\n", &signature),
+            Err(FwcError::ScriptSignatureNotValid)
+        ));
+        assert!(matches!(
+            verify(&file, SCRIPT, b"not a signature"),
+            Err(FwcError::ScriptSignatureNotValid)
+        ));
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn verifies_path_and_expiry_of_script_signatures() {
+        let key = PKey::generate_ed25519().unwrap();
+        let file = key_file(&key);
+        let sign = |path: &str, expires: u64| {
+            let data = format!("path: {path}\nexpires: {expires}\n");
+            let signature = Signer::new_without_digest(&key)
+                .unwrap()
+                .sign_oneshot_to_vec(&[data.as_bytes(), SCRIPT].concat())
+                .unwrap();
+            format!("{data}signature: {}\n", base64::encode_block(&signature))
+        };
+        let expires = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+
+        let signature =
+            ScriptSignature::parse(sign("/etc/fwcloud/fwcloud.sh", expires).as_bytes()).unwrap();
+        assert_eq!(signature.path, "/etc/fwcloud/fwcloud.sh");
+        assert_eq!(signature.expires, expires);
+        assert!(signature.verify(&file, SCRIPT).is_ok());
+        assert!(matches!(
+            signature.verify(&file, b"#!/bin/sh\n"),
+            Err(FwcError::ScriptSignatureNotValid)
+        ));
+
+        // The signed path and expiry can't be changed.
+        let original = sign("/etc/fwcloud/fwcloud.sh", expires);
+        for forged in [
+            original.replace("/etc/fwcloud/", "/tmp/"),
+            original.replace(
+                &format!("expires: {expires}"),
+                &format!("expires: {}", expires + 3600),
+            ),
+        ] {
+            let signature = ScriptSignature::parse(forged.as_bytes()).unwrap();
+            assert!(matches!(
+                signature.verify(&file, SCRIPT),
+                Err(FwcError::ScriptSignatureNotValid)
+            ));
+        }
+
+        let signature =
+            ScriptSignature::parse(sign("/etc/fwcloud/fwcloud.sh", expires - 120).as_bytes())
+                .unwrap();
+        assert!(matches!(
+            signature.verify(&file, SCRIPT),
+            Err(FwcError::ScriptSignatureExpired)
+        ));
+
+        for bad in [
+            "",
+            "expires: 1\npath: /a\nsignature: AAAA\n",
+            "path: /a\nexpires: soon\nsignature: AAAA\n",
+            "path: /a\nexpires: 1\nsignature: not base64!\n",
+        ] {
+            assert!(matches!(
+                ScriptSignature::parse(bad.as_bytes()),
+                Err(FwcError::ScriptSignatureNotValid)
+            ));
+        }
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_keys() {
+        assert!(matches!(
+            load_key("/nonexistent/console.pem"),
+            Err(FwcError::ScriptSigningKey(_))
+        ));
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let file = key_file(&key);
+        assert!(matches!(
+            load_key(&file),
+            Err(FwcError::ScriptSigningKey(_))
+        ));
+        fs::write(&file, "-----BEGIN PUBLIC KEY-----\nfoo\n").unwrap();
+        assert!(matches!(
+            load_key(&file),
+            Err(FwcError::ScriptSigningKey(_))
+        ));
+
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Data covered by the HMAC-SHA256 signature of a request.
+///
+/// The signed message is the concatenation, separated by new line characters, of the request
+/// method, the path (including the query string), the timestamp, the nonce and the hex encoded
+/// SHA-256 hash of the request body.
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub timestamp: u64,
+    pub nonce: &'a str,
+    pub body: &'a [u8],
+}
+
+impl SignedRequest<'_> {
+    pub fn message(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            self.method,
+            self.path,
+            self.timestamp,
+            self.nonce,
+            hex::encode(Sha256::digest(self.body))
+        )
+    }
+
+    /// Verify the hex encoded signature in constant time.
+    pub fn verify(&self, key: &str, signature: &str) -> bool {
+        let signature = match hex::decode(signature) {
+            Ok(data) => data,
+            Err(_) => return false,
+        };
+
+        let mut mac =
+            HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts any key size");
+        mac.update(self.message().as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// Bounded cache of the nonces seen inside the allowed timestamps window.
+#[derive(Default)]
+pub struct NonceCache {
+    nonces: HashSet<String>,
+    queue: VecDeque<(u64, String)>,
+}
+
+impl NonceCache {
+    pub fn new() -> Self {
+        NonceCache::default()
+    }
+
+    /// Register a nonce received at `now`. Returns false if the nonce has already been used or
+    /// the cache is full of nonces that can not be expired yet.
+    ///
+    /// `max_skew` is the maximum clock difference allowed for the timestamp of the requests.
+    pub fn insert(&mut self, nonce: &str, now: u64, max_skew: u64, max_size: usize) -> bool {
+        // A nonce received at `now` could be replayed with its timestamp valid until `now + 2 * max_skew`.
+        // Older nonces can be forgotten because their requests will be rejected by the timestamp check.
+        while let Some((ts, _)) = self.queue.front() {
+            if now.saturating_sub(*ts) <= max_skew * 2 {
+                break;
+            }
+            if let Some((_, old)) = self.queue.pop_front() {
+                self.nonces.remove(&old);
+            }
+        }
+
+        if self.nonces.contains(nonce) || self.queue.len() >= max_size {
+            return false;
+        }
+
+        self.nonces.insert(String::from(nonce));
+        self.queue.push_back((now, String::from(nonce)));
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_factory(body: &[u8]) -> SignedRequest<'_> {
+        SignedRequest {
+            method: "PUT",
+            path: "/api/v1/ping",
+            timestamp: 1700000000,
+            nonce: "b0b3b8a0-3c8e-4a8f-9a57-3a1b0ad1c3a4",
+            body,
+        }
+    }
+
+    fn sign(req: &SignedRequest, key: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(req.message().as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let req = request_factory(b"{\"name\":\"test\"}");
+        let signature = sign(&req, "d64c88318c8f213f427af857d0013f93");
+
+        assert!(req.verify("d64c88318c8f213f427af857d0013f93", &signature));
+        assert!(!req.verify("d64c88318c8f213f427af857d0013f94", &signature));
+        assert!(!req.verify("d64c88318c8f213f427af857d0013f93", "bad signature"));
+
+        let other = request_factory(b"{\"name\":\"other\"}");
+        assert!(!other.verify("d64c88318c8f213f427af857d0013f93", &signature));
+    }
+
+    #[test]
+    fn rejects_reused_nonces() {
+        let mut cache = NonceCache::new();
+
+        assert!(cache.insert("nonce1", 1000, 300, 10));
+        assert!(cache.insert("nonce2", 1000, 300, 10));
+        assert!(!cache.insert("nonce1", 1100, 300, 10));
+
+        // After twice the timestamps window the nonce is forgotten.
+        assert!(cache.insert("nonce1", 1601, 300, 10));
+    }
+
+    #[test]
+    fn nonce_cache_is_bounded() {
+        let mut cache = NonceCache::new();
+
+        assert!(cache.insert("nonce1", 1000, 300, 2));
+        assert!(cache.insert("nonce2", 1000, 300, 2));
+        assert!(!cache.insert("nonce3", 1000, 300, 2));
+        assert!(cache.insert("nonce3", 1601, 300, 2));
+    }
+}
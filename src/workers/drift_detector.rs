@@ -0,0 +1,77 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use futures::executor::block_on;
+use log::{debug, info};
+use std::sync::Arc;
+use std::{thread, time};
+
+use crate::config::Config;
+use crate::utils::drift;
+
+/// Periodic check of the running ruleset against the one installed by FWCloud.
+pub struct DriftDetector {
+    check_interval: u64,
+}
+
+impl DriftDetector {
+    pub fn new(cfg: &Config) -> Self {
+        DriftDetector {
+            check_interval: cfg.drift_check_interval,
+        }
+    }
+
+    pub fn start(&self, cfg: Arc<Config>) {
+        let check_interval = self.check_interval;
+        if check_interval == 0 {
+            info!("Ruleset drift detection disabled");
+            return;
+        }
+
+        thread::spawn(move || {
+            block_on(async {
+                info!(
+                    "Starting ruleset drift detector thread (id: {})",
+                    thread_id::get()
+                );
+
+                loop {
+                    // Start of mutex scope.
+                    {
+                        // Don't compare while a FWCloud script is being installed.
+                        debug!("Locking script mutex (thread id: {})", thread_id::get());
+                        let mutex = Arc::clone(&cfg.mutex.fwcloud_script);
+                        let _mutex_data = mutex.lock().await;
+                        debug!("Script mutex locked (thread id: {})", thread_id::get());
+
+                        drift::check(&cfg);
+
+                        debug!("Releasing script mutex (thread id: {})", thread_id::get());
+                    } // End of mutex scope.
+
+                    // Pause between checks.
+                    thread::sleep(time::Duration::from_secs(check_interval));
+                }
+            })
+        });
+    }
+}
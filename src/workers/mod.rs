@@ -22,6 +22,7 @@
 
 use std::sync::mpsc::Sender;
 
+pub mod drift_detector;
 pub mod openvpn_status_collector;
 
 #[derive(Clone)]
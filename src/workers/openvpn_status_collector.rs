@@ -60,16 +60,37 @@ impl OpenVPNStCollectorInner {
         };
 
         // Create the list of OpenVPN status files.
-        for file in cfg.openvpn_status_files.iter() {
-            data.openvpn_status_files.push(OpenVPNStFile {
+        data.update_files(cfg);
+
+        data
+    }
+
+    /// Sync the list of OpenVPN status files with the configuration, it can change when the configuration is reloaded.
+    ///
+    /// The files that are kept in the list don't lose their last update timestamp.
+    fn update_files(&mut self, cfg: &Config) {
+        let live = cfg.live();
+        if self
+            .openvpn_status_files
+            .iter()
+            .map(|item| &item.st_file)
+            .eq(live.openvpn_status_files.iter())
+        {
+            return;
+        }
+
+        let old_files = std::mem::take(&mut self.openvpn_status_files);
+        for file in live.openvpn_status_files.iter() {
+            self.openvpn_status_files.push(OpenVPNStFile {
                 st_file: String::from(file),
                 tmp_file: format!("{}/{}.tmp", cfg.tmp_dir, file.replace('/', "_")),
                 cache_file: format!("{}/{}.data", cfg.data_dir, file.replace('/', "_")),
-                last_update: 0,
+                last_update: old_files
+                    .iter()
+                    .find(|item| &item.st_file == file)
+                    .map_or(0, |item| item.last_update),
             });
         }
-
-        data
     }
 
     /// This function will convert datetime string that it receives in the amounts os seconds since `UNIX_EPOCH`.
@@ -257,6 +278,7 @@ impl OpenVPNStCollector {
                         //thread::sleep(time::Duration::from_millis(10_000));
 
                         let mut collector = local_self.lock().unwrap();
+                        collector.update_files(&cfg);
                         collector.collect_all_files_data();
                         pause = collector.sampling_interval;
 
@@ -356,6 +378,7 @@ mod tests {
     #[serial]
     fn generates_right_default_openvpn_status_file_vector() {
         let collector = collector_factory(vec![], false);
+        let cwd = std::env::current_dir().unwrap();
         assert_eq!(collector.openvpn_status_files.len(), 1);
         assert_eq!(
             collector.openvpn_status_files[0].st_file,
@@ -363,11 +386,14 @@ mod tests {
         );
         assert_eq!(
             collector.openvpn_status_files[0].tmp_file,
-            String::from("./tmp/_etc_openvpn_openvpn-status.log.tmp")
+            format!("{}/tmp/_etc_openvpn_openvpn-status.log.tmp", cwd.display())
         );
         assert_eq!(
             collector.openvpn_status_files[0].cache_file,
-            String::from("./data/_etc_openvpn_openvpn-status.log.data")
+            format!(
+                "{}/data/_etc_openvpn_openvpn-status.log.data",
+                cwd.display()
+            )
         );
         assert_eq!(collector.openvpn_status_files[0].last_update, 0);
     }
@@ -386,16 +412,17 @@ mod tests {
         let list = status_files_list_factory(n);
         let collector = collector_factory(vec![("OPENVPN_STATUS_FILES", list.join(","))], false);
         assert_eq!(collector.openvpn_status_files.len(), n);
+        let cwd = std::env::current_dir().unwrap();
 
-        for inx in 0..n {
-            assert_eq!(collector.openvpn_status_files[inx].st_file, list[inx]);
+        for (inx, file) in list.iter().enumerate() {
+            assert_eq!(collector.openvpn_status_files[inx].st_file, *file);
             assert_eq!(
                 collector.openvpn_status_files[inx].tmp_file,
-                format!("./tmp/{}.tmp", list[inx].replace('/', "_"))
+                format!("{}/tmp/{}.tmp", cwd.display(), file.replace('/', "_"))
             );
             assert_eq!(
                 collector.openvpn_status_files[inx].cache_file,
-                format!("./data/{}.data", list[inx].replace('/', "_"))
+                format!("{}/data/{}.data", cwd.display(), file.replace('/', "_"))
             );
             assert_eq!(collector.openvpn_status_files[inx].last_update, 0);
         }
@@ -0,0 +1,92 @@
+/*
+    Copyright 2022 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+use fwcloud_agent::config::ApiKey;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::Value;
+
+#[tokio::test]
+async fn audit_log_records_mutating_requests() {
+    // Unique key name for finding our entries in the shared audit log.
+    let key_name = format!("audit{}", common::random_api_key(16));
+    let key = common::random_api_key(64);
+
+    let cfg_opt = common::TestCfgOpt {
+        enable_api_key: true,
+        api_key: common::random_api_key(64),
+        allowed_ips: vec![],
+        api_keys: vec![ApiKey {
+            name: key_name.clone(),
+            key: key.clone(),
+            scopes: vec![String::from("*")],
+        }],
+    };
+
+    let base_url = common::spawn_app(Some(cfg_opt));
+    let client = reqwest::Client::new();
+
+    // Read only requests are not audited.
+    let res = client
+        .put(format!("{base_url}/api/v1/ping"))
+        .header("X-API-Key", &key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = client
+        .post(format!("{base_url}/api/v1/plugin"))
+        .header("X-API-Key", &key)
+        .header(CONTENT_TYPE, "application/json")
+        .body("{\"name\":\"openvpn\",\"action\":\"INVALID\"}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = client
+        .get(format!("{base_url}/api/v1/audit?limit=1000"))
+        .header("X-API-Key", &key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+
+    let page: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let entries: Vec<&Value> = page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry["key"] == key_name.as_str())
+        .collect();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["method"], "POST");
+    assert_eq!(entries[0]["route"], "/api/v1/plugin");
+    assert_eq!(entries[0]["peer_ip"], "127.0.0.1");
+    assert_eq!(entries[0]["params"]["name"], "openvpn");
+    assert_eq!(entries[0]["params"]["action"], "INVALID");
+    assert_eq!(entries[0]["status"], 400);
+    assert_eq!(entries[0]["error"], "action: Invalid plugin action");
+}
@@ -22,12 +22,23 @@
 
 mod common;
 
+use fwcloud_agent::config::ApiKey;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const PLUGIN_ENABLE: &str = "{\"name\":\"test\",\"action\":\"enable\"}";
+const PLUGIN_DISABLE: &str = "{\"name\":\"test\",\"action\":\"disable\"}";
+
 #[tokio::test]
 async fn auth_error_no_api_key() {
     let cfg_opt = common::TestCfgOpt {
         enable_api_key: true,
         api_key: common::random_api_key(64),
         allowed_ips: vec![],
+        api_keys: vec![],
     };
 
     let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
@@ -45,6 +56,7 @@ async fn auth_invalid_api_key() {
         enable_api_key: true,
         api_key: common::random_api_key(64),
         allowed_ips: vec![],
+        api_keys: vec![],
     };
 
     let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
@@ -69,6 +81,7 @@ async fn auth_valid_api_key() {
         enable_api_key: true,
         api_key: api_key.clone(),
         allowed_ips: vec![],
+        api_keys: vec![],
     };
 
     let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
@@ -92,6 +105,7 @@ async fn auth_myip_in_allowed_ips() {
         enable_api_key: true,
         api_key: api_key.clone(),
         allowed_ips: vec!["127.0.0.1".to_string()],
+        api_keys: vec![],
     };
 
     let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
@@ -115,6 +129,7 @@ async fn auth_myip_not_in_allowed_ips() {
         enable_api_key: true,
         api_key: api_key.clone(),
         allowed_ips: vec!["10.20.30.40".to_string()],
+        api_keys: vec![],
     };
 
     let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
@@ -133,3 +148,255 @@ async fn auth_myip_not_in_allowed_ips() {
         "{\"message\":\"Authorization error, access from your IP is not allowed\"}"
     );
 }
+
+#[tokio::test]
+async fn auth_myip_in_allowed_network() {
+    let api_key: String = common::random_api_key(64);
+
+    let cfg_opt = common::TestCfgOpt {
+        enable_api_key: true,
+        api_key: api_key.clone(),
+        allowed_ips: vec!["10.0.0.0/8".to_string(), "127.0.0.0/8".to_string()],
+        api_keys: vec![],
+    };
+
+    let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
+
+    let res = reqwest::Client::new()
+        .put(url)
+        .header("X-API-Key", api_key)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.content_length(), Some(0));
+}
+
+#[tokio::test]
+async fn auth_myip_not_in_allowed_networks() {
+    let api_key: String = common::random_api_key(64);
+
+    let cfg_opt = common::TestCfgOpt {
+        enable_api_key: true,
+        api_key: api_key.clone(),
+        allowed_ips: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+        api_keys: vec![],
+    };
+
+    let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
+
+    let res = reqwest::Client::new()
+        .put(url)
+        .header("X-API-Key", api_key)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 403);
+    let body = res.text().await.unwrap();
+    assert_eq!(
+        body,
+        "{\"message\":\"Authorization error, access from your IP is not allowed\"}"
+    );
+}
+
+#[tokio::test]
+async fn auth_allowed_ips_enforced_without_api_key() {
+    let cfg_opt = common::TestCfgOpt {
+        enable_api_key: false,
+        api_key: common::random_api_key(64),
+        allowed_ips: vec!["10.20.30.40".to_string()],
+        api_keys: vec![],
+    };
+
+    let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
+
+    let res = reqwest::Client::new().put(url).send().await.unwrap();
+
+    assert_eq!(res.status().as_u16(), 403);
+    let body = res.text().await.unwrap();
+    assert_eq!(
+        body,
+        "{\"message\":\"Authorization error, access from your IP is not allowed\"}"
+    );
+}
+
+#[tokio::test]
+async fn auth_named_api_key_scopes() {
+    let monitoring_key: String = common::random_api_key(64);
+    let plugins_key: String = common::random_api_key(64);
+
+    let cfg_opt = common::TestCfgOpt {
+        enable_api_key: true,
+        api_key: common::random_api_key(64),
+        allowed_ips: vec![],
+        api_keys: vec![
+            ApiKey {
+                name: String::from("monitoring"),
+                key: monitoring_key.clone(),
+                scopes: vec![String::from("read:*")],
+            },
+            ApiKey {
+                name: String::from("plugins"),
+                key: plugins_key.clone(),
+                scopes: vec![String::from("plugin:disable")],
+            },
+        ],
+    };
+
+    let base_url = common::spawn_app(Some(cfg_opt));
+    let client = reqwest::Client::new();
+
+    let test_cases = vec![
+        (&monitoring_key, "ping", "{}", 200),
+        (&monitoring_key, "plugin", PLUGIN_DISABLE, 403),
+        (&plugins_key, "ping", "{}", 403),
+        (&plugins_key, "plugin", PLUGIN_ENABLE, 403),
+        (&plugins_key, "plugin", PLUGIN_DISABLE, 200),
+    ];
+
+    for (key, route, body, status) in test_cases {
+        let req = if route == "ping" {
+            client.put(format!("{base_url}/api/v1/ping"))
+        } else {
+            client
+                .post(format!("{base_url}/api/v1/plugin"))
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+        };
+        let res = req.header("X-API-Key", key).send().await.unwrap();
+
+        assert_eq!(res.status().as_u16(), status);
+        if status == 403 {
+            let body = res.text().await.unwrap();
+            assert_eq!(
+                body,
+                "{\"message\":\"Authorization error, API key not allowed for this operation\"}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn auth_failures_ban_source_ip() {
+    let cfg_opt = common::TestCfgOpt {
+        enable_api_key: true,
+        api_key: common::random_api_key(64),
+        allowed_ips: vec![],
+        api_keys: vec![],
+    };
+    let api_key = cfg_opt.api_key.clone();
+
+    let base_url = common::spawn_app(Some(cfg_opt));
+    let client = reqwest::Client::new();
+
+    // With the default policy the source IP is banned after 5 failed authentications.
+    for _ in 0..5 {
+        let res = client
+            .put(format!("{base_url}/api/v1/ping"))
+            .header("X-API-Key", common::random_api_key(64))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+    }
+
+    // Even with the valid API key the banned IP is refused.
+    let res = client
+        .get(format!("{base_url}/api/v1/auth/bans"))
+        .header("X-API-Key", &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 429);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "{\"message\":\"Too many authentication failures, try again later\"}"
+    );
+}
+
+fn sign_request(key: &str, method: &str, path: &str, timestamp: u64, nonce: &str) -> String {
+    let message = format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        timestamp,
+        nonce,
+        hex::encode(Sha256::digest(b""))
+    );
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[tokio::test]
+async fn auth_signed_requests() {
+    let api_key: String = common::random_api_key(64);
+
+    let cfg_opt = common::TestCfgOpt {
+        enable_api_key: true,
+        api_key: api_key.clone(),
+        allowed_ips: vec![],
+        api_keys: vec![],
+    };
+
+    let url = format!("{}/api/v1/ping", common::spawn_app(Some(cfg_opt)));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let nonce = Uuid::new_v4().to_string();
+    let stale_nonce = Uuid::new_v4().to_string();
+
+    let test_cases = vec![
+        // Valid signature.
+        (
+            now,
+            nonce.clone(),
+            sign_request(&api_key, "PUT", "/api/v1/ping", now, &nonce),
+            200,
+            "",
+        ),
+        // Replayed request.
+        (
+            now,
+            nonce.clone(),
+            sign_request(&api_key, "PUT", "/api/v1/ping", now, &nonce),
+            403,
+            "{\"message\":\"Request nonce already used\"}",
+        ),
+        // Stale timestamp.
+        (
+            now - 3600,
+            stale_nonce.clone(),
+            sign_request(&api_key, "PUT", "/api/v1/ping", now - 3600, &stale_nonce),
+            403,
+            "{\"message\":\"Request timestamp out of the allowed time window\"}",
+        ),
+        // Signature for another route.
+        (
+            now,
+            stale_nonce.clone(),
+            sign_request(&api_key, "PUT", "/api/v1/info", now, &stale_nonce),
+            403,
+            "{\"message\":\"Invalid request signature\"}",
+        ),
+    ];
+
+    for (timestamp, nonce, signature, status, message) in test_cases {
+        let res = reqwest::Client::new()
+            .put(&url)
+            .header("X-FWC-Key-Id", "default")
+            .header("X-FWC-Timestamp", timestamp.to_string())
+            .header("X-FWC-Nonce", nonce)
+            .header("X-FWC-Signature", signature)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), status);
+        assert_eq!(res.text().await.unwrap(), message);
+    }
+}
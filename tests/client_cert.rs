@@ -0,0 +1,155 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use fwcloud_agent::config::{Config, LiveOptions};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage};
+use openssl::x509::{X509NameBuilder, X509};
+use serial_test::serial;
+use std::env;
+use uuid::Uuid;
+
+// Certificate signed by the given issuer, or self-signed if there is no issuer.
+fn cert_factory(cn: &str, issuer: Option<(&X509, &PKey<Private>)>) -> (X509, PKey<Private>) {
+    let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", cn).unwrap();
+    let name = name.build();
+
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_pubkey(&pkey).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    match issuer {
+        Some((ca_cert, ca_key)) => {
+            builder.set_issuer_name(ca_cert.subject_name()).unwrap();
+            builder
+                .append_extension(ExtendedKeyUsage::new().client_auth().build().unwrap())
+                .unwrap();
+            builder.sign(ca_key, MessageDigest::sha256()).unwrap();
+        }
+        None => {
+            builder.set_issuer_name(&name).unwrap();
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+            builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        }
+    }
+
+    (builder.build(), pkey)
+}
+
+fn client_factory(identity: Option<(&X509, &PKey<Private>)>) -> reqwest::Client {
+    // The agent's certificate is the self-signed one generated on start.
+    let mut builder = reqwest::Client::builder().danger_accept_invalid_certs(true);
+    if let Some((cert, pkey)) = identity {
+        let identity = reqwest::Identity::from_pkcs8_pem(
+            &cert.to_pem().unwrap(),
+            &pkey.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+        builder = builder.identity(identity);
+    }
+
+    builder.build().unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn client_cert_authorization() {
+    let etc_dir = env::temp_dir().join(format!("fwcloud-agent-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&etc_dir).unwrap();
+
+    let (ca_cert, ca_key) = cert_factory("FWCloud Test CA", None);
+    std::fs::write(etc_dir.join("ca.pem"), ca_cert.to_pem().unwrap()).unwrap();
+    let (console_cert, console_key) = cert_factory("console", Some((&ca_cert, &ca_key)));
+    let (other_cert, other_key) = cert_factory("other", Some((&ca_cert, &ca_key)));
+
+    env::set_var("ETC_DIR", &etc_dir);
+    env::set_var("ENABLE_SSL", "true");
+    env::set_var("ENABLE_CLIENT_CERT", "true");
+    env::set_var("ALLOWED_CLIENT_NAMES", "console");
+    let mut config = Config::new().unwrap();
+    env::remove_var("ETC_DIR");
+    env::remove_var("ENABLE_SSL");
+    env::remove_var("ENABLE_CLIENT_CERT");
+    env::remove_var("ALLOWED_CLIENT_NAMES");
+
+    config.enable_env_logger = false;
+    config.bind_ip = "127.0.0.1".to_string();
+    config.bind_port = 0;
+    config.set_live(LiveOptions {
+        enable_api_key: false,
+        ..(*config.live()).clone()
+    });
+    config.workers = 1;
+    let listeners = config.bind_to();
+    let url = format!("https://127.0.0.1:{}/api/v1/ping", config.bind_port);
+
+    let server = fwcloud_agent::run(config, listeners).expect("Failed to run FWCloud-Agent server");
+    drop(tokio::spawn(server));
+
+    // Client certificate signed by the CA and with an allowed name.
+    let res = client_factory(Some((&console_cert, &console_key)))
+        .put(&url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+
+    // Signed by the CA, but its name is not in the allowed list.
+    let res = client_factory(Some((&other_cert, &other_key)))
+        .put(&url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 403);
+    let body = res.text().await.unwrap();
+    assert_eq!(
+        body,
+        "{\"message\":\"Authorization error, client certificate not allowed\"}"
+    );
+
+    // Without client certificate the TLS handshake is refused.
+    let res = client_factory(None).put(&url).send().await;
+    assert!(res.is_err());
+
+    std::fs::remove_dir_all(&etc_dir).unwrap();
+}
@@ -20,15 +20,18 @@
     along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use ipnet::IpNet;
 use rand::Rng;
 use rand_distr::Alphanumeric;
+use std::net::IpAddr;
 
-use fwcloud_agent::config::Config;
+use fwcloud_agent::config::{ApiKey, Config, LiveOptions};
 
 pub struct TestCfgOpt {
     pub enable_api_key: bool,
     pub api_key: String,
     pub allowed_ips: Vec<String>,
+    pub api_keys: Vec<ApiKey>,
 }
 
 // Launch our application in the background.
@@ -37,6 +40,7 @@ pub fn spawn_app(custom: Option<TestCfgOpt>) -> String {
         enable_api_key: false,
         api_key: random_api_key(64),
         allowed_ips: vec![],
+        api_keys: vec![],
     });
 
     let mut config = Config::new().unwrap();
@@ -44,21 +48,33 @@ pub fn spawn_app(custom: Option<TestCfgOpt>) -> String {
     config.enable_env_logger = false;
     config.bind_ip = "127.0.0.1".to_string();
     config.bind_port = 0;
-    let listener = config.bind_to();
     config.enable_tls = false;
-    config.enable_api_key = cfg_opt.enable_api_key;
-    config.api_key = cfg_opt.api_key;
-    config.allowed_ips = cfg_opt.allowed_ips;
+    let listeners = config.bind_to();
+    config.set_live(LiveOptions {
+        enable_api_key: cfg_opt.enable_api_key,
+        api_key: cfg_opt.api_key,
+        allowed_ips: cfg_opt
+            .allowed_ips
+            .iter()
+            .map(|ip| {
+                ip.parse::<IpNet>()
+                    .unwrap_or_else(|_| IpNet::from(ip.parse::<IpAddr>().unwrap()))
+            })
+            .collect(),
+        api_keys: cfg_opt.api_keys,
+        ..(*config.live()).clone()
+    });
     config.workers = 1;
 
     let protocol = "http";
     let ip = config.bind_ip.clone();
     let port = config.bind_port;
 
-    let server = fwcloud_agent::run(config, listener).expect("Failed to run FWCloud-Agent server");
+    let server = fwcloud_agent::run(config, listeners).expect("Failed to run FWCloud-Agent server");
     // Launch the server as a background task
     // tokio::spawn returns a handle to the spawned future,
     // but we have no use for it here, hence the non-binding let
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(server);
 
     format!("{}://{}:{}", protocol, ip, port)
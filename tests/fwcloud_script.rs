@@ -35,3 +35,138 @@ async fn fwcloud_script_without_data() {
         "{\"message\":\"Destination directory parameter not found in multipart/form-data stream\"}"
     );
 }
+
+#[tokio::test]
+async fn fwcloud_script_not_writable_by_others() {
+    let url = format!("{}/api/v1/fwcloud_script/upload", common::spawn_app(None));
+
+    let body = format!(
+        "--boundary\r\nContent-Disposition: form-data; name=\"dst_dir\"\r\n\r\n{}\r\n\
+         --boundary\r\nContent-Disposition: form-data; name=\"perms\"\r\n\r\n777\r\n\
+         --boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"fwcloud.sh\"\r\n\r\n#!/bin/sh\r\n\
+         --boundary--\r\n",
+        std::env::temp_dir().display()
+    );
+
+    let res = reqwest::Client::new()
+        .post(url)
+        .header("Content-Type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+    let body = res.text().await.unwrap();
+    assert_eq!(
+        body,
+        "{\"message\":\"FWCloud script can not be writable by group or others\"}"
+    );
+}
+
+#[tokio::test]
+async fn fwcloud_script_bad_rollback_timeout() {
+    let url = format!(
+        "{}/api/v1/fwcloud_script/upload?rollback_timeout=5",
+        common::spawn_app(None)
+    );
+
+    let res = reqwest::Client::new().post(url).send().await.unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn fwcloud_script_nothing_pending_of_confirmation() {
+    let base = common::spawn_app(None);
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/api/v1/fwcloud_script/pending", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.text().await.unwrap(), "{}");
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/api/v1/fwcloud_script/confirm", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn fwcloud_script_history() {
+    let base = common::spawn_app(None);
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/api/v1/fwcloud_script/history", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.text().await.unwrap().starts_with('['));
+
+    for path in [
+        "history/999999",
+        "history/diff?from=999998&to=999999",
+        "history/foo",
+    ] {
+        let res = reqwest::Client::new()
+            .get(format!("{}/api/v1/fwcloud_script/{}", base, path))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 404);
+    }
+
+    let res = reqwest::Client::new()
+        .post(format!(
+            "{}/api/v1/fwcloud_script/history/999999/install",
+            base
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn fwcloud_script_validate_without_data() {
+    let url = format!("{}/api/v1/fwcloud_script/validate", common::spawn_app(None));
+
+    let res = reqwest::Client::new().post(url).send().await.unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+    let body = res.text().await.unwrap();
+    assert_eq!(
+        body,
+        "{\"message\":\"At least one file must be included in the request\"}"
+    );
+}
+
+#[tokio::test]
+async fn fwcloud_script_validate_only_script_and_signature() {
+    let url = format!("{}/api/v1/fwcloud_script/validate", common::spawn_app(None));
+
+    let mut body = String::new();
+    for name in ["fwcloud.sh", "fwcloud.sh.txt"] {
+        body.push_str(&format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\r\n#!/bin/sh\r\n"
+        ));
+    }
+    body.push_str("--boundary--\r\n");
+
+    let res = reqwest::Client::new()
+        .post(url)
+        .header("Content-Type", "multipart/form-data; boundary=boundary")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status().as_u16(), 400);
+    let body = res.text().await.unwrap();
+    assert_eq!(body, "{\"message\":\"File name was not the expected one\"}");
+}
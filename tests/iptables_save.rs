@@ -31,3 +31,36 @@ async fn iptables_save_data_api_call_exists() {
     assert_ne!(res.status().as_u16(), 400);
     assert_ne!(res.status().as_u16(), 404);
 }
+
+#[tokio::test]
+async fn iptables_save_data_rejects_bad_options() {
+    let base = common::spawn_app(None);
+
+    for query in [
+        "table=foo",
+        "backend=xtables",
+        "ipv6=yes",
+        "counters=1&table=nat;ls",
+        "format=xml",
+    ] {
+        let res = reqwest::Client::new()
+            .get(format!("{}/api/v1/iptables-save/data?{}", base, query))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn iptables_save_backend_api_call_exists() {
+    let url = format!("{}/api/v1/iptables-save/backend", common::spawn_app(None));
+
+    let res = reqwest::Client::new().get(url).send().await.unwrap();
+
+    assert_eq!(res.status().as_u16(), 200);
+    let body: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert!(body.get("iptables").is_some());
+    assert!(body.get("ip6tables").is_some());
+}
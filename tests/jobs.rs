@@ -0,0 +1,128 @@
+/*
+    Copyright 2022 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use std::time::Duration;
+
+#[tokio::test]
+async fn jobs_not_found() {
+    let base = common::spawn_app(None);
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/api/v1/jobs", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.text().await.unwrap().starts_with('['));
+
+    for path in ["1b4e28ba-2fa1-11d2-883f-0016d3cca427", "foo"] {
+        let res = reqwest::Client::new()
+            .get(format!("{}/api/v1/jobs/{}", base, path))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 404);
+    }
+
+    let res = reqwest::Client::new()
+        .post(format!(
+            "{}/api/v1/jobs/1b4e28ba-2fa1-11d2-883f-0016d3cca427/cancel",
+            base
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn plugin_job() {
+    let base = common::spawn_app(None);
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/api/v1/plugin?job=true", base))
+        .header(CONTENT_TYPE, "application/json")
+        .body("{\"name\":\"test\",\"action\":\"enable\"}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 202);
+    let location = res.headers()[LOCATION].to_str().unwrap().to_string();
+
+    for _ in 0..100 {
+        let res = reqwest::Client::new()
+            .get(format!("{}{}", base, location))
+            .send()
+            .await
+            .unwrap();
+        let job: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        if job["state"] == "succeeded" {
+            assert_eq!(job["kind"], "plugin");
+            assert_eq!(job["output"], "ENABLED\n");
+            return;
+        }
+        assert!(job["state"] == "queued" || job["state"] == "running");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Job not finished");
+}
+
+#[tokio::test]
+async fn systemctl_job() {
+    let base = common::spawn_app(None);
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/api/v1/systemctl?job=true", base))
+        .header(CONTENT_TYPE, "application/json")
+        .body("{\"command\":\"status\",\"service\":\"openvpn\"}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 202);
+    let location = res.headers()[LOCATION].to_str().unwrap().to_string();
+    let job: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(job["kind"], "systemctl");
+    assert_eq!(job["description"], "status openvpn");
+    assert_eq!(
+        location,
+        format!("/api/v1/jobs/{}", job["id"].as_str().unwrap())
+    );
+
+    // The result depends on systemctl being available, but the job must finish.
+    for _ in 0..100 {
+        let res = reqwest::Client::new()
+            .get(format!("{}{}", base, location))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), 200);
+        let job: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        if job["state"] == "succeeded" || job["state"] == "failed" {
+            assert!(job["output"].is_string());
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Job not finished");
+}
@@ -0,0 +1,90 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use fwcloud_agent::config::{random_api_key, Config, LiveOptions};
+use serial_test::serial;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use uuid::Uuid;
+
+#[tokio::test]
+#[serial]
+async fn serves_ipv6_and_unix_socket_listeners() {
+    env::set_var("EXTRA_LISTENERS", "[::1]:0;tls=false");
+    let mut config = Config::new().unwrap();
+    env::remove_var("EXTRA_LISTENERS");
+
+    let api_key = random_api_key();
+    let unix_socket = format!(
+        "{}/fwcloud-agent-{}.sock",
+        env::temp_dir().display(),
+        Uuid::new_v4()
+    );
+    config.enable_env_logger = false;
+    config.bind_ip = "127.0.0.1".to_string();
+    config.bind_port = 0;
+    config.enable_tls = false;
+    config.set_live(LiveOptions {
+        api_key: api_key.clone(),
+        ..(*config.live()).clone()
+    });
+    config.unix_socket = unix_socket.clone();
+    config.workers = 1;
+    let listeners = config.bind_to();
+    let ipv6_port = config.listeners[1].address.port();
+
+    let server = fwcloud_agent::run(config, listeners).expect("Failed to run FWCloud-Agent server");
+    drop(tokio::spawn(server));
+
+    // The IPv6 listener uses the API key authorization.
+    let client = reqwest::Client::new();
+    let url = format!("http://[::1]:{ipv6_port}/api/v1/ping");
+    let res = client.put(&url).send().await.unwrap();
+    assert_eq!(res.status().as_u16(), 403);
+    let res = client
+        .put(&url)
+        .header("X-API-Key", &api_key)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+
+    // The Unix socket doesn't need API key, only the owner can connect to it.
+    let mode = std::fs::metadata(&unix_socket)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let mut stream = UnixStream::connect(&unix_socket).await.unwrap();
+    stream
+        .write_all(b"PUT /api/v1/ping HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).await.unwrap();
+    assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+
+    std::fs::remove_file(&unix_socket).unwrap();
+}
@@ -0,0 +1,48 @@
+/*
+    Copyright 2025 SOLTECSIS SOLUCIONES TECNOLOGICAS, SLU
+    https://soltecsis.com
+    info@soltecsis.com
+
+
+    This file is part of FWCloud (https://fwcloud.net).
+
+    FWCloud is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Affero General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    FWCloud is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with FWCloud.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+mod common;
+
+#[tokio::test]
+async fn nftables_ruleset_api_call_exists() {
+    let url = format!("{}/api/v1/nftables/ruleset", common::spawn_app(None));
+
+    let res = reqwest::Client::new().get(url).send().await.unwrap();
+
+    assert_ne!(res.status().as_u16(), 400);
+    assert_ne!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn nftables_ruleset_rejects_bad_filters() {
+    let base = common::spawn_app(None);
+
+    for query in ["family=ipv4", "table=fil%20ter", "family=inet&table="] {
+        let res = reqwest::Client::new()
+            .get(format!("{}/api/v1/nftables/ruleset?{}", base, query))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), 400);
+    }
+}
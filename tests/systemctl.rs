@@ -94,3 +94,20 @@ async fn systemctl_with_invalid_data() {
         assert_eq!(body, error_message);
     }
 }
+
+#[tokio::test]
+async fn systemctl_with_invalid_timeout() {
+    let base = common::spawn_app(None);
+
+    for timeout in ["0", "86401", "abc"] {
+        let res = reqwest::Client::new()
+            .post(format!("{}/api/v1/systemctl?timeout={}", base, timeout))
+            .header(CONTENT_TYPE, "application/json")
+            .body("{\"command\":\"status\",\"service\":\"openvpn\"}")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), 400);
+    }
+}